@@ -0,0 +1,122 @@
+//! Host-side register map calculator.
+//!
+//! Solves for an output frequency with the same code the firmware uses and
+//! prints the resulting register map as a TICS Pro hex dump, so a map can
+//! be worked out without any hardware:
+//!
+//! ```text
+//! cargo run --bin lmx2594calc --features host --target x86_64-unknown-linux-gnu -- \
+//!     --fref 100M --freq 2500M
+//! ```
+//!
+//! The map starts from `REG_MAP`, keeping its reference path. Frequencies
+//! are in Hz, with an optional `k`, `M` or `G` suffix.
+
+use lmx2594ctl::frequency::{self, Frequency};
+use lmx2594ctl::lmx2594::{self, REG_MAP};
+use std::{env, process};
+
+const USAGE: &str = "usage: lmx2594calc --fref <Hz> --freq <Hz>";
+
+/// What was asked for on the command line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Args {
+    fref: Frequency,
+    freq: Frequency,
+}
+
+/// Parse a whole number of Hz, kHz, MHz or GHz
+fn parse_frequency(arg: &str) -> Result<Frequency, String> {
+    let (digits, scale) = match arg.as_bytes().last() {
+        Some(b'k') => (&arg[..arg.len() - 1], 1_000),
+        Some(b'M') => (&arg[..arg.len() - 1], 1_000_000),
+        Some(b'G') => (&arg[..arg.len() - 1], 1_000_000_000),
+        _ => (arg, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(scale))
+        .map(Frequency::from_hz)
+        .ok_or_else(|| format!("invalid frequency {:?}", arg))
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+    let (mut fref, mut freq) = (None, None);
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        let slot = match flag.as_str() {
+            "--fref" => &mut fref,
+            "--freq" => &mut freq,
+            _ => return Err(format!("unknown argument {:?}", flag)),
+        };
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", flag))?;
+        *slot = Some(parse_frequency(&value)?);
+    }
+    match (fref, freq) {
+        (Some(fref), Some(freq)) => Ok(Args { fref, freq }),
+        _ => Err("both --fref and --freq are needed".into()),
+    }
+}
+
+/// The hex dump for `args`
+fn run(args: Args) -> Result<String, String> {
+    let mut regs = REG_MAP;
+    frequency::set_frequency(args.fref, &mut regs, args.freq).map_err(|e| e.to_string())?;
+    let mut dump = String::new();
+    lmx2594::write_hex_dump(&regs, &mut dump).map_err(|e| e.to_string())?;
+    Ok(dump)
+}
+
+fn main() {
+    match parse_args(env::args().skip(1)).and_then(run) {
+        Ok(dump) => print!("{}", dump),
+        Err(e) => {
+            eprintln!("lmx2594calc: {}\n{}", e, USAGE);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn dump_matches_the_solver() {
+        let parsed = parse_args(args("--fref 100M --freq 2500M")).unwrap();
+        let fref = Frequency::from_mhz(100);
+        assert_eq!(
+            parsed,
+            Args {
+                fref,
+                freq: Frequency::from_mhz(2500)
+            }
+        );
+
+        let mut regs = REG_MAP;
+        frequency::set_frequency(fref, &mut regs, Frequency::from_mhz(2500)).unwrap();
+        let dump = run(parsed).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 113);
+        assert_eq!(lines[0], "R112\t0x700000");
+        for (line, word) in lines.iter().zip(regs.iter().rev()) {
+            let (_, hex) = line.split_once("\t0x").unwrap();
+            assert_eq!(u32::from_str_radix(hex, 16), Ok(*word));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_args(args("--fref 100M")).is_err());
+        assert!(parse_args(args("--fref 100X --freq 1G")).is_err());
+        assert!(parse_args(args("--fref 100M --freq 1G --verbose")).is_err());
+        assert!(run(parse_args(args("--fref 100M --freq 1k")).unwrap()).is_err());
+    }
+}
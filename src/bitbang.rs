@@ -0,0 +1,162 @@
+//! uWire over plain GPIOs, for boards without a spare SPI peripheral.
+//!
+//! `BitBangSpi` clocks bytes out MSB first in MODE_0: SCK idles low, SDI is
+//! set up while SCK is low, and the LMX2594 samples it on the rising edge.
+//! It implements the same blocking SPI traits as the rp2040 driver, so it
+//! takes the hardware SPI's place in `Lmx2594Driver` or the free functions
+//! in `lmx2594`, with CSB driven from a third GPIO through `ChipSelect` as
+//! usual.
+//!
+//! Writes only need SCK and SDI. Readback also needs a MISO input wired to
+//! MUXout, which makes `Transfer` available; pass `NoMiso` without one, and
+//! stick to the write-only functions.
+
+use embedded_hal::{
+    blocking::{
+        delay::DelayUs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::{InputPin, OutputPin},
+};
+
+/// Default time SCK spends in each half of its period, for a 500 kHz clock.
+/// The LMX2594 takes far faster clocks; this leaves room for long wires.
+pub const DEFAULT_HALF_PERIOD_US: u32 = 1;
+
+/// Stands in for the MISO pin on a write-only bus
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct NoMiso;
+
+/// uWire master on GPIOs, see the module documentation
+pub struct BitBangSpi<SCK, MOSI, MISO, D> {
+    sck: SCK,
+    mosi: MOSI,
+    miso: MISO,
+    delay: D,
+    /// Time SCK spends in each half of its period
+    pub half_period_us: u32,
+}
+
+impl<SCK, MOSI, MISO, D, E> BitBangSpi<SCK, MOSI, MISO, D>
+where
+    SCK: OutputPin<Error = E>,
+    MOSI: OutputPin<Error = E>,
+    D: DelayUs<u32>,
+{
+    /// Take the pins and a delay for timing the clock, and drive SCK low
+    /// ready for the first bit. The clock starts at
+    /// `DEFAULT_HALF_PERIOD_US`.
+    pub fn new(mut sck: SCK, mosi: MOSI, miso: MISO, delay: D) -> Result<Self, E> {
+        sck.set_low()?;
+        Ok(BitBangSpi {
+            sck,
+            mosi,
+            miso,
+            delay,
+            half_period_us: DEFAULT_HALF_PERIOD_US,
+        })
+    }
+
+    /// Give the pins and delay back
+    pub fn free(self) -> (SCK, MOSI, MISO, D) {
+        (self.sck, self.mosi, self.miso, self.delay)
+    }
+
+    /// Clock one bit out, calling `sample` at the end of the low half of
+    /// the period, just before the rising edge
+    fn clock_bit<F>(&mut self, bit: bool, mut sample: F) -> Result<(), E>
+    where
+        F: FnMut(&mut MISO) -> Result<(), E>,
+    {
+        if bit {
+            self.mosi.set_high()?;
+        } else {
+            self.mosi.set_low()?;
+        }
+        self.delay.delay_us(self.half_period_us);
+        sample(&mut self.miso)?;
+        self.sck.set_high()?;
+        self.delay.delay_us(self.half_period_us);
+        self.sck.set_low()
+    }
+}
+
+impl<SCK, MOSI, MISO, D, E> Write<u8> for BitBangSpi<SCK, MOSI, MISO, D>
+where
+    SCK: OutputPin<Error = E>,
+    MOSI: OutputPin<Error = E>,
+    D: DelayUs<u32>,
+{
+    type Error = E;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), E> {
+        for &byte in words {
+            for i in (0..8).rev() {
+                self.clock_bit(byte >> i & 1 != 0, |_| Ok(()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<SCK, MOSI, MISO, D, E> Transfer<u8> for BitBangSpi<SCK, MOSI, MISO, D>
+where
+    SCK: OutputPin<Error = E>,
+    MOSI: OutputPin<Error = E>,
+    MISO: InputPin<Error = E>,
+    D: DelayUs<u32>,
+{
+    type Error = E;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], E> {
+        for word in words.iter_mut() {
+            let mut read = 0;
+            for i in (0..8).rev() {
+                self.clock_bit(*word >> i & 1 != 0, |miso| {
+                    read = read << 1 | miso.is_high()? as u8;
+                    Ok(())
+                })?;
+            }
+            *word = read;
+        }
+        Ok(words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::Lmx2594;
+    use crate::mock::{self, PinTransaction, State};
+
+    fn level(high: bool) -> PinTransaction {
+        PinTransaction::set(if high { State::High } else { State::Low })
+    }
+
+    #[test]
+    fn writes_register_msb_first_in_mode_0() {
+        let word: u32 = 0x2c_1fa3;
+        // Each byte goes out MSB first, in `WIRE_ORDER`
+        let bits: Vec<bool> = mock::bytes(word)
+            .iter()
+            .flat_map(|&byte| (0..8).rev().map(move |i| byte >> i & 1 != 0))
+            .collect();
+
+        let mut sck_expected = vec![level(false)];
+        for _ in &bits {
+            sck_expected.extend([level(true), level(false)]);
+        }
+        let mosi_expected: Vec<_> = bits.iter().map(|&b| level(b)).collect();
+        let sck = mock::Pin::new(&sck_expected);
+        let mosi = mock::Pin::new(&mosi_expected);
+        let mut cs = mock::Pin::new(&mock::cs_frames(1));
+
+        let mut spi = BitBangSpi::new(sck, mosi, NoMiso, mock::Delay::new()).unwrap();
+        word.write_reg(&mut spi, &mut cs, &mut [0; 3]);
+
+        let (mut sck, mut mosi, _, _) = spi.free();
+        sck.done();
+        mosi.done();
+        cs.done();
+    }
+}
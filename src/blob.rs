@@ -0,0 +1,225 @@
+//! Binary register map images, for loading a map generated elsewhere (e.g.
+//! exported from TICS Pro) without rebuilding the firmware.
+//!
+//! An image is the 113 register words as 3 bytes each, address byte first
+//! as they are sent over uWire, followed by a little-endian CRC-32 (the
+//! IEEE polynomial, as used by zlib) of those 339 bytes:
+//!
+//! ```text
+//! | word 0 | word 1 | ... | word 112 | CRC-32 |
+//! |   3    |   3    |     |    3     |   4    |
+//! ```
+//!
+//! The words may come in any order, e.g. descending as TICS exports them,
+//! but every register R0-R112 must appear exactly once. The read/write bit
+//! at the top of each address byte is reserved in a write and must be
+//! clear. The "program as shown" bits inside the words are not checked,
+//! since they differ between silicon revisions (see `SiliconProfile`).
+
+use crate::lmx2594::{Lmx2594, READ};
+use core::fmt;
+
+/// Bytes of register words in an image
+pub const MAP_LEN: usize = 113 * 3;
+/// Bytes in a whole image, including the CRC
+pub const BLOB_LEN: usize = MAP_LEN + 4;
+
+/// Why an image was rejected
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum BlobError {
+    /// The image is not `BLOB_LEN` bytes long
+    WrongLength(usize),
+    /// The CRC does not match the register words
+    BadCrc,
+    /// A word has the read bit set in its address byte
+    ReservedBits(u8),
+    /// A word addresses a register beyond R112
+    InvalidAddress(u8),
+    /// Two words address the same register
+    DuplicateAddress(u8),
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobError::WrongLength(len) => {
+                write!(f, "image is {} bytes, expected {}", len, BLOB_LEN)
+            }
+            BlobError::BadCrc => f.write_str("image CRC mismatch"),
+            BlobError::ReservedBits(addr) => write!(f, "R{} has the read bit set", addr),
+            BlobError::InvalidAddress(addr) => write!(f, "no register at address {}", addr),
+            BlobError::DuplicateAddress(addr) => write!(f, "R{} appears twice", addr),
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3, reflected, as zlib) of `bytes`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// The image of `regs`, in address order
+pub fn encode(regs: &[u32; 113]) -> [u8; BLOB_LEN] {
+    let mut image = [0; BLOB_LEN];
+    for (r, word) in regs.iter().zip(image.chunks_exact_mut(3)) {
+        word.copy_from_slice(&r.reg());
+    }
+    let crc = crc32(&image[..MAP_LEN]);
+    image[MAP_LEN..].copy_from_slice(&crc.to_le_bytes());
+    image
+}
+
+/// Check an image and unpack it into a register map
+pub fn parse(image: &[u8]) -> Result<[u32; 113], BlobError> {
+    if image.len() != BLOB_LEN {
+        return Err(BlobError::WrongLength(image.len()));
+    }
+    let (words, crc) = image.split_at(MAP_LEN);
+    if crc32(words) != u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+        return Err(BlobError::BadCrc);
+    }
+
+    let mut regs = [0; 113];
+    let mut seen = 0u128;
+    for word in words.chunks_exact(3) {
+        let addr = word[0];
+        if addr & READ != 0 {
+            return Err(BlobError::ReservedBits(addr & !READ));
+        }
+        if addr as usize >= regs.len() {
+            return Err(BlobError::InvalidAddress(addr));
+        }
+        if seen & 1 << addr != 0 {
+            return Err(BlobError::DuplicateAddress(addr));
+        }
+        seen |= 1 << addr;
+        regs[addr as usize] = u32::from_be_bytes([0, word[0], word[1], word[2]]);
+    }
+    // 113 distinct in-range addresses, so every register is present
+    Ok(regs)
+}
+
+/// Collects an image arriving a byte at a time
+pub struct Receiver {
+    buf: [u8; BLOB_LEN],
+    len: usize,
+}
+
+impl Receiver {
+    pub const fn new() -> Self {
+        Receiver {
+            buf: [0; BLOB_LEN],
+            len: 0,
+        }
+    }
+
+    /// Add a received byte, returning the image once `BLOB_LEN` bytes
+    /// have arrived. The next byte starts a new image.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8; BLOB_LEN]> {
+        if self.len == BLOB_LEN {
+            self.len = 0;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len == BLOB_LEN {
+            Some(&self.buf)
+        } else {
+            None
+        }
+    }
+
+    /// Number of bytes of the current image received so far
+    pub fn received(&self) -> usize {
+        self.len % BLOB_LEN
+    }
+
+    /// Drop a partly received image
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::REG_MAP;
+
+    /// Rewrite the CRC after tampering with the words
+    fn reseal(image: &mut [u8; BLOB_LEN]) {
+        let crc = crc32(&image[..MAP_LEN]);
+        image[MAP_LEN..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn crc_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trips_in_any_order() {
+        assert_eq!(parse(&encode(&REG_MAP)), Ok(REG_MAP));
+
+        let mut descending = [0; BLOB_LEN];
+        for (r, word) in REG_MAP.iter().rev().zip(descending.chunks_exact_mut(3)) {
+            word.copy_from_slice(&r.reg());
+        }
+        reseal(&mut descending);
+        assert_eq!(parse(&descending), Ok(REG_MAP));
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let image = encode(&REG_MAP);
+        assert_eq!(
+            parse(&image[..MAP_LEN]),
+            Err(BlobError::WrongLength(MAP_LEN))
+        );
+
+        let mut corrupt = image;
+        corrupt[5] ^= 1;
+        assert_eq!(parse(&corrupt), Err(BlobError::BadCrc));
+
+        let mut read_bit = image;
+        read_bit[3 * 7] |= READ;
+        reseal(&mut read_bit);
+        assert_eq!(parse(&read_bit), Err(BlobError::ReservedBits(7)));
+
+        let mut beyond = image;
+        beyond[3 * 7] = 113;
+        reseal(&mut beyond);
+        assert_eq!(parse(&beyond), Err(BlobError::InvalidAddress(113)));
+
+        let mut twice = image;
+        twice[3 * 7] = 6;
+        reseal(&mut twice);
+        assert_eq!(parse(&twice), Err(BlobError::DuplicateAddress(6)));
+    }
+
+    #[test]
+    fn receiver_yields_each_whole_image() {
+        let image = encode(&REG_MAP);
+        let mut rx = Receiver::new();
+        for _ in 0..2 {
+            for &byte in &image[..BLOB_LEN - 1] {
+                assert!(rx.push(byte).is_none());
+            }
+            assert_eq!(rx.received(), BLOB_LEN - 1);
+            assert_eq!(rx.push(image[BLOB_LEN - 1]), Some(&image));
+            assert_eq!(rx.received(), 0);
+        }
+    }
+}
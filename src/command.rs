@@ -0,0 +1,509 @@
+//! Line-based control interface, served over USB serial by the firmware.
+//!
+//! Each command is one line of ASCII text; numbers may be given in decimal
+//! or as `0x`-prefixed hex. Every command produces one line of response.
+//!
+//! ```text
+//! read <addr>    read back the register at <addr> (0-112)
+//! enable <A|B>   power up an output
+//! disable <A|B>  power down an output
+//! release        connect the outputs held in high impedance at boot
+//! freq <hz>      retune the output to <hz>, as `mode` prefers
+//! mode <m>       solver preference for later retunes: auto, int or frac
+//! load           receive a binary register map image (map-upload feature)
+//! factory        reprogram the built-in register map, forgetting any
+//!                uploaded one
+//! ```
+
+use crate::driver::Lmx2594Driver;
+use crate::fields;
+use crate::frequency::{Frequency, SolveMode};
+use crate::lmx2594::{ChipSelect, Error, HeldOutputs, Output, ProgramTiming, RetunePolicy};
+use core::convert::TryFrom;
+use core::fmt::{self, Debug};
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+
+/// Longest command line accepted; anything beyond this is dropped
+pub const MAX_LINE: usize = 64;
+
+/// A parsed control command
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Command {
+    /// Read back the register at an address
+    Read(u8),
+    /// Power an output up (`true`) or down (`false`)
+    Enable(Output, bool),
+    /// Switch the outputs held by `lmx2594::BootPolicy::HighZ` to their
+    /// sources
+    Release,
+    /// Retune the output to a frequency, see `retune`
+    Frequency(Frequency),
+    /// Set the solver preference for later frequency changes
+    Mode(SolveMode),
+    /// Take the following bytes as a register map image, see `blob`
+    #[cfg(feature = "map-upload")]
+    Load,
+    /// Go back to the built-in register map, see `lmx2594::factory_reset`
+    FactoryReset,
+}
+
+/// Why a line could not be parsed as a command
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ParseError {
+    /// The line is blank
+    Empty,
+    /// The first word is not a known command
+    UnknownCommand,
+    /// The command needs an argument that was not given
+    MissingArgument,
+    /// An argument is not a number, or is too large
+    InvalidNumber,
+    /// An argument is not `A` or `B`
+    InvalidOutput,
+    /// An argument is not `auto`, `int` or `frac`
+    InvalidMode,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::Empty => "empty command",
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::InvalidNumber => "invalid number",
+            ParseError::InvalidOutput => "output must be A or B",
+            ParseError::InvalidMode => "mode must be auto, int or frac",
+        })
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hex number
+fn parse_number(word: &str) -> Result<u64, ParseError> {
+    let parsed = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    parsed.map_err(|_| ParseError::InvalidNumber)
+}
+
+/// Parse an output name, `A` or `B` in either case
+fn parse_output(word: &str) -> Result<Output, ParseError> {
+    match word {
+        "A" | "a" => Ok(Output::A),
+        "B" | "b" => Ok(Output::B),
+        _ => Err(ParseError::InvalidOutput),
+    }
+}
+
+/// Parse a solver preference
+fn parse_mode(word: &str) -> Result<SolveMode, ParseError> {
+    match word {
+        "auto" => Ok(SolveMode::Auto),
+        "int" => Ok(SolveMode::Integer),
+        "frac" => Ok(SolveMode::Fractional),
+        _ => Err(ParseError::InvalidMode),
+    }
+}
+
+/// What the control interface keeps track of between commands
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct DeviceState {
+    /// Shadow copy of the register map the device is programmed with, kept
+    /// up to date by commands that change a setting
+    pub regs: [u32; 113],
+    /// Solver preference for frequency changes
+    pub mode: SolveMode,
+    /// Set by `freq`: the firmware is to run `retune`, which clears this
+    /// and produces the response line
+    pub retune: Option<Frequency>,
+    /// Output mux settings put aside by `BootPolicy::HighZ`, until the
+    /// `release` command switches the outputs to them
+    pub held: Option<HeldOutputs>,
+    /// Set by `load`: input is a register map image until `blob::BLOB_LEN`
+    /// bytes have arrived, rather than command lines
+    #[cfg(feature = "map-upload")]
+    pub loading: bool,
+    /// Set by `factory`: the firmware is to run `lmx2594::factory_reset`,
+    /// drop any stored map and send the response line, then clear this
+    pub reset_requested: bool,
+}
+
+impl DeviceState {
+    pub const fn new(regs: [u32; 113]) -> Self {
+        DeviceState {
+            regs,
+            mode: SolveMode::Auto,
+            retune: None,
+            held: None,
+            #[cfg(feature = "map-upload")]
+            loading: false,
+            reset_requested: false,
+        }
+    }
+}
+
+/// Parse one line of input
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let name = words.next().ok_or(ParseError::Empty)?;
+    let mut arg = || words.next().ok_or(ParseError::MissingArgument);
+    match name {
+        "read" => {
+            let addr = parse_number(arg()?)?;
+            let addr = u8::try_from(addr).map_err(|_| ParseError::InvalidNumber)?;
+            Ok(Command::Read(addr))
+        }
+        "enable" => Ok(Command::Enable(parse_output(arg()?)?, true)),
+        "disable" => Ok(Command::Enable(parse_output(arg()?)?, false)),
+        "release" => Ok(Command::Release),
+        "freq" => Ok(Command::Frequency(Frequency::from_hz(
+            parse_number(arg()?)?,
+        ))),
+        "mode" => Ok(Command::Mode(parse_mode(arg()?)?)),
+        #[cfg(feature = "map-upload")]
+        "load" => Ok(Command::Load),
+        "factory" => Ok(Command::FactoryReset),
+        _ => Err(ParseError::UnknownCommand),
+    }
+}
+
+/// Run a command against the device, writing the response line to `out`
+pub fn execute<S, C, P, W>(
+    cmd: Command,
+    lmx: &mut Lmx2594Driver<S, C, P>,
+    state: &mut DeviceState,
+    out: &mut W,
+) -> fmt::Result
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    P: OutputPin,
+    P::Error: Debug,
+    W: fmt::Write,
+{
+    let result: Result<(), Error> = match cmd {
+        Command::Read(addr) => lmx.readback(state.regs[0], addr).map(|value| {
+            defmt::debug!("Read R{} = {=u16:#06x}", addr, value);
+            match fields::register_name(addr) {
+                Some(name) => write!(out, "R{} ({}) = {:#06x}\r\n", addr, name, value),
+                None => write!(out, "R{} = {:#06x}\r\n", addr, value),
+            }
+            .ok();
+        }),
+        Command::Enable(output, enable) => {
+            lmx.enable_output(&mut state.regs, output, enable)
+                .map(|()| {
+                    let status = if enable { "enabled" } else { "disabled" };
+                    write!(out, "{} {}\r\n", output, status).ok();
+                })
+        }
+        Command::Release => match state.held.take() {
+            Some(held) => {
+                let released = lmx.release_outputs(&mut state.regs, held);
+                match released {
+                    Ok(()) => {
+                        write!(out, "outputs released\r\n").ok();
+                    }
+                    // Still held as far as anyone can tell; allow a retry
+                    Err(_) => state.held = Some(held),
+                }
+                released
+            }
+            None => {
+                write!(out, "outputs not held\r\n").ok();
+                Ok(())
+            }
+        },
+        // Answered by `retune`, which needs a delay and the reference
+        Command::Frequency(target) => {
+            state.retune = Some(target);
+            Ok(())
+        }
+        Command::Mode(mode) => {
+            state.mode = mode;
+            let name = match mode {
+                SolveMode::Auto => "auto",
+                SolveMode::Integer => "int",
+                SolveMode::Fractional => "frac",
+            };
+            write!(out, "mode {}\r\n", name).ok();
+            Ok(())
+        }
+        #[cfg(feature = "map-upload")]
+        Command::Load => {
+            state.loading = true;
+            write!(out, "send {} bytes\r\n", crate::blob::BLOB_LEN).ok();
+            Ok(())
+        }
+        // Answered once the firmware has done it
+        Command::FactoryReset => {
+            state.reset_requested = true;
+            Ok(())
+        }
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => write!(out, "error: {}\r\n", e),
+    }
+}
+
+/// Carry out the retune asked for by a `freq` command, if there is one,
+/// writing the response line to `out`.
+///
+/// The shadow map in `state` is retuned with `state.mode` from a reference
+/// of `fref`, holding the outputs until the PLL has relocked.
+pub fn retune<S, C, P, D, W>(
+    lmx: &mut Lmx2594Driver<S, C, P>,
+    delay: &mut D,
+    state: &mut DeviceState,
+    fref: Frequency,
+    timing: &ProgramTiming,
+    out: &mut W,
+) -> fmt::Result
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    P: OutputPin,
+    P::Error: Debug,
+    D: DelayMs<u32>,
+    W: fmt::Write,
+{
+    let target = match state.retune.take() {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+    let policy = RetunePolicy::HoldOutputs;
+    let retuned = lmx.change_frequency(
+        delay,
+        &mut state.regs,
+        fref,
+        target,
+        state.mode,
+        policy,
+        timing,
+    );
+    match retuned {
+        Ok(plan) => write!(out, "output {}\r\n", plan.output),
+        Err(e) => write!(out, "error: {}\r\n", e),
+    }
+}
+
+/// Parse and run one line of input, writing the response line to `out`
+pub fn handle_line<S, C, P, W>(
+    line: &str,
+    lmx: &mut Lmx2594Driver<S, C, P>,
+    state: &mut DeviceState,
+    out: &mut W,
+) -> fmt::Result
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    P: OutputPin,
+    P::Error: Debug,
+    W: fmt::Write,
+{
+    match parse(line) {
+        Ok(cmd) => execute(cmd, lmx, state, out),
+        Err(e) => write!(out, "error: {}\r\n", e),
+    }
+}
+
+/// Assembles incoming bytes into command lines
+pub struct LineBuffer {
+    buf: [u8; MAX_LINE],
+    len: usize,
+    complete: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        LineBuffer {
+            buf: [0; MAX_LINE],
+            len: 0,
+            complete: false,
+        }
+    }
+
+    /// Add a received byte, returning the line once it is terminated by
+    /// CR or LF. Blank lines and lines that are not valid UTF-8 are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<&str> {
+        if self.complete {
+            self.len = 0;
+            self.complete = false;
+        }
+        match byte {
+            b'\r' | b'\n' if self.len > 0 => {
+                self.complete = true;
+                core::str::from_utf8(&self.buf[..self.len]).ok()
+            }
+            b'\r' | b'\n' => None,
+            _ => {
+                if self.len < MAX_LINE {
+                    self.buf[self.len] = byte;
+                    self.len += 1;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fixed-size buffer holding a response line until it is sent
+pub struct Response {
+    buf: [u8; 96],
+    len: usize,
+}
+
+impl Response {
+    pub const fn new() -> Self {
+        Response {
+            buf: [0; 96],
+            len: 0,
+        }
+    }
+
+    /// The response text written so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Write for Response {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::{HardwareCs, REG_MAP};
+    use crate::mock;
+
+    #[test]
+    fn enable_and_disable_parse_and_write_r44() {
+        assert_eq!(parse("enable a"), Ok(Command::Enable(Output::A, true)));
+        assert_eq!(parse("disable B"), Ok(Command::Enable(Output::B, false)));
+        assert_eq!(parse("enable"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("enable C"), Err(ParseError::InvalidOutput));
+
+        // `REG_MAP` has OUTA powered up
+        let mut off = REG_MAP;
+        fields::OUTA_PD.set(&mut off, 1);
+        let spi = mock::Spi::new(&mock::writes([off[44], REG_MAP[44]]));
+        let cs = mock::Pin::new(&mock::cs_frames(2));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+        let mut state = DeviceState::new(REG_MAP);
+        let mut out = String::new();
+
+        handle_line("disable A", &mut lmx, &mut state, &mut out).unwrap();
+        assert_eq!(out, "OUTA disabled\r\n");
+        assert_eq!(state.regs, off);
+        out.clear();
+        handle_line("enable A", &mut lmx, &mut state, &mut out).unwrap();
+        assert_eq!(out, "OUTA enabled\r\n");
+        assert_eq!(state.regs, REG_MAP);
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+
+    #[test]
+    fn read_returns_the_value_read_back() {
+        let spi = mock::Spi::new(&mock::readback(REG_MAP[0], 44, 0x1fa3));
+        let cs = mock::Pin::new(&mock::cs_frames(3));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+        let mut state = DeviceState::new(REG_MAP);
+        let mut out = String::new();
+
+        handle_line("read 44", &mut lmx, &mut state, &mut out).unwrap();
+        assert_eq!(out, "R44 (OUTA_PWR) = 0x1fa3\r\n");
+
+        // Rejected before anything reaches the bus
+        out.clear();
+        handle_line("read 113", &mut lmx, &mut state, &mut out).unwrap();
+        assert_eq!(out, "error: no register at address 113\r\n");
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+
+    #[test]
+    fn freq_retunes_as_the_mode_prefers() {
+        assert_eq!(
+            parse("freq 10050000000"),
+            Ok(Command::Frequency(Frequency::from_mhz(10_050)))
+        );
+        assert_eq!(parse("freq 10G"), Err(ParseError::InvalidNumber));
+
+        let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+        let trace = mock::Trace::with_readback(locked);
+        let mut lmx = Lmx2594Driver::new(trace.spi(), HardwareCs, trace.pin());
+        let mut delay = trace.delay();
+        let fref = Frequency::from_mhz(100);
+        let timing = ProgramTiming::default();
+        let mut state = DeviceState::new(REG_MAP);
+        let mut run = |line, state: &mut DeviceState| {
+            let mut out = String::new();
+            handle_line(line, &mut lmx, state, &mut out).unwrap();
+            retune(&mut lmx, &mut delay, state, fref, &timing, &mut out).unwrap();
+            assert_eq!(state.retune, None);
+            out
+        };
+
+        // N = 100.5 at a 100 MHz PFD: integer mode refuses it untouched
+        run("mode int", &mut state);
+        let out = run("freq 10050000000", &mut state);
+        assert_eq!(out, "error: cannot produce 10050000000 Hz\r\n");
+        assert_eq!(state.regs, REG_MAP);
+        assert!(trace.events().is_empty());
+
+        run("mode frac", &mut state);
+        let out = run("freq 10050000000", &mut state);
+        assert_eq!(out, "output 10050000000 Hz\r\n");
+        assert_eq!(fields::pll_n(&state.regs), 100);
+        assert_ne!(fields::pll_num(&state.regs), 0);
+        assert!(trace.events().contains(&mock::Event::Write(state.regs[36])));
+
+        // An exact multiple is fine in integer mode, with the modulator off
+        run("mode int", &mut state);
+        let out = run("freq 10000000000", &mut state);
+        assert_eq!(out, "output 10000000000 Hz\r\n");
+        assert_eq!(fields::MASH_ORDER.get(&state.regs), 0);
+    }
+}
@@ -0,0 +1,212 @@
+//! Programming the register map with the SPI shifting offloaded to DMA.
+//!
+//! The LMX2594 latches its 24-bit shift register on the rising edge of
+//! CSB, so every register needs a CSB frame of its own (see
+//! `lmx2594::ChipSelect`). A single DMA transfer of the whole packed map
+//! would clock every register through one frame, and only the last 24 bits
+//! shifted in would be latched. Instead the map is laid out as back-to-back
+//! 3-byte frames and each frame is sent as its own DMA burst, with CSB
+//! toggled in software between bursts. The CPU still waits for each burst
+//! to drain before raising CSB; what the DMA saves is feeding the FIFO byte
+//! by byte.
+//!
+//! The firmware itself does not use this: it programs the map once at boot,
+//! where waiting on each burst leaves the CPU no freer than the blocking
+//! HAL transfers. It is for applications that rewrite the map often, and
+//! builds on `write_regs` with a `Burst` of their own or `Spi0Dma`.
+
+use crate::lmx2594::{ChipSelect, Error, WIRE_ORDER};
+use rp_pico::hal::pac;
+
+/// Bytes in one register frame
+pub const FRAME_LEN: usize = 3;
+
+/// One DMA burst: a single register frame within the laid-out buffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Descriptor {
+    /// Offset of the frame from the start of the buffer
+    pub offset: usize,
+    /// Number of bytes to transfer, always `FRAME_LEN`
+    pub len: usize,
+}
+
+impl Descriptor {
+    /// The bytes of `buf` this burst transfers
+    pub fn frame<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        &buf[self.offset..self.offset + self.len]
+    }
+}
+
+/// Lay out `regs` in `out` as consecutive 3-byte frames, in the order
+/// given. Returns the number of frames written, which is less than
+/// `regs.len()` if `out` is too short to hold them all.
+pub fn layout(regs: &[u32], out: &mut [u8]) -> usize {
+    let mut count = 0;
+    for (r, frame) in regs.iter().zip(out.chunks_exact_mut(FRAME_LEN)) {
+        frame.copy_from_slice(&WIRE_ORDER.pack(*r));
+        count += 1;
+    }
+    count
+}
+
+/// The bursts that send `count` frames laid out by `layout`, one per
+/// register
+pub fn descriptors(count: usize) -> impl Iterator<Item = Descriptor> {
+    (0..count).map(|i| Descriptor {
+        offset: i * FRAME_LEN,
+        len: FRAME_LEN,
+    })
+}
+
+/// A DMA channel feeding the SPI transmit FIFO
+pub trait Burst {
+    /// Transfer `bytes` to the SPI, returning once the last bit has been
+    /// shifted out so that CSB can be raised
+    fn transfer(&mut self, bytes: &[u8]);
+}
+
+/// Write each register in `regs` with one DMA burst per CSB frame, using
+/// `scratch` to hold the laid-out frames. Returns the number of registers
+/// written; registers that do not fit in `scratch` are not sent.
+pub fn write_regs<B, C>(dma: &mut B, spi_cs: &mut C, regs: &[u32], scratch: &mut [u8]) -> usize
+where
+    B: Burst,
+    C: ChipSelect,
+{
+    let count = layout(regs, scratch);
+    if count < regs.len() {
+        defmt::warn!(
+            "DMA scratch buffer holds {} of {} registers",
+            count,
+            regs.len()
+        );
+    }
+    for d in descriptors(count) {
+        spi_cs.select();
+        dma.transfer(d.frame(scratch));
+        spi_cs.deselect();
+    }
+    count
+}
+
+/// `Burst` on one RP2040 DMA channel, paced by the SPI0 transmit DREQ.
+///
+/// SPI0 itself stays owned by the HAL driver; this only enables its
+/// transmit DMA request and drains the receive FIFO after each burst, so
+/// blocking HAL transfers keep working in between.
+pub struct Spi0Dma {
+    dma: pac::DMA,
+    channel: usize,
+}
+
+/// DREQ number of the SPI0 transmit FIFO
+const DREQ_SPI0_TX: u8 = 16;
+
+/// Number of DMA channels on the RP2040
+const DMA_CHANNELS: usize = 12;
+
+impl Spi0Dma {
+    /// Take the DMA block out of reset and use `channel` for bursts.
+    /// `Error::InvalidDmaChannel` unless `channel` is one of the RP2040's
+    /// 12 channels, 0-11.
+    pub fn new(dma: pac::DMA, channel: usize, resets: &mut pac::RESETS) -> Result<Self, Error> {
+        if channel >= DMA_CHANNELS {
+            return Err(Error::InvalidDmaChannel(channel));
+        }
+        resets.reset.modify(|_, w| w.dma().clear_bit());
+        while resets.reset_done.read().dma().bit_is_clear() {}
+        // SAFETY: only the TXDMAE bit is touched, which the HAL driver
+        // never uses.
+        let spi = unsafe { &*pac::SPI0::ptr() };
+        spi.sspdmacr.modify(|_, w| w.txdmae().set_bit());
+        Ok(Spi0Dma { dma, channel })
+    }
+
+    /// Release the DMA block
+    pub fn free(self) -> pac::DMA {
+        self.dma
+    }
+}
+
+impl Burst for Spi0Dma {
+    fn transfer(&mut self, bytes: &[u8]) {
+        // SAFETY: SPI0 registers are only read here, or popped from the
+        // receive FIFO, which the HAL driver expects to find empty.
+        let spi = unsafe { &*pac::SPI0::ptr() };
+        let ch = &self.dma.ch[self.channel];
+        ch.ch_read_addr
+            .write(|w| unsafe { w.bits(bytes.as_ptr() as u32) });
+        ch.ch_write_addr
+            .write(|w| unsafe { w.bits(&spi.sspdr as *const _ as u32) });
+        ch.ch_trans_count
+            .write(|w| unsafe { w.bits(bytes.len() as u32) });
+        ch.ch_ctrl_trig.write(|w| unsafe {
+            w.treq_sel()
+                .bits(DREQ_SPI0_TX)
+                // Chaining to itself disables chaining
+                .chain_to()
+                .bits(self.channel as u8)
+                .incr_read()
+                .set_bit()
+                .incr_write()
+                .clear_bit()
+                .data_size()
+                .size_byte()
+                .en()
+                .set_bit()
+        });
+        // `bytes` must outlive the transfer, so wait here for it to finish
+        while ch.ch_ctrl_trig.read().busy().bit_is_set() {}
+        while spi.sspsr.read().bsy().bit_is_set() {}
+        while spi.sspsr.read().rne().bit_is_set() {
+            spi.sspdr.read();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::HardwareCs;
+    use crate::mock;
+
+    /// Records the bytes of each burst
+    #[derive(Default)]
+    struct Bursts(Vec<Vec<u8>>);
+
+    impl Burst for Bursts {
+        fn transfer(&mut self, bytes: &[u8]) {
+            self.0.push(bytes.to_vec());
+        }
+    }
+
+    #[test]
+    fn each_register_gets_a_frame_and_a_burst_of_its_own() {
+        let regs = [0x2c1fa3, 0x240800, 0x00241c];
+        let mut buf = [0; 9];
+        assert_eq!(layout(&regs, &mut buf), 3);
+        let frames: Vec<u8> = regs.iter().flat_map(|&r| mock::bytes(r)).collect();
+        assert_eq!(buf[..], frames[..]);
+
+        let expected = [(0, 3), (3, 3), (6, 3)];
+        let found: Vec<_> = descriptors(3).map(|d| (d.offset, d.len)).collect();
+        assert_eq!(found, expected);
+        for (d, &r) in descriptors(3).zip(regs.iter()) {
+            assert_eq!(d.frame(&buf), &mock::bytes(r)[..]);
+        }
+    }
+
+    #[test]
+    fn a_short_scratch_buffer_sends_only_the_registers_it_holds() {
+        let regs = [0x2c1fa3, 0x240800, 0x00241c];
+        // Room for two frames and part of a third
+        let mut scratch = [0; 8];
+        assert_eq!(layout(&regs, &mut scratch), 2);
+        assert_eq!(scratch[6..], [0, 0]);
+
+        let mut dma = Bursts::default();
+        let sent = write_regs(&mut dma, &mut HardwareCs, &regs, &mut scratch);
+        assert_eq!(sent, 2);
+        assert_eq!(dma.0, [mock::bytes(regs[0]), mock::bytes(regs[1])]);
+    }
+}
@@ -0,0 +1,638 @@
+//! An owned handle on one LMX2594.
+//!
+//! `Lmx2594Driver` takes the SPI bus, the uWire chip select and the chip
+//! enable pin once, along with the 3-byte scratch buffer every transfer
+//! needs, and forwards to the free functions in `lmx2594`. The trait bounds
+//! on `new` are what check the pin configuration: on the rp2040 only pins
+//! in an output mode implement `OutputPin`, and only an initialised SPI
+//! implements the blocking transfer traits.
+
+use crate::frequency::{Frequency, FrequencyPlan, MashOrder, SolveMode};
+use crate::lmx2594::{
+    self, ChipSelect, DeviceId, Error, HeldOutputs, Hop, Output, OutputSource, Pin1Function,
+    ProgramOptions, ProgramTiming, ReadbackState, RetunePolicy, VerifyReport,
+};
+use crate::profile::{self, Profile};
+use crate::ramp::{self, RampDir};
+use crate::refdetect;
+use crate::sync;
+use core::fmt::Debug;
+use embedded_hal::{
+    blocking::{
+        delay::DelayMs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+
+/// One LMX2594, with the peripherals it is wired to
+pub struct Lmx2594Driver<S, C, P> {
+    spi: S,
+    spi_cs: C,
+    ce: P,
+    buf: [u8; 3],
+    lock_lost: bool,
+    calibration_pending: bool,
+    pin1: Pin1Function,
+}
+
+impl<S, C, P> Lmx2594Driver<S, C, P>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    P: OutputPin,
+    P::Error: Debug,
+{
+    /// Take ownership of an SPI bus initialised in `lmx2594::SPI_MODE`,
+    /// the CSB driver and the output on uWire pin 1, normally chip enable
+    /// (see `Pin1Function`)
+    pub fn new(spi: S, spi_cs: C, ce: P) -> Self {
+        Lmx2594Driver {
+            spi,
+            spi_cs,
+            ce,
+            buf: [0; 3],
+            lock_lost: false,
+            calibration_pending: false,
+            pin1: Pin1Function::ChipEnable,
+        }
+    }
+
+    /// Give the peripherals back
+    pub fn free(self) -> (S, C, P) {
+        (self.spi, self.spi_cs, self.ce)
+    }
+
+    /// Replace the SPI bus with what `reinit` makes of it, e.g. to reset
+    /// and reconfigure the peripheral after a bus fault (see `recovery`).
+    /// Everything else the driver tracks is kept.
+    pub fn reinit_spi(self, reinit: impl FnOnce(S) -> S) -> Self {
+        Lmx2594Driver {
+            spi: reinit(self.spi),
+            ..self
+        }
+    }
+
+    /// The SPI bus, e.g. to change its baud rate
+    pub fn spi_mut(&mut self) -> &mut S {
+        &mut self.spi
+    }
+
+    /// Write one 24-bit register word, returning a failed SPI write as
+    /// `Error::WriteFailed`
+    pub fn write_reg(&mut self, word: u32) -> Result<(), Error> {
+        lmx2594::write_word(&mut self.spi, &mut self.spi_cs, &mut self.buf, word)
+    }
+
+    /// See `lmx2594::read_reg`
+    pub fn read_reg(&mut self, addr: u8) -> Result<u16, Error> {
+        lmx2594::read_reg(&mut self.spi, &mut self.spi_cs, &mut self.buf, addr)
+    }
+
+    /// See `lmx2594::readback`
+    pub fn readback(&mut self, r0: u32, addr: u8) -> Result<u16, Error> {
+        lmx2594::readback(&mut self.spi, &mut self.spi_cs, &mut self.buf, r0, addr)
+    }
+
+    /// See `lmx2594::read_all_readback`
+    pub fn read_all_readback(&mut self, r0: u32) -> Result<ReadbackState, Error> {
+        lmx2594::read_all_readback(&mut self.spi, &mut self.spi_cs, &mut self.buf, r0)
+    }
+
+    /// See `lmx2594::read_device_id`
+    pub fn read_device_id(&mut self) -> Result<DeviceId, Error> {
+        lmx2594::read_device_id(&mut self.spi, &mut self.spi_cs, &mut self.buf)
+    }
+
+    /// See `lmx2594::read_n`
+    pub fn read_n(&mut self, r0: u32) -> Result<u32, Error> {
+        lmx2594::read_n(&mut self.spi, &mut self.spi_cs, &mut self.buf, r0)
+    }
+
+    /// See `lmx2594::is_locked`. An unlocked reading is also latched for
+    /// `read_lock_loss`.
+    pub fn is_locked(&mut self, r0: u32) -> Result<bool, Error> {
+        let locked = lmx2594::is_locked(&mut self.spi, &mut self.spi_cs, &mut self.buf, r0)?;
+        self.lock_lost |= !locked;
+        Ok(locked)
+    }
+
+    /// Whether the PLL has been seen unlocked since the last
+    /// `clear_lock_loss`, checking the lock detect state once more first.
+    ///
+    /// The LMX2594 has no sticky lock-loss status of its own, so the flag
+    /// is latched here from every `is_locked` made through the driver, and
+    /// an unlock that starts and ends between two checks is missed. Poll
+    /// often, or watch lock detect on MUXout, to catch short ones.
+    pub fn read_lock_loss(&mut self, r0: u32) -> Result<bool, Error> {
+        self.is_locked(r0)?;
+        Ok(self.lock_lost)
+    }
+
+    /// Clear the flag read by `read_lock_loss`. Nothing is written to the
+    /// device.
+    pub fn clear_lock_loss(&mut self) {
+        self.lock_lost = false;
+    }
+
+    /// See `lmx2594::wait_for_lock`. Each poll goes through `is_locked`,
+    /// so an unlocked reading while waiting, even before the PLL first
+    /// settles, is latched for `read_lock_loss` too.
+    pub fn wait_for_lock<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        r0: u32,
+        timeout_ms: u32,
+        debounce: u32,
+    ) -> Result<(), Error> {
+        lmx2594::poll_for_lock(delay, timeout_ms, debounce, || self.is_locked(r0))
+    }
+
+    /// See `lmx2594::program_all`
+    pub fn program_all<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &[u32],
+        options: &ProgramOptions,
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::program_all(spi, cs, delay, buf, regs, options, timing)
+    }
+
+    /// See `lmx2594::program_range`
+    pub fn program_range<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &[u32; 113],
+        start: u8,
+        end: u8,
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::program_range(spi, cs, delay, buf, regs, start, end, timing)
+    }
+
+    /// See `lmx2594::bring_up`
+    pub fn bring_up<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &[u32; 113],
+        options: &ProgramOptions,
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        self.pin1 = options.pin1;
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::bring_up(spi, cs, &mut self.ce, delay, buf, regs, options, timing)
+    }
+
+    /// See `lmx2594::factory_reset`
+    pub fn factory_reset<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &mut [u32; 113],
+        options: &ProgramOptions,
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::factory_reset(spi, cs, delay, buf, regs, options, timing)
+    }
+
+    /// Power the device off by pulling CE low, if pin 1 is wired as chip
+    /// enable, and wait `timing.power_on_ms` for it to drain; `bring_up`
+    /// powers it on again. With pin 1 as RAMPDIR this does nothing, and
+    /// `bring_up` only resets the registers.
+    pub fn power_off<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        if self.pin1 == Pin1Function::ChipEnable {
+            self.ce.set_low().map_err(|_| Error::PinFailed)?;
+            delay.delay_ms(timing.power_on_ms);
+        }
+        Ok(())
+    }
+
+    /// Drive pin 1 as RAMPDIR, if the options last given to `bring_up` say
+    /// it is wired that way; see `ramp::set_ramp_dir`
+    pub fn set_ramp_dir(&mut self, dir: RampDir) -> Result<(), Error> {
+        ramp::set_ramp_dir(&mut self.ce, self.pin1, dir)
+    }
+
+    /// See `lmx2594::measure_lock_time`
+    pub fn measure_lock_time<D, T>(
+        &mut self,
+        delay: &mut D,
+        regs: &mut [u32; 113],
+        fref: Frequency,
+        target: Frequency,
+        timing: &ProgramTiming,
+        now_us: T,
+    ) -> Result<u32, Error>
+    where
+        D: DelayMs<u32>,
+        T: FnMut() -> u64,
+    {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::measure_lock_time(spi, cs, delay, buf, regs, fref, target, timing, now_us)
+    }
+
+    /// See `lmx2594::check_reference`
+    pub fn check_reference<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::check_reference(spi, cs, delay, buf, timing)
+    }
+
+    /// See `lmx2594::recalibrate`
+    pub fn recalibrate<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        r0: u32,
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::recalibrate(spi, cs, delay, buf, r0, timing)
+    }
+
+    /// See `lmx2594::read_vco_daciset`
+    pub fn read_vco_daciset(&mut self, r0: u32) -> Result<u16, Error> {
+        lmx2594::read_vco_daciset(&mut self.spi, &mut self.spi_cs, &mut self.buf, r0)
+    }
+
+    /// See `lmx2594::set_vco_daciset`
+    pub fn set_vco_daciset(
+        &mut self,
+        regs: &mut [u32; 113],
+        code: Option<u16>,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::set_vco_daciset(spi, cs, buf, regs, code)
+    }
+
+    /// See `lmx2594::mute`
+    pub fn mute(&mut self, regs: &[u32; 113]) -> Result<(), Error> {
+        lmx2594::mute(&mut self.spi, &mut self.spi_cs, &mut self.buf, regs)
+    }
+
+    /// See `lmx2594::unmute`
+    pub fn unmute(&mut self, regs: &[u32; 113]) -> Result<(), Error> {
+        lmx2594::unmute(&mut self.spi, &mut self.spi_cs, &mut self.buf, regs)
+    }
+
+    /// See `lmx2594::enable_output`
+    pub fn enable_output(
+        &mut self,
+        regs: &mut [u32; 113],
+        output: Output,
+        enable: bool,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::enable_output(spi, cs, buf, regs, output, enable)
+    }
+
+    /// See `lmx2594::set_output_source`
+    pub fn set_output_source(
+        &mut self,
+        regs: &mut [u32; 113],
+        output: Output,
+        source: OutputSource,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::set_output_source(spi, cs, buf, regs, output, source)
+    }
+
+    /// See `lmx2594::release_outputs`
+    pub fn release_outputs(
+        &mut self,
+        regs: &mut [u32; 113],
+        held: HeldOutputs,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::release_outputs(spi, cs, buf, regs, held)
+    }
+
+    /// See `lmx2594::set_both_outputs`
+    pub fn set_both_outputs(
+        &mut self,
+        regs: &mut [u32; 113],
+        drive: u8,
+        source: OutputSource,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::set_both_outputs(spi, cs, buf, regs, drive, source, enabled)
+    }
+
+    /// See `lmx2594::set_mash_order`
+    pub fn set_mash_order(&mut self, regs: &mut [u32; 113], order: MashOrder) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::set_mash_order(spi, cs, buf, regs, order)
+    }
+
+    /// See `profile::set_profile`
+    pub fn set_profile(&mut self, regs: &mut [u32; 113], profile: Profile) -> Result<(), Error> {
+        profile::set_profile(
+            &mut self.spi,
+            &mut self.spi_cs,
+            &mut self.buf,
+            regs,
+            profile,
+        )
+    }
+
+    /// See `lmx2594::shutdown`
+    pub fn shutdown(&mut self, regs: &[u32; 113]) -> Result<(), Error> {
+        lmx2594::shutdown(&mut self.spi, &mut self.spi_cs, &mut self.buf, regs)
+    }
+
+    /// See `lmx2594::wake_and_lock`
+    pub fn wake_and_lock<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &[u32; 113],
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::wake_and_lock(spi, cs, delay, buf, regs, timing)
+    }
+
+    /// See `lmx2594::change_frequency`
+    #[allow(clippy::too_many_arguments)]
+    pub fn change_frequency<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &mut [u32; 113],
+        fref: Frequency,
+        target: Frequency,
+        mode: SolveMode,
+        policy: RetunePolicy,
+        timing: &ProgramTiming,
+    ) -> Result<FrequencyPlan, Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        let plan = lmx2594::change_frequency(
+            spi, cs, delay, buf, regs, fref, target, mode, policy, timing,
+        )?;
+        self.calibration_pending |= policy == RetunePolicy::Deferred;
+        Ok(plan)
+    }
+
+    /// See `lmx2594::commit_calibration`. Clears `calibration_pending` once
+    /// the calibration is written, whether or not the PLL then locks.
+    pub fn commit_calibration<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &[u32; 113],
+        timing: &ProgramTiming,
+    ) -> Result<(), Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::recalibrate(spi, cs, delay, buf, regs[0], timing)?;
+        self.calibration_pending = false;
+        self.wait_for_lock(delay, regs[0], timing.lock_timeout_ms, timing.lock_debounce)
+    }
+
+    /// Whether a retune under `RetunePolicy::Deferred` made through the
+    /// driver is still waiting for `commit_calibration`
+    pub fn calibration_pending(&self) -> bool {
+        self.calibration_pending
+    }
+
+    /// See `sync::change_frequency_synced`
+    #[allow(clippy::too_many_arguments)]
+    pub fn change_frequency_synced<D, Y>(
+        &mut self,
+        delay: &mut D,
+        regs: &mut [u32; 113],
+        fref: Frequency,
+        target: Frequency,
+        mode: SolveMode,
+        policy: RetunePolicy,
+        timing: &ProgramTiming,
+        sync: Option<&mut Y>,
+    ) -> Result<FrequencyPlan, Error>
+    where
+        D: DelayMs<u32>,
+        Y: OutputPin,
+        Y::Error: Debug,
+    {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        let plan = sync::change_frequency_synced(
+            spi, cs, delay, buf, regs, fref, target, mode, policy, timing, sync,
+        )?;
+        self.calibration_pending |= policy == RetunePolicy::Deferred;
+        Ok(plan)
+    }
+
+    /// See `refdetect::detect_reference`
+    pub fn detect_reference<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &[u32; 113],
+        lo: Frequency,
+        hi: Frequency,
+        resolution: Frequency,
+        timing: &ProgramTiming,
+    ) -> Result<Frequency, Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        refdetect::detect_reference(spi, cs, delay, buf, regs, lo, hi, resolution, timing)
+    }
+
+    /// See `lmx2594::fast_hop`
+    #[allow(clippy::too_many_arguments)]
+    pub fn fast_hop<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &mut [u32; 113],
+        fref: Frequency,
+        target: Frequency,
+        policy: RetunePolicy,
+        timing: &ProgramTiming,
+    ) -> Result<Hop, Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        let hop = lmx2594::fast_hop(spi, cs, delay, buf, regs, fref, target, policy, timing)?;
+        self.calibration_pending |= policy == RetunePolicy::Deferred;
+        Ok(hop)
+    }
+
+    /// See `lmx2594::program_and_verify`
+    #[allow(clippy::too_many_arguments)]
+    pub fn program_and_verify<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+        regs: &mut [u32; 113],
+        fref: Frequency,
+        target: Frequency,
+        mode: SolveMode,
+        options: &ProgramOptions,
+        timing: &ProgramTiming,
+    ) -> Result<VerifyReport, Error> {
+        let (spi, cs, buf) = (&mut self.spi, &mut self.spi_cs, &mut self.buf);
+        lmx2594::program_and_verify(
+            spi, cs, delay, buf, regs, fref, target, mode, options, timing,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{self, MUXOUT_LD_SEL};
+    use crate::lmx2594::{HardwareCs, FCAL_EN_OFF, FCAL_EN_ON, REG_MAP, RESET_OFF, RESET_ON};
+    use crate::mock;
+
+    const LOCKED: u16 = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+
+    #[test]
+    fn write_reg_uses_the_peripherals_it_holds() {
+        let spi = mock::Spi::new(&mock::writes([REG_MAP[44]]));
+        let cs = mock::Pin::new(&mock::cs_frames(1));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+        lmx.write_reg(REG_MAP[44]).unwrap();
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+
+        let mut lmx = Lmx2594Driver::new(mock::FailingSpi, HardwareCs, mock::Pin::new(&[]));
+        assert_eq!(lmx.write_reg(REG_MAP[44]), Err(Error::WriteFailed(44)));
+    }
+
+    #[test]
+    fn repeated_writes_reuse_the_scratch_buffer() {
+        // Each word fully overwrites the last, so no stale byte reaches the
+        // bus, and a read in between leaves nothing behind either
+        let words = [REG_MAP[46], 0x2c0000, REG_MAP[44]];
+        let mut transactions = mock::writes(words[..2].iter().copied());
+        transactions.push(mock::read(110, 0xffff));
+        transactions.extend(mock::writes([words[2]]));
+        let spi = mock::Spi::new(&transactions);
+        let cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+
+        for &word in &words[..2] {
+            lmx.write_reg(word).unwrap();
+            assert_eq!(lmx.buf[..], mock::bytes(word)[..]);
+        }
+        assert_eq!(lmx.read_reg(110), Ok(0xffff));
+        lmx.write_reg(words[2]).unwrap();
+        assert_eq!(lmx.buf[..], mock::bytes(words[2])[..]);
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+
+    #[test]
+    fn lock_loss_latches_until_cleared() {
+        let r0 = FCAL_EN_OFF & !MUXOUT_LD_SEL.mask();
+        let reads = [LOCKED, 0, LOCKED, LOCKED];
+        let spi = mock::Spi::new(&reads.map(|v| mock::read(110, v)));
+        let cs = mock::Pin::new(&mock::cs_frames(reads.len()));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+
+        assert!(!lmx.read_lock_loss(r0).unwrap());
+        assert!(!lmx.is_locked(r0).unwrap());
+        assert!(lmx.read_lock_loss(r0).unwrap());
+        lmx.clear_lock_loss();
+        assert!(!lmx.read_lock_loss(r0).unwrap());
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+
+    #[test]
+    fn lock_lost_while_waiting_is_latched() {
+        let r0 = FCAL_EN_OFF & !MUXOUT_LD_SEL.mask();
+        // Drops out once, then holds for the debounce and one more check
+        let reads = [LOCKED, 0, LOCKED, LOCKED, LOCKED, LOCKED];
+        let spi = mock::Spi::new(&reads.map(|v| mock::read(110, v)));
+        let cs = mock::Pin::new(&mock::cs_frames(reads.len()));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+
+        lmx.wait_for_lock(&mut mock::Delay::new(), r0, 10, 3)
+            .unwrap();
+        assert!(lmx.read_lock_loss(r0).unwrap());
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+
+    #[test]
+    fn deferred_retunes_calibrate_once_on_commit() {
+        let trace = mock::Trace::with_readback(LOCKED);
+        let mut lmx = Lmx2594Driver::new(trace.spi(), HardwareCs, trace.pin());
+        let mut delay = trace.delay();
+        let (mut regs, timing) = (REG_MAP, ProgramTiming::default());
+        let fref = Frequency::from_mhz(100);
+        let calibrations = || {
+            let fcal = |e: &mock::Event| match *e {
+                mock::Event::Write(w) => w >> 16 == 0 && w & fields::FCAL_EN.mask() != 0,
+                _ => false,
+            };
+            trace.events().iter().filter(|e| fcal(e)).count()
+        };
+
+        for mhz in [10_050, 10_100] {
+            let target = Frequency::from_mhz(mhz);
+            lmx.change_frequency(
+                &mut delay,
+                &mut regs,
+                fref,
+                target,
+                SolveMode::Auto,
+                RetunePolicy::Deferred,
+                &timing,
+            )
+            .unwrap();
+        }
+        assert!(lmx.calibration_pending());
+        assert_eq!(calibrations(), 0);
+        assert!(!trace.events().contains(&mock::Event::Read(110)));
+
+        lmx.commit_calibration(&mut delay, &regs, &timing).unwrap();
+        assert!(!lmx.calibration_pending());
+        assert_eq!(calibrations(), 1);
+        assert!(trace.events().contains(&mock::Event::Read(110)));
+    }
+
+    #[test]
+    fn factory_reset_reprograms_the_built_in_map() {
+        let expected: Vec<u32> = [RESET_ON, RESET_OFF]
+            .iter()
+            .chain(REG_MAP.iter().rev())
+            .copied()
+            .chain([FCAL_EN_ON, FCAL_EN_OFF])
+            .collect();
+        let spi = mock::Spi::new(&mock::writes(expected.iter().copied()));
+        let cs = mock::Pin::new(&mock::cs_frames(expected.len()));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+
+        let mut regs = REG_MAP;
+        fields::set_pll_n(&mut regs, 100);
+        fields::OUTA_PD.set(&mut regs, 1);
+        lmx.factory_reset(
+            &mut mock::Delay::new(),
+            &mut regs,
+            &ProgramOptions::default(),
+            &ProgramTiming::default(),
+        )
+        .unwrap();
+        assert_eq!(regs, REG_MAP);
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+}
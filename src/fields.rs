@@ -0,0 +1,307 @@
+//! Named bit fields of the LMX2594 register map.
+//!
+//! Each register holds 16 bits of data below its 8-bit address byte (see
+//! `lmx2594`). Fields are read from and written into a full register map
+//! in RAM; nothing here talks to the device.
+
+use crate::lmx2594::Error;
+
+/// A bit field within the 16-bit data of a single register
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Field {
+    /// Register address
+    pub addr: u8,
+    /// Position of the least significant bit of the field
+    pub shift: u8,
+    /// Width of the field in bits
+    pub width: u8,
+}
+
+impl Field {
+    const fn new(addr: u8, shift: u8, width: u8) -> Self {
+        Field { addr, shift, width }
+    }
+
+    /// Mask of the field, already shifted into place
+    pub const fn mask(&self) -> u32 {
+        ((1 << self.width) - 1) << self.shift
+    }
+
+    /// Extract the field from a single register word
+    pub const fn extract(&self, word: u32) -> u32 {
+        (word & self.mask()) >> self.shift
+    }
+
+    /// Read the field out of a register map
+    pub const fn get(&self, regs: &[u32; 113]) -> u32 {
+        self.extract(regs[self.addr as usize])
+    }
+
+    /// Write the field into a register map, leaving the other bits alone.
+    /// Bits of `value` that do not fit in the field are discarded.
+    pub const fn set(&self, regs: &mut [u32; 113], value: u32) {
+        let r = &mut regs[self.addr as usize];
+        *r = (*r & !self.mask()) | ((value << self.shift) & self.mask());
+    }
+}
+
+/// Powers down the whole device
+pub const POWERDOWN: Field = Field::new(0, 0, 1);
+/// Resets all registers to their silicon defaults while set
+pub const RESET: Field = Field::new(0, 1, 1);
+/// MUXout pin function: 0 = SPI readback, 1 = lock detect.
+///
+/// Lock detect on MUXout is always active high; the LMX2594 has no
+/// polarity control, so logic wanting active-low lock needs an inverter.
+pub const MUXOUT_LD_SEL: Field = Field::new(0, 2, 1);
+/// Starts a VCO calibration when written as 1
+pub const FCAL_EN: Field = Field::new(0, 3, 1);
+/// Lets a rising edge on the SYNC pin realign the output phase
+pub const VCO_PHASE_SYNC: Field = Field::new(0, 14, 1);
+/// Settling time of each VCO amplitude calibration step, in calibration
+/// clock cycles
+pub const ACAL_CMP_DLY: Field = Field::new(4, 8, 8);
+/// Holds the VCO amplitude at `VCO_DACISET` instead of letting the
+/// calibration choose it
+pub const VCO_DACISET_FORCE: Field = Field::new(8, 14, 1);
+/// Doubles the reference before the pre-R divider
+pub const OSC_2X: Field = Field::new(9, 12, 1);
+/// Reference multiplier, 1 bypasses it
+pub const MULT: Field = Field::new(10, 7, 5);
+/// Post-multiplier reference divider
+pub const PLL_R: Field = Field::new(11, 4, 8);
+/// Pre-multiplier reference divider
+pub const PLL_R_PRE: Field = Field::new(12, 0, 12);
+/// Charge pump gain, see `frequency::charge_pump_ua`
+pub const CPG: Field = Field::new(14, 4, 3);
+/// VCO amplitude code used while `VCO_DACISET_FORCE` is set
+pub const VCO_DACISET: Field = Field::new(16, 0, 9);
+/// Enables the first divide-by-2 stage of the channel divider
+pub const CHDIV_DIV2: Field = Field::new(31, 14, 1);
+/// Bits 18:16 of the N divider
+pub const PLL_N_HI: Field = Field::new(34, 0, 3);
+/// Bits 15:0 of the N divider
+pub const PLL_N_LO: Field = Field::new(36, 0, 16);
+/// Bits 31:16 of the fractional denominator
+pub const PLL_DEN_HI: Field = Field::new(38, 0, 16);
+/// Bits 15:0 of the fractional denominator
+pub const PLL_DEN_LO: Field = Field::new(39, 0, 16);
+/// Bits 31:16 of the fractional numerator
+pub const PLL_NUM_HI: Field = Field::new(42, 0, 16);
+/// Bits 15:0 of the fractional numerator
+pub const PLL_NUM_LO: Field = Field::new(43, 0, 16);
+/// Order of the fractional sigma-delta modulator, 0 = integer mode
+pub const MASH_ORDER: Field = Field::new(44, 0, 3);
+/// Powers down OUTA
+pub const OUTA_PD: Field = Field::new(44, 6, 1);
+/// Powers down OUTB
+pub const OUTB_PD: Field = Field::new(44, 7, 1);
+/// OUTA output power, 0-63
+pub const OUTA_PWR: Field = Field::new(44, 8, 6);
+/// OUTB output power, 0-63
+pub const OUTB_PWR: Field = Field::new(45, 0, 6);
+/// Output buffer current for both outputs: 0 = maximum boost, 3 = none
+pub const OUT_ISET: Field = Field::new(45, 9, 2);
+/// OUTA source: 0 = channel divider, 1 = VCO, 3 = high impedance
+pub const OUTA_MUX: Field = Field::new(45, 11, 2);
+/// OUTB source: 0 = channel divider, 1 = VCO, 2 = SYSREF, 3 = high impedance
+pub const OUTB_MUX: Field = Field::new(46, 0, 2);
+/// OUTx_MUX value selecting the channel divider
+pub const OUT_MUX_CHDIV: u32 = 0;
+/// OUTx_MUX value selecting the VCO directly
+pub const OUT_MUX_VCO: u32 = 1;
+/// OUTB_MUX value selecting the SYSREF output
+pub const OUT_MUX_SYSREF: u32 = 2;
+/// OUTx_MUX value putting the output in high impedance
+pub const OUT_MUX_HIGH_Z: u32 = 3;
+/// SYSREF mode: 0 = master, generated here; 1 = repeater of SysRefReq
+pub const SYSREF_REPEAT: Field = Field::new(71, 2, 1);
+/// Enables the SYSREF generator
+pub const SYSREF_EN: Field = Field::new(71, 3, 1);
+/// In master mode, send bursts of `SYSREF_PULSE_CNT` pulses on each
+/// SysRefReq rising edge instead of running continuously
+pub const SYSREF_PULSE: Field = Field::new(71, 4, 1);
+/// Pulses per burst in pulsed master mode
+pub const SYSREF_PULSE_CNT: Field = Field::new(74, 12, 4);
+/// Channel divider, as an index into `frequency::CHDIV_VALUES`
+pub const CHDIV: Field = Field::new(75, 6, 5);
+/// Starts each VCO calibration from the core and settings found by the
+/// last one, instead of from scratch
+pub const QUICK_RECAL_EN: Field = Field::new(78, 9, 1);
+
+/// Lock detect state read back from R110, see `RB_LD_VTUNE_LOCKED`
+pub const RB_LD_VTUNE: Field = Field::new(110, 9, 2);
+/// RB_LD_VTUNE value reported while the PLL is locked
+pub const RB_LD_VTUNE_LOCKED: u32 = 2;
+/// VCO core chosen by the last calibration, 1-7 for VCO1-VCO7
+pub const RB_VCO_SEL: Field = Field::new(110, 5, 3);
+/// VCO capacitor bank code chosen by the last calibration
+pub const RB_VCO_CAPCTRL: Field = Field::new(111, 0, 8);
+/// VCO amplitude code chosen by the last calibration
+pub const RB_VCO_DACISET: Field = Field::new(112, 0, 9);
+
+/// Primary field of each register that has one, by address, for
+/// annotating register dumps. Sorted by address.
+pub static REGISTER_NAMES: [(u8, &str); 21] = [
+    (0, "FCAL_EN"),
+    (9, "OSC_2X"),
+    (10, "MULT"),
+    (11, "PLL_R"),
+    (12, "PLL_R_PRE"),
+    (14, "CPG"),
+    (16, "VCO_DACISET"),
+    (31, "CHDIV_DIV2"),
+    (34, "PLL_N"),
+    (36, "PLL_N"),
+    (38, "PLL_DEN"),
+    (39, "PLL_DEN"),
+    (42, "PLL_NUM"),
+    (43, "PLL_NUM"),
+    (44, "OUTA_PWR"),
+    (45, "OUTA_MUX"),
+    (46, "OUTB_MUX"),
+    (75, "CHDIV"),
+    (110, "RB_LD_VTUNE"),
+    (111, "RB_VCO_CAPCTRL"),
+    (112, "RB_VCO_DACISET"),
+];
+
+/// Name of the primary field of the register at `addr`, if it has one
+pub fn register_name(addr: u8) -> Option<&'static str> {
+    REGISTER_NAMES
+        .binary_search_by_key(&addr, |&(a, _)| a)
+        .ok()
+        .map(|i| REGISTER_NAMES[i].1)
+}
+
+/// The 19-bit N divider, split across R34 and R36
+pub const fn pll_n(regs: &[u32; 113]) -> u32 {
+    (PLL_N_HI.get(regs) << 16) | PLL_N_LO.get(regs)
+}
+
+/// Set the 19-bit N divider
+pub const fn set_pll_n(regs: &mut [u32; 113], n: u32) {
+    PLL_N_HI.set(regs, n >> 16);
+    PLL_N_LO.set(regs, n);
+}
+
+/// The 32-bit fractional numerator, split across R42 and R43
+pub const fn pll_num(regs: &[u32; 113]) -> u32 {
+    (PLL_NUM_HI.get(regs) << 16) | PLL_NUM_LO.get(regs)
+}
+
+/// Set the 32-bit fractional numerator, which must stay below the
+/// denominator already in `regs`; `Error::OutOfRange` otherwise, leaving
+/// `regs` unchanged. Use `set_frac` to change both at once.
+pub const fn set_pll_num(regs: &mut [u32; 113], num: u32) -> Result<(), Error> {
+    if num >= pll_den(regs) {
+        return Err(Error::OutOfRange);
+    }
+    write_pll_num(regs, num);
+    Ok(())
+}
+
+const fn write_pll_num(regs: &mut [u32; 113], num: u32) {
+    PLL_NUM_HI.set(regs, num >> 16);
+    PLL_NUM_LO.set(regs, num);
+}
+
+/// The 32-bit fractional denominator, split across R38 and R39
+pub const fn pll_den(regs: &[u32; 113]) -> u32 {
+    (PLL_DEN_HI.get(regs) << 16) | PLL_DEN_LO.get(regs)
+}
+
+/// Set the 32-bit fractional denominator, which must stay above the
+/// numerator already in `regs`; `Error::OutOfRange` otherwise, leaving
+/// `regs` unchanged. Use `set_frac` to change both at once.
+pub const fn set_pll_den(regs: &mut [u32; 113], den: u32) -> Result<(), Error> {
+    if den <= pll_num(regs) {
+        return Err(Error::OutOfRange);
+    }
+    write_pll_den(regs, den);
+    Ok(())
+}
+
+const fn write_pll_den(regs: &mut [u32; 113], den: u32) {
+    PLL_DEN_HI.set(regs, den >> 16);
+    PLL_DEN_LO.set(regs, den);
+}
+
+/// Set the fractional numerator and denominator together, so that the map
+/// never holds a fraction of 1 or more, even when the new denominator is
+/// below the old numerator. `Error::OutOfRange` unless `num < den`, leaving
+/// `regs` unchanged.
+pub const fn set_frac(regs: &mut [u32; 113], num: u32, den: u32) -> Result<(), Error> {
+    if num >= den {
+        return Err(Error::OutOfRange);
+    }
+    write_pll_den(regs, den);
+    write_pll_num(regs, num);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::REG_MAP;
+
+    #[test]
+    fn frac_setters_keep_num_below_den() {
+        let mut regs = REG_MAP;
+        set_frac(&mut regs, 500, 1000).unwrap();
+        assert_eq!((pll_num(&regs), pll_den(&regs)), (500, 1000));
+
+        // Shrinking the fraction below the old numerator needs both at once
+        assert_eq!(set_pll_den(&mut regs, 3), Err(Error::OutOfRange));
+        set_frac(&mut regs, 1, 3).unwrap();
+        assert_eq!((pll_num(&regs), pll_den(&regs)), (1, 3));
+
+        let before = regs;
+        assert_eq!(set_frac(&mut regs, 3, 3), Err(Error::OutOfRange));
+        assert_eq!(set_frac(&mut regs, 0, 0), Err(Error::OutOfRange));
+        assert_eq!(set_pll_num(&mut regs, 3), Err(Error::OutOfRange));
+        assert_eq!(set_pll_den(&mut regs, 1), Err(Error::OutOfRange));
+        assert_eq!(regs, before);
+
+        set_pll_num(&mut regs, 2).unwrap();
+        set_pll_den(&mut regs, 7).unwrap();
+        assert_eq!((pll_num(&regs), pll_den(&regs)), (2, 7));
+    }
+
+    #[test]
+    fn fraction_splits_across_both_registers_at_full_width() {
+        let mut regs = REG_MAP;
+        set_frac(&mut regs, u32::MAX - 1, u32::MAX).unwrap();
+        assert_eq!((pll_num(&regs), pll_den(&regs)), (u32::MAX - 1, u32::MAX));
+        assert_eq!(
+            [regs[38], regs[39], regs[42], regs[43]],
+            [0x26_ffff, 0x27_ffff, 0x2a_ffff, 0x2b_fffe]
+        );
+
+        set_frac(&mut regs, 0x8000_0000, 0x8000_0001).unwrap();
+        assert_eq!(
+            [regs[38], regs[39], regs[42], regs[43]],
+            [0x26_8000, 0x27_0001, 0x2a_8000, 0x2b_0000]
+        );
+        assert_eq!(set_pll_num(&mut regs, 0x8000_0001), Err(Error::OutOfRange));
+        assert_eq!(
+            set_frac(&mut regs, u32::MAX, u32::MAX),
+            Err(Error::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn register_names_are_found_by_address() {
+        assert_eq!(register_name(34), Some("PLL_N"));
+        assert_eq!(register_name(36), Some("PLL_N"));
+        assert_eq!(register_name(42), Some("PLL_NUM"));
+        assert_eq!(register_name(43), Some("PLL_NUM"));
+        assert_eq!(register_name(0), Some("FCAL_EN"));
+        assert_eq!(register_name(112), Some("RB_VCO_DACISET"));
+        assert_eq!(register_name(35), None);
+        assert_eq!(register_name(113), None);
+
+        // The lookup is a binary search, so the table must stay sorted
+        assert!(REGISTER_NAMES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+}
@@ -0,0 +1,105 @@
+//! Keeping an uploaded register map image in the Pico's flash.
+//!
+//! The image (see `blob`) lives at the start of the last 4 KiB sector of
+//! the 2 MiB flash, which `memory.x` keeps out of the program's reach.
+//! Reading goes through the XIP window like any other constant. Writing
+//! uses the bootrom's flash routines, which need XIP switched off while
+//! they run, so the code calling them runs from RAM with interrupts
+//! disabled, and only calls through function pointers looked up
+//! beforehand. XIP is restored in its slow generic mode afterwards.
+
+use crate::blob::{self, BLOB_LEN};
+
+/// Size of the Pico's flash
+pub const FLASH_SIZE: u32 = 2 * 1024 * 1024;
+/// Smallest erasable unit of flash
+pub const SECTOR_SIZE: u32 = 4096;
+/// Offset of the stored image from the start of flash
+pub const MAP_OFFSET: u32 = FLASH_SIZE - SECTOR_SIZE;
+
+/// Where flash is mapped for reading
+const XIP_BASE: u32 = 0x1000_0000;
+/// Smallest programmable unit of flash
+const PAGE_SIZE: usize = 256;
+/// The image rounded up to whole pages
+const PROGRAM_LEN: usize = BLOB_LEN.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+/// The image as it is stored, which may be erased flash or stale
+pub fn stored() -> &'static [u8; BLOB_LEN] {
+    // SAFETY: the sector is reserved in memory.x, always mapped, and only
+    // changed by `store` and `erase`, which nothing can run alongside
+    unsafe { &*((XIP_BASE + MAP_OFFSET) as *const [u8; BLOB_LEN]) }
+}
+
+/// The stored register map, if a valid image has been stored
+pub fn load() -> Option<[u32; 113]> {
+    blob::parse(stored()).ok()
+}
+
+/// Bootrom routines used by `store`
+struct RomFns {
+    connect_internal_flash: extern "C" fn(),
+    flash_exit_xip: extern "C" fn(),
+    flash_range_erase: extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: extern "C" fn(),
+    flash_enter_cmd_xip: extern "C" fn(),
+}
+
+/// Find a bootrom function by its two-letter tag
+fn rom_fn<T>(tag: &[u8; 2]) -> T {
+    type Lookup<T> = unsafe extern "C" fn(*const u16, u32) -> T;
+    // SAFETY: the bootrom keeps 16-bit pointers to its function table at
+    // 0x14 and to the lookup routine at 0x18 (RP2040 datasheet 2.8.3)
+    unsafe {
+        let table = *(0x14 as *const u16) as *const u16;
+        let lookup: Lookup<T> = core::mem::transmute(*(0x18 as *const u16) as usize);
+        lookup(table, u16::from_le_bytes(*tag) as u32)
+    }
+}
+
+/// Erase the sector and program `data`, if any, into it. Runs from RAM,
+/// since flash cannot be read while it is being written.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+fn write_sector(rom: &RomFns, data: Option<&[u8; PROGRAM_LEN]>) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    // 0x20 is the 4 KiB sector erase command
+    (rom.flash_range_erase)(MAP_OFFSET, SECTOR_SIZE as usize, SECTOR_SIZE, 0x20);
+    if let Some(data) = data {
+        (rom.flash_range_program)(MAP_OFFSET, data.as_ptr(), data.len());
+    }
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+}
+
+/// The bootrom routines, looked up before XIP is switched off
+fn rom_fns() -> RomFns {
+    RomFns {
+        connect_internal_flash: rom_fn(b"IF"),
+        flash_exit_xip: rom_fn(b"EX"),
+        flash_range_erase: rom_fn(b"RE"),
+        flash_range_program: rom_fn(b"RP"),
+        flash_flush_cache: rom_fn(b"FC"),
+        flash_enter_cmd_xip: rom_fn(b"CX"),
+    }
+}
+
+/// Replace the stored image. Blocks, with interrupts off, for the tens of
+/// milliseconds the erase takes; the caller should have checked the image
+/// with `blob::parse` already.
+pub fn store(image: &[u8; BLOB_LEN]) {
+    let rom = rom_fns();
+    // Unused bytes of the last page are left as erased flash
+    let mut data = [0xff; PROGRAM_LEN];
+    data[..BLOB_LEN].copy_from_slice(image);
+    cortex_m::interrupt::free(|_| write_sector(&rom, Some(&data)));
+}
+
+/// Erase the stored image, so that the firmware boots with `REG_MAP`.
+/// Blocks like `store`.
+pub fn erase() {
+    let rom = rom_fns();
+    cortex_m::interrupt::free(|_| write_sector(&rom, None));
+}
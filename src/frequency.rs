@@ -0,0 +1,2360 @@
+//! Frequency planning for the LMX2594.
+//!
+//! The synthesizer frequencies follow from the register map as
+//!
+//! ```text
+//! fPD  = fOSC * (1 + OSC_2X) * MULT / (PLL_R_PRE * PLL_R)
+//! fVCO = fPD * (PLL_N + PLL_NUM / PLL_DEN)
+//! fOUT = fVCO / CHDIV, or fVCO when the output mux selects the VCO
+//! ```
+//!
+//! Frequencies are passed around as `Frequency` so that the unit is always
+//! explicit at the call site.
+
+use crate::fields;
+use crate::lmx2594::{Error, Output, REG_MAP};
+use core::fmt;
+
+/// A frequency, stored as an integer number of Hz
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default, defmt::Format)]
+#[repr(transparent)]
+pub struct Frequency(u64);
+
+impl Frequency {
+    pub const fn from_hz(hz: u64) -> Self {
+        Frequency(hz)
+    }
+
+    pub const fn from_khz(khz: u64) -> Self {
+        Frequency(khz * 1_000)
+    }
+
+    pub const fn from_mhz(mhz: u64) -> Self {
+        Frequency(mhz * 1_000_000)
+    }
+
+    pub const fn from_ghz(ghz: u64) -> Self {
+        Frequency(ghz * 1_000_000_000)
+    }
+
+    pub const fn as_hz(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", self.0)
+    }
+}
+
+/// Lowest frequency the VCO can be calibrated to
+pub const VCO_MIN: Frequency = Frequency::from_mhz(7_500);
+/// Highest frequency the VCO can be calibrated to
+pub const VCO_MAX: Frequency = Frequency::from_ghz(15);
+
+/// Highest phase detector frequency, in integer mode
+pub const PFD_MAX: Frequency = Frequency::from_mhz(400);
+/// Lowest phase detector frequency, in integer mode
+pub const PFD_MIN: Frequency = Frequency::from_khz(125);
+/// Highest phase detector frequency in fractional mode, MASH order 1-3
+pub const PFD_MAX_FRAC: Frequency = Frequency::from_mhz(300);
+/// Highest phase detector frequency in fractional mode, MASH order 4
+pub const PFD_MAX_FRAC_4TH: Frequency = Frequency::from_mhz(240);
+/// Lowest phase detector frequency in fractional mode
+pub const PFD_MIN_FRAC: Frequency = Frequency::from_mhz(5);
+/// Lowest reference frequency accepted on OSCin
+pub const OSC_IN_MIN: Frequency = Frequency::from_mhz(5);
+/// Highest reference frequency accepted on OSCin
+pub const OSC_IN_MAX: Frequency = Frequency::from_mhz(1_400);
+/// Highest reference frequency the OSC_2X doubler accepts
+pub const DOUBLER_MAX_INPUT: Frequency = Frequency::from_mhz(200);
+/// Input range of the reference multiplier when it is not bypassed
+pub const MULT_INPUT: (Frequency, Frequency) = (Frequency::from_mhz(40), Frequency::from_mhz(70));
+/// Output range of the reference multiplier when it is not bypassed
+pub const MULT_OUTPUT: (Frequency, Frequency) =
+    (Frequency::from_mhz(180), Frequency::from_mhz(250));
+
+/// Highest VCO frequency usable with channel divider values other than 2
+pub const VCO_MAX_DIVIDED: Frequency = Frequency::from_mhz(11_500);
+
+/// Divide ratios selected by each value of the CHDIV field
+pub static CHDIV_VALUES: [u32; 18] = CHDIV_TABLE;
+
+const CHDIV_TABLE: [u32; 18] = [
+    2, 4, 6, 8, 12, 16, 24, 32, 48, 64, 72, 96, 128, 192, 256, 384, 512, 768,
+];
+
+/// The channel divider as it sits in the register map: the CHDIV code in
+/// R75 and the CHDIV_DIV2 enable in R31.
+///
+/// The divider is a chain of segments behind a single code, and the code is
+/// not the ratio, so ratios should only ever be turned into register bits
+/// here. With CHDIV_DIV2 clear the divider is bypassed (ratio 1) and CHDIV
+/// is ignored; with it set, CHDIV selects the ratio:
+///
+/// ```text
+/// CHDIV   0  1  2  3   4   5   6   7   8   9  10  11   12   13   14   15   16   17
+/// ratio   2  4  6  8  12  16  24  32  48  64  72  96  128  192  256  384  512  768
+/// ```
+///
+/// Codes 18-31 are not valid.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct ChDivEncoding {
+    /// CHDIV, R75[10:6]
+    pub chdiv: u8,
+    /// CHDIV_DIV2, R31[14]
+    pub div2: bool,
+}
+
+impl ChDivEncoding {
+    /// The divider bypassed
+    pub const BYPASS: Self = ChDivEncoding {
+        chdiv: 0,
+        div2: false,
+    };
+
+    /// The encoding of `ratio`, or `None` if the divider cannot divide by
+    /// it. A ratio of 1 is `BYPASS`.
+    pub const fn from_ratio(ratio: u32) -> Option<Self> {
+        if ratio == 1 {
+            return Some(Self::BYPASS);
+        }
+        let mut i = 0;
+        while i < CHDIV_TABLE.len() {
+            if CHDIV_TABLE[i] == ratio {
+                return Some(ChDivEncoding {
+                    chdiv: i as u8,
+                    div2: true,
+                });
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// The divide ratio, or `None` for an invalid CHDIV code
+    pub const fn ratio(self) -> Option<u32> {
+        if !self.div2 {
+            Some(1)
+        } else if (self.chdiv as usize) < CHDIV_TABLE.len() {
+            Some(CHDIV_TABLE[self.chdiv as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Read the divider out of a register map
+    pub const fn from_regs(regs: &[u32; 113]) -> Self {
+        ChDivEncoding {
+            chdiv: fields::CHDIV.get(regs) as u8,
+            div2: fields::CHDIV_DIV2.get(regs) != 0,
+        }
+    }
+
+    /// Write the divider into R31 and R75 of a register map. Bypassing
+    /// leaves CHDIV as it was.
+    pub const fn apply(self, regs: &mut [u32; 113]) {
+        if self.div2 {
+            fields::CHDIV.set(regs, self.chdiv as u32);
+        }
+        fields::CHDIV_DIV2.set(regs, self.div2 as u32);
+    }
+}
+
+/// Numerator and denominator of fPD / fOSC for the reference path.
+///
+/// A divider or multiplier of zero is not a valid setting; it is treated as
+/// one so that a garbage map cannot cause a division by zero.
+const fn pfd_ratio(regs: &[u32; 113]) -> (u128, u128) {
+    let doubler = 1 + fields::OSC_2X.get(regs) as u128;
+    let (mult, div) = mult_ratio(regs);
+    (doubler * mult, div)
+}
+
+/// As `pfd_ratio`, leaving out the OSC_2X doubler
+const fn mult_ratio(regs: &[u32; 113]) -> (u128, u128) {
+    const fn at_least_one(x: u32) -> u128 {
+        if x == 0 {
+            1
+        } else {
+            x as u128
+        }
+    }
+    let mult = at_least_one(fields::MULT.get(regs));
+    let r_pre = at_least_one(fields::PLL_R_PRE.get(regs));
+    let r = at_least_one(fields::PLL_R.get(regs));
+    (mult, r_pre * r)
+}
+
+/// Settings of the reference path between OSCin and the phase detector
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct RefPath {
+    /// OSC_2X: double the reference
+    pub doubler: bool,
+    /// MULT: 1 to bypass, otherwise 3-7
+    pub mult: u8,
+    /// PLL_R_PRE: 1-4095
+    pub r_pre: u16,
+    /// PLL_R: 1-255
+    pub r: u8,
+}
+
+impl RefPath {
+    /// Read the reference path out of a register map
+    pub fn from_regs(regs: &[u32; 113]) -> Self {
+        RefPath {
+            doubler: fields::OSC_2X.get(regs) != 0,
+            mult: fields::MULT.get(regs) as u8,
+            r_pre: fields::PLL_R_PRE.get(regs) as u16,
+            r: fields::PLL_R.get(regs) as u8,
+        }
+    }
+
+    /// Write the reference path into R9-R12 of a register map
+    pub fn apply(&self, regs: &mut [u32; 113]) {
+        fields::OSC_2X.set(regs, self.doubler as u32);
+        fields::MULT.set(regs, self.mult as u32);
+        fields::PLL_R_PRE.set(regs, self.r_pre as u32);
+        fields::PLL_R.set(regs, self.r as u32);
+    }
+
+    /// Phase detector frequency this path produces from `fref`
+    pub fn pfd(&self, fref: Frequency) -> Frequency {
+        let num = (1 + self.doubler as u64) * self.mult.max(1) as u64;
+        let den = self.r_pre.max(1) as u64 * self.r.max(1) as u64;
+        Frequency(fref.0 * num / den)
+    }
+}
+
+/// Find a reference path taking `fref` to exactly `target_pfd`.
+///
+/// The simplest path wins: the doubler and multiplier are only used if the
+/// dividers alone cannot reach the target, and division is done in PLL_R
+/// before PLL_R_PRE.
+pub fn solve_ref_path(fref: Frequency, target_pfd: Frequency) -> Result<RefPath, Error> {
+    let unreachable = Error::InvalidFrequency(target_pfd);
+    if !(PFD_MIN..=PFD_MAX).contains(&target_pfd) {
+        return Err(unreachable);
+    }
+    for &doubler in [false, true].iter() {
+        if doubler && fref > DOUBLER_MAX_INPUT {
+            continue;
+        }
+        let doubled = fref.0 * (1 + doubler as u64);
+        for &mult in [1u8, 3, 4, 5, 6, 7].iter() {
+            let multiplied = doubled * mult as u64;
+            if !multiplied.is_multiple_of(target_pfd.0) {
+                continue;
+            }
+            let divide = multiplied / target_pfd.0;
+            for r_pre in 1..=4095u64 {
+                if !divide.is_multiple_of(r_pre) {
+                    continue;
+                }
+                let r = divide / r_pre;
+                if r > 255 {
+                    continue;
+                }
+                if mult > 1 {
+                    let input = Frequency(doubled / r_pre);
+                    let output = Frequency(input.0 * mult as u64);
+                    if !(MULT_INPUT.0..=MULT_INPUT.1).contains(&input)
+                        || !(MULT_OUTPUT.0..=MULT_OUTPUT.1).contains(&output)
+                        || !doubled.is_multiple_of(r_pre)
+                    {
+                        continue;
+                    }
+                }
+                return Ok(RefPath {
+                    doubler,
+                    mult,
+                    r_pre: r_pre as u16,
+                    r: r as u8,
+                });
+            }
+        }
+    }
+    Err(unreachable)
+}
+
+/// Which reference path `solve_ref_path_with` picks when several reach a
+/// usable phase detector frequency
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum RefPathStrategy {
+    /// Double and multiply as little as possible, then take the highest PFD
+    /// that allows. Each active stage adds its own noise floor.
+    MinMultiplier,
+    /// Take the highest PFD, for the lowest in-band phase noise, with the
+    /// least doubling and multiplication among equals
+    #[default]
+    MaxPfd,
+    /// Take the highest PFD without the multiplier, whose output carries
+    /// spurs at multiples of its input; the doubler may still be used
+    LowestSpur,
+}
+
+/// Settings for `solve_ref_path_with`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub struct RefPathConfig {
+    /// Which of the valid paths to pick
+    pub strategy: RefPathStrategy,
+}
+
+impl RefPath {
+    /// Combined gain of the doubler and multiplier
+    fn multiplication(&self) -> u32 {
+        (1 + self.doubler as u32) * self.mult.max(1) as u32
+    }
+}
+
+/// With the doubler and MULT fixed, the path with the highest PFD not above
+/// `max_pfd`, if any
+fn fastest_ref_path(
+    fref: Frequency,
+    doubler: bool,
+    mult: u8,
+    max_pfd: Frequency,
+) -> Option<RefPath> {
+    let doubled = fref.0 * (1 + doubler as u64);
+    if mult == 1 {
+        // Any total division will do; as in `solve_ref_path`, PLL_R takes
+        // as much of it as it can
+        let mut divide = doubled.div_ceil(max_pfd.0).max(1);
+        while divide <= 4095 * 255 {
+            if let Some(r) = (1..=255u64)
+                .rev()
+                .find(|&r| divide.is_multiple_of(r) && divide / r <= 4095)
+            {
+                return Some(RefPath {
+                    doubler,
+                    mult,
+                    r_pre: (divide / r) as u16,
+                    r: r as u8,
+                });
+            }
+            divide += 1;
+        }
+        return None;
+    }
+    // PLL_R_PRE brings the reference into the multiplier's input range, and
+    // PLL_R divides its output down
+    let mut best: Option<RefPath> = None;
+    for r_pre in 1..=4095u64 {
+        let input = Frequency(doubled / r_pre);
+        if input < MULT_INPUT.0 {
+            break;
+        }
+        let output = Frequency(input.0 * mult as u64);
+        if !doubled.is_multiple_of(r_pre)
+            || input > MULT_INPUT.1
+            || !(MULT_OUTPUT.0..=MULT_OUTPUT.1).contains(&output)
+        {
+            continue;
+        }
+        let r = output.0.div_ceil(max_pfd.0);
+        if r > 255 {
+            continue;
+        }
+        let path = RefPath {
+            doubler,
+            mult,
+            r_pre: r_pre as u16,
+            r: r as u8,
+        };
+        if best.is_none_or(|b| path.pfd(fref) > b.pfd(fref)) {
+            best = Some(path);
+        }
+    }
+    best
+}
+
+/// Find a reference path taking `fref` to a phase detector frequency of at
+/// most `max_pfd`, choosing among the valid ones by `config.strategy`.
+///
+/// Unlike `solve_ref_path`, the PFD need not hit `max_pfd` exactly. It is
+/// returned as `Error::InvalidFrequency` if no path reaches `PFD_MIN`.
+pub fn solve_ref_path_with(
+    fref: Frequency,
+    max_pfd: Frequency,
+    config: &RefPathConfig,
+) -> Result<RefPath, Error> {
+    let max_pfd = max_pfd.min(PFD_MAX);
+    let mut best: Option<RefPath> = None;
+    for &doubler in [false, true].iter() {
+        if doubler && fref > DOUBLER_MAX_INPUT {
+            continue;
+        }
+        for &mult in [1u8, 3, 4, 5, 6, 7].iter() {
+            if mult > 1 && config.strategy == RefPathStrategy::LowestSpur {
+                continue;
+            }
+            let path = match fastest_ref_path(fref, doubler, mult, max_pfd) {
+                Some(path) if path.pfd(fref) >= PFD_MIN => path,
+                _ => continue,
+            };
+            let better = match best {
+                None => true,
+                Some(b) => {
+                    let (pfd, best_pfd) = (path.pfd(fref), b.pfd(fref));
+                    let (gain, best_gain) = (path.multiplication(), b.multiplication());
+                    match config.strategy {
+                        RefPathStrategy::MinMultiplier => {
+                            gain < best_gain || (gain == best_gain && pfd > best_pfd)
+                        }
+                        RefPathStrategy::MaxPfd | RefPathStrategy::LowestSpur => {
+                            pfd > best_pfd || (pfd == best_pfd && gain < best_gain)
+                        }
+                    }
+                }
+            };
+            if better {
+                best = Some(path);
+            }
+        }
+    }
+    best.ok_or(Error::InvalidFrequency(max_pfd))
+}
+
+/// Set the reference path in `regs` so that a reference of `fref` gives a
+/// phase detector frequency of exactly `target_pfd`.
+///
+/// If `regs` already holds a fractional N divider, `target_pfd` must also
+/// satisfy `check_fractional_pfd`.
+pub fn set_pfd(
+    fref: Frequency,
+    regs: &mut [u32; 113],
+    target_pfd: Frequency,
+) -> Result<RefPath, Error> {
+    let path = solve_ref_path(fref, target_pfd)?;
+    if fields::pll_num(regs) != 0 {
+        check_fractional_pfd(target_pfd, regs)?;
+    }
+    path.apply(regs);
+    Ok(path)
+}
+
+/// A reference oscillator and path suggested by `suggest_reference`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct ReferenceSuggestion {
+    /// Reference frequency to fit on OSCin
+    pub reference: Frequency,
+    /// Reference path taking it to `pfd`
+    pub path: RefPath,
+    /// Phase detector frequency, at most the target PFD
+    pub pfd: Frequency,
+    /// Integer N divider giving the VCO frequency from `pfd`
+    pub n: u32,
+    /// Where the output is taken from
+    pub output: OutputPath,
+}
+
+/// Suggest a reference frequency for producing `target` in integer mode
+/// with a PFD as close to `target_pfd` as possible without exceeding it.
+///
+/// The PFD is the highest integer fraction of the VCO frequency not above
+/// `target_pfd`. Of the references that reach it, the one with the
+/// simplest path wins: straight into the phase detector if the PFD is in
+/// the OSCin range, otherwise the smallest PLL_R multiple of the PFD.
+pub fn suggest_reference(
+    target: Frequency,
+    target_pfd: Frequency,
+) -> Result<ReferenceSuggestion, Error> {
+    let output = output_path(target)?;
+    let vco = check_vco_range(Frequency(target.0 * output.divide_ratio() as u64))?;
+    let target_pfd = target_pfd.min(PFD_MAX);
+    if target_pfd < PFD_MIN {
+        return Err(Error::InvalidFrequency(target_pfd));
+    }
+
+    let mut n = vco.0.div_ceil(target_pfd.0);
+    while n < 1 << 19 && !vco.0.is_multiple_of(n) {
+        n += 1;
+    }
+    let pfd = Frequency(vco.0 / n);
+    if pfd < PFD_MIN || n >= 1 << 19 {
+        return Err(Error::InvalidFrequency(target));
+    }
+
+    for r in 1..=255 {
+        let reference = Frequency(pfd.0 * r);
+        if reference < OSC_IN_MIN {
+            continue;
+        }
+        if reference > OSC_IN_MAX {
+            break;
+        }
+        if let Ok(path) = solve_ref_path(reference, pfd) {
+            return Ok(ReferenceSuggestion {
+                reference,
+                path,
+                pfd,
+                n: n as u32,
+                output,
+            });
+        }
+    }
+    Err(Error::InvalidFrequency(pfd))
+}
+
+/// Where the reference is doubled, if at all, on a given board.
+///
+/// Everywhere else in this module `fref` is the frequency at OSCin. A board
+/// with a doubler between its oscillator (e.g. a VCXO) and OSCin presents
+/// twice the oscillator frequency there and must leave OSC_2X off, or the
+/// reference would be doubled twice; a board without one can double with
+/// OSC_2X instead, but only below `DOUBLER_MAX_INPUT`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum ReferenceSource {
+    /// The oscillator drives OSCin, OSC_2X off
+    #[default]
+    Direct,
+    /// The oscillator drives OSCin, doubled by OSC_2X
+    InternalDoubler,
+    /// The oscillator is doubled on the board before OSCin, OSC_2X off
+    ExternalDoubled,
+}
+
+impl ReferenceSource {
+    /// Frequency at OSCin for an oscillator of `fosc`
+    pub fn osc_in(self, fosc: Frequency) -> Frequency {
+        match self {
+            ReferenceSource::ExternalDoubled => Frequency(fosc.0 * 2),
+            _ => fosc,
+        }
+    }
+
+    /// Check that an oscillator of `fosc` is usable with this source,
+    /// returning `Error::InvalidFrequency` with the offending input if not
+    pub fn check(self, fosc: Frequency) -> Result<(), Error> {
+        let osc_in = self.osc_in(fosc);
+        if !(OSC_IN_MIN..=OSC_IN_MAX).contains(&osc_in) {
+            return Err(Error::InvalidFrequency(osc_in));
+        }
+        if self == ReferenceSource::InternalDoubler && fosc > DOUBLER_MAX_INPUT {
+            return Err(Error::InvalidFrequency(fosc));
+        }
+        Ok(())
+    }
+
+    /// Set OSC_2X in `regs` to match
+    pub fn apply(self, regs: &mut [u32; 113]) {
+        let doubler = self == ReferenceSource::InternalDoubler;
+        fields::OSC_2X.set(regs, doubler as u32);
+    }
+
+    /// Phase detector frequency for an oscillator of `fosc`, taking the
+    /// doubling from this source and the rest of the path from `regs`
+    pub fn pfd(self, fosc: Frequency, regs: &[u32; 113]) -> Frequency {
+        let doubler = match self {
+            ReferenceSource::Direct => 1,
+            _ => 2,
+        };
+        let (mult, div) = mult_ratio(regs);
+        Frequency((fosc.0 as u128 * doubler * mult / div) as u64)
+    }
+}
+
+/// Phase detector frequency for a reference of `fref`
+pub fn pfd_frequency(fref: Frequency, regs: &[u32; 113]) -> Frequency {
+    let (num, den) = pfd_ratio(regs);
+    Frequency((fref.0 as u128 * num / den) as u64)
+}
+
+/// VCO frequency for a reference of `fref`, ignoring the VCO range
+pub fn vco_frequency_unchecked(fref: Frequency, regs: &[u32; 113]) -> Frequency {
+    let (pfd_num, pfd_den) = pfd_ratio(regs);
+    let n = fields::pll_n(regs) as u128;
+    let num = fields::pll_num(regs) as u128;
+    let den = fields::pll_den(regs).max(1) as u128;
+    Frequency((fref.0 as u128 * pfd_num * (n * den + num) / (pfd_den * den)) as u64)
+}
+
+/// VCO frequency (before the channel divider) for a reference of `fref`.
+///
+/// Returns `Error::VcoOutOfRange` if the VCO would have to run outside of
+/// 7.5-15 GHz, which is the usual reason a divider/N combination does not
+/// lock.
+pub fn vco_frequency(fref: Frequency, regs: &[u32; 113]) -> Result<Frequency, Error> {
+    check_vco_range(vco_frequency_unchecked(fref, regs))
+}
+
+/// Frequency of OUTA for a reference of `fref`, following its mux setting.
+///
+/// Returns `None` when OUTA is in high impedance.
+pub fn output_frequency(fref: Frequency, regs: &[u32; 113]) -> Option<Frequency> {
+    channel_frequency(fref, regs, Output::A)
+}
+
+/// Frequency of `output` for a reference of `fref`, following its own mux
+/// setting, so OUTA and OUTB can differ when one is on the VCO and the
+/// other on the channel divider.
+///
+/// Returns `None` when the output is in high impedance, or OUTB carries
+/// SYSREF.
+pub fn channel_frequency(fref: Frequency, regs: &[u32; 113], output: Output) -> Option<Frequency> {
+    let mux = match output {
+        Output::A => fields::OUTA_MUX,
+        Output::B => fields::OUTB_MUX,
+    };
+    let vco = vco_frequency_unchecked(fref, regs);
+    match mux.get(regs) {
+        fields::OUT_MUX_CHDIV => {
+            let div = CHDIV_VALUES.get(fields::CHDIV.get(regs) as usize)?;
+            Some(Frequency(vco.0 / *div as u64))
+        }
+        fields::OUT_MUX_VCO => Some(vco),
+        _ => None,
+    }
+}
+
+/// A frequency that need not be a whole number of Hz, held as the exact
+/// fraction `num / den` Hz
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Resolution {
+    /// Numerator, in Hz
+    pub num: u128,
+    /// Denominator, never zero
+    pub den: u128,
+}
+
+impl Resolution {
+    /// Rounded to the nearest µHz
+    pub fn as_uhz(&self) -> u64 {
+        ((self.num * 1_000_000 + self.den / 2) / self.den) as u64
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let uhz = self.as_uhz();
+        write!(f, "{}.{:06} Hz", uhz / 1_000_000, uhz % 1_000_000)
+    }
+}
+
+/// Smallest step OUTA can be tuned by with the current PFD, PLL_DEN and
+/// channel divider: one LSB of PLL_NUM, divided down like the output.
+/// `None` under the same conditions as `output_frequency`.
+pub fn frequency_resolution_hz(fref: Frequency, regs: &[u32; 113]) -> Option<Resolution> {
+    let div = match fields::OUTA_MUX.get(regs) {
+        fields::OUT_MUX_CHDIV => *CHDIV_VALUES.get(fields::CHDIV.get(regs) as usize)?,
+        fields::OUT_MUX_VCO => 1,
+        _ => return None,
+    };
+    let (pfd_num, pfd_den) = pfd_ratio(regs);
+    let num = fref.0 as u128 * pfd_num;
+    let den = pfd_den * fields::pll_den(regs).max(1) as u128 * div as u128;
+    let common = gcd(num, den).max(1);
+    Some(Resolution {
+        num: num / common,
+        den: den / common,
+    })
+}
+
+/// Lowest and highest output frequencies `solve` can reach for a reference
+/// of `fref` through the reference path in `regs`, or `None` if the PFD is
+/// too slow for N to reach the VCO range at all.
+///
+/// The low end is the bottom of the VCO range through the largest channel
+/// divider; the high end is the top of the VCO range, or the fastest VCO
+/// the 19-bit N divider reaches from a slow PFD. Not every frequency in
+/// between is reachable exactly: see `frequency_resolution_hz`, and note
+/// that a PFD below `PFD_MIN_FRAC` only reaches its integer multiples.
+pub fn achievable_range(fref: Frequency, regs: &[u32; 113]) -> Option<(Frequency, Frequency)> {
+    let (pfd_num, pfd_den) = pfd_ratio(regs);
+    // N stays below 2^19, so the VCO stays below 2^19 PFD periods
+    let top = ((fref.0 as u128 * pfd_num) << 19) / pfd_den;
+    let top = top.saturating_sub(1).min(VCO_MAX.0 as u128) as u64;
+    if top < VCO_MIN.0 {
+        return None;
+    }
+    let max_ratio = CHDIV_TABLE[CHDIV_TABLE.len() - 1] as u64;
+    Some((Frequency(VCO_MIN.0.div_ceil(max_ratio)), Frequency(top)))
+}
+
+/// Check that `vco` lies in the calibratable VCO range
+pub fn check_vco_range(vco: Frequency) -> Result<Frequency, Error> {
+    if (VCO_MIN..=VCO_MAX).contains(&vco) {
+        Ok(vco)
+    } else {
+        defmt::warn!("VCO frequency {} Hz is outside of 7.5-15 GHz", vco.0);
+        Err(Error::VcoOutOfRange(vco))
+    }
+}
+
+/// Check that `pfd` is within the fractional-mode limits for the MASH order
+/// set in `regs`.
+///
+/// The phase detector cannot run as fast in fractional mode as in integer
+/// mode, and a map that exceeds the limit programs cleanly but never locks.
+/// The offending PFD is returned as `Error::InvalidFrequency`; the fix is
+/// either an integer-mode plan or a reference path with a lower PFD.
+pub fn check_fractional_pfd(pfd: Frequency, regs: &[u32; 113]) -> Result<(), Error> {
+    let max = if fields::MASH_ORDER.get(regs) >= 4 {
+        PFD_MAX_FRAC_4TH
+    } else {
+        PFD_MAX_FRAC
+    };
+    if (PFD_MIN_FRAC..=max).contains(&pfd) {
+        Ok(())
+    } else {
+        defmt::warn!(
+            "PFD of {} Hz is outside of the fractional-mode range {}-{} Hz; use integer mode or change the reference path",
+            pfd.0,
+            PFD_MIN_FRAC.0,
+            max.0
+        );
+        Err(Error::InvalidFrequency(pfd))
+    }
+}
+
+/// How the output is derived from the VCO
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum OutputPath {
+    /// Straight from the VCO, bypassing the channel divider
+    Vco,
+    /// Through the channel divider, holding the CHDIV field value
+    Divider(u8),
+}
+
+impl OutputPath {
+    /// The channel divider setting this path needs
+    pub const fn encoding(self) -> ChDivEncoding {
+        match self {
+            OutputPath::Vco => ChDivEncoding::BYPASS,
+            OutputPath::Divider(chdiv) => ChDivEncoding { chdiv, div2: true },
+        }
+    }
+
+    /// Ratio between the VCO and output frequencies
+    pub fn divide_ratio(self) -> u32 {
+        match self {
+            OutputPath::Vco => 1,
+            OutputPath::Divider(chdiv) => CHDIV_VALUES[chdiv as usize],
+        }
+    }
+}
+
+/// Divider settings reaching a target frequency from a fixed reference path
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct FrequencyPlan {
+    /// Where the output is taken from
+    pub path: OutputPath,
+    /// Integer part of the N divider
+    pub n: u32,
+    /// Fractional numerator
+    pub num: u32,
+    /// Fractional denominator
+    pub den: u32,
+    /// VCO frequency actually reached
+    pub vco: Frequency,
+    /// Output frequency actually reached
+    pub output: Frequency,
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+/// The fraction `num / den` closest to `numer / denom` with `den` no more
+/// than `max_den`, the smaller denominator on a tie.
+///
+/// This is the best rational approximation, found from the continued
+/// fraction expansion of `numer / denom`: the last convergent within
+/// `max_den`, or the largest semiconvergent after it, whichever is closer.
+/// An exact fraction in lowest terms is returned when one fits. Any `u64`
+/// inputs are safe; a `denom` of 0 gives `(0, 1)`, and a `max_den` of 0
+/// counts as 1. A `num` too large for a `u64`, possible only for ratios
+/// above 1, saturates.
+pub fn approximate_ratio(numer: u64, denom: u64, max_den: u64) -> (u64, u64) {
+    if denom == 0 {
+        return (0, 1);
+    }
+    let max_den = max_den.max(1) as u128;
+    let (x_num, x_den) = (numer as u128, denom as u128);
+    // Previous and current convergents, p/q
+    let (mut p0, mut q0, mut p1, mut q1) = (0u128, 1u128, 1u128, 0u128);
+    let (mut n, mut d) = (x_num, x_den);
+    while d != 0 {
+        let a = n / d;
+        let q2 = q0 + a * q1;
+        if q2 > max_den {
+            break;
+        }
+        let p2 = p0 + a * p1;
+        (p0, q0, p1, q1) = (p1, q1, p2, q2);
+        (n, d) = (d, n - a * d);
+    }
+    let fit = |p: u128, q: u128| (p.min(u64::MAX as u128) as u64, q as u64);
+    if d == 0 {
+        return fit(p1, q1);
+    }
+    let k = (max_den - q0) / q1;
+    let (p2, q2) = (p0 + k * p1, q0 + k * q1);
+    // |x - p/q| * x_den = |x_num * q - p * x_den| / q, where the numerator
+    // is below x_den; wrapping arithmetic recovers it exactly
+    let error = |p: u128, q: u128| {
+        (x_num.wrapping_mul(q).wrapping_sub(p.wrapping_mul(x_den)) as i128).unsigned_abs()
+    };
+    if error(p2, q2) * q1 < error(p1, q1) * q2 {
+        fit(p2, q2)
+    } else {
+        fit(p1, q1)
+    }
+}
+
+/// Why `choose_divider` picked the output path it did
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum DividerReason {
+    /// The target is in the VCO range, so no divider is needed
+    VcoInRange,
+    /// Only one channel divider reaches the target
+    OnlyChoice,
+    /// Of several dividers reaching the target, this one puts the VCO
+    /// closest to the center of a core
+    BestCentered {
+        /// Number of other dividers that would also have worked
+        alternatives: u8,
+    },
+}
+
+/// The output path `choose_divider` picked for a target
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct DividerChoice {
+    /// The chosen path
+    pub path: OutputPath,
+    /// VCO frequency it needs
+    pub vco: Frequency,
+    /// Index into `VCO_CORES` of the core covering `vco`
+    pub core: usize,
+    /// Distance of `vco` from the center of that core, in thousandths of
+    /// the core's half-width: 0 at the center, 1000 at either edge
+    pub core_offset: u32,
+    /// Why this path was chosen
+    pub reason: DividerReason,
+}
+
+/// The VCO core covering `vco` and how far off its center `vco` is, as
+/// for `DividerChoice::core_offset`
+const fn core_offset(vco: u64) -> Option<(usize, u32)> {
+    let mut i = 0;
+    while i < VCO_CORES.len() {
+        let (min, max) = (VCO_CORES[i].min.0, VCO_CORES[i].max.0);
+        if vco >= min && vco <= max {
+            let center = (min + max) / 2;
+            let offset = vco.abs_diff(center);
+            return Some((i, (offset * 1000 / ((max - min) / 2)) as u32));
+        }
+        i += 1;
+    }
+    None
+}
+
+/// CHDIV value of the divider putting the VCO closest to the center of a
+/// core for `target`, the smaller divider on a tie, and the number of
+/// dividers that reach `target` at all
+const fn best_divider(target: u64) -> (Option<usize>, u32) {
+    let mut best: Option<(usize, u32)> = None;
+    let mut count = 0;
+    let mut i = 0;
+    while i < CHDIV_TABLE.len() {
+        let vco = target.saturating_mul(CHDIV_TABLE[i] as u64);
+        let max = if CHDIV_TABLE[i] == 2 {
+            VCO_MAX
+        } else {
+            VCO_MAX_DIVIDED
+        };
+        if vco >= VCO_MIN.0 && vco <= max.0 {
+            count += 1;
+            if let Some((_, offset)) = core_offset(vco) {
+                best = match best {
+                    Some((_, b)) if b <= offset => best,
+                    _ => Some((i, offset)),
+                };
+            }
+        }
+        i += 1;
+    }
+    match best {
+        Some((chdiv, _)) => (Some(chdiv), count),
+        None => (None, count),
+    }
+}
+
+/// Pick the output path for `target`: the VCO directly if it is in range,
+/// otherwise the channel divider that puts the VCO nearest the center of
+/// one of its cores, where phase noise and spurs are best.
+pub fn choose_divider(target: Frequency) -> Result<DividerChoice, Error> {
+    let (path, reason) = if (VCO_MIN..=VCO_MAX).contains(&target) {
+        (OutputPath::Vco, DividerReason::VcoInRange)
+    } else {
+        match best_divider(target.0) {
+            (Some(chdiv), 1) => (OutputPath::Divider(chdiv as u8), DividerReason::OnlyChoice),
+            (Some(chdiv), count) => (
+                OutputPath::Divider(chdiv as u8),
+                DividerReason::BestCentered {
+                    alternatives: (count - 1) as u8,
+                },
+            ),
+            (None, _) => return Err(Error::InvalidFrequency(target)),
+        }
+    };
+    let vco = Frequency(target.0 * path.divide_ratio() as u64);
+    let (core, core_offset) = core_offset(vco.0).ok_or(Error::VcoOutOfRange(vco))?;
+    Ok(DividerChoice {
+        path,
+        vco,
+        core,
+        core_offset,
+        reason,
+    })
+}
+
+fn output_path(target: Frequency) -> Result<OutputPath, Error> {
+    choose_divider(target).map(|choice| choice.path)
+}
+
+/// Solve for the N divider and channel divider that produce `target` with a
+/// reference of `fref` and the reference path already set in `regs`.
+///
+/// Targets in the VCO range are output straight from the VCO. The
+/// fractional part is exact when it fits a 32-bit denominator, and rounded
+/// to the nearest step of the largest denominator otherwise. A fractional
+/// result is rejected if the PFD is beyond `check_fractional_pfd`'s limits,
+/// as is one needing an N beyond 19 bits, with
+/// `Error::UnreachableWithReference` giving the nearest output reachable.
+pub fn solve(
+    fref: Frequency,
+    regs: &[u32; 113],
+    target: Frequency,
+) -> Result<FrequencyPlan, Error> {
+    solve_rounded(fref, regs, target, Rounding::Nearest)
+}
+
+/// Which way `solve_rounded` rounds a fraction that does not fit a 32-bit
+/// denominator
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum Rounding {
+    /// To the nearest step, as `solve` does
+    #[default]
+    Nearest,
+    /// Down, so the output never exceeds the target
+    Down,
+    /// Up, so the output never falls short of the target
+    Up,
+}
+
+/// `solve`, rounding an inexact fraction in the given direction
+pub fn solve_rounded(
+    fref: Frequency,
+    regs: &[u32; 113],
+    target: Frequency,
+    rounding: Rounding,
+) -> Result<FrequencyPlan, Error> {
+    solve_path(fref, regs, target, output_path(target)?, rounding)
+}
+
+/// `solve_rounded` through a given output path
+fn solve_path(
+    fref: Frequency,
+    regs: &[u32; 113],
+    target: Frequency,
+    path: OutputPath,
+    rounding: Rounding,
+) -> Result<FrequencyPlan, Error> {
+    let vco = check_vco_range(Frequency(target.0 * path.divide_ratio() as u64))?;
+
+    // vco / fPD = vco * pfd_den / (fref * pfd_num) = N + NUM / DEN
+    let (pfd_num, pfd_den) = pfd_ratio(regs);
+    let total_num = vco.0 as u128 * pfd_den;
+    let total_den = fref.0 as u128 * pfd_num;
+    if total_den == 0 {
+        return Err(Error::InvalidFrequency(target));
+    }
+    let mut n = total_num / total_den;
+    let rem = total_num % total_den;
+    let g = gcd(rem, total_den);
+    let (mut num, mut den) = (rem / g, total_den / g);
+    if den > u32::MAX as u128 {
+        let bias = match rounding {
+            Rounding::Nearest => den / 2,
+            Rounding::Down => 0,
+            Rounding::Up => den - 1,
+        };
+        num = (num * u32::MAX as u128 + bias) / den;
+        den = u32::MAX as u128;
+        if num == den {
+            n += 1;
+            num = 0;
+        }
+    }
+    if n >= 1 << 19 {
+        return Err(unreachable(fref, regs, target, path));
+    }
+    let (n, num, den) = (n as u32, num as u32, den as u32);
+    if num != 0 && check_fractional_pfd(pfd_frequency(fref, regs), regs).is_err() {
+        return Err(unreachable(fref, regs, target, path));
+    }
+    Ok(plan_for(fref, regs, path, n, num, den))
+}
+
+/// The error for a `target` that `path` cannot reach from this reference
+/// path, either because N would overflow or because it needs a fraction
+/// the PFD is too fast for.
+///
+/// It carries the nearest output an integer N reaches through `path`:
+/// exactly the nearest reachable frequency in the second case, and the top
+/// of the range to within one PFD step in the first. If no integer N puts
+/// the VCO in range either, it is a plain `Error::InvalidFrequency`.
+fn unreachable(fref: Frequency, regs: &[u32; 113], target: Frequency, path: OutputPath) -> Error {
+    let (pfd_num, pfd_den) = pfd_ratio(regs);
+    let total_num = target.0 as u128 * path.divide_ratio() as u128 * pfd_den;
+    let total_den = fref.0 as u128 * pfd_num;
+    let n = (total_num / total_den.max(1)).min((1 << 19) - 1) as u32;
+    [n, n + 1]
+        .iter()
+        .filter(|&&n| n > 0 && n < 1 << 19)
+        .map(|&n| plan_for(fref, regs, path, n, 0, 1))
+        .filter(|plan| (VCO_MIN..=VCO_MAX).contains(&plan.vco))
+        .min_by_key(|plan| plan.output.0.abs_diff(target.0))
+        .map(|plan| Error::UnreachableWithReference {
+            target,
+            nearest_achievable: plan.output,
+        })
+        .unwrap_or(Error::InvalidFrequency(target))
+}
+
+/// The plan for the given dividers, with the frequencies they reach
+fn plan_for(
+    fref: Frequency,
+    regs: &[u32; 113],
+    path: OutputPath,
+    n: u32,
+    num: u32,
+    den: u32,
+) -> FrequencyPlan {
+    let (pfd_num, pfd_den) = pfd_ratio(regs);
+    let vco = Frequency(
+        ((fref.0 as u128 * pfd_num * (n as u128 * den as u128 + num as u128))
+            / (pfd_den * den as u128)) as u64,
+    );
+    FrequencyPlan {
+        path,
+        n,
+        num,
+        den,
+        vco,
+        output: Frequency(vco.0 / path.divide_ratio() as u64),
+    }
+}
+
+/// How close to an integer boundary `solve_avoiding_boundary` lets a
+/// fractional plan get
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct BoundaryAvoidance {
+    /// Closest the VCO may sit to an integer multiple of the PFD without
+    /// being on it. The worst fractional spurs land at this offset from the
+    /// carrier, so it is usually set beyond the loop bandwidth.
+    pub min_offset: Frequency,
+    /// Furthest the output may be moved from the target to get there
+    pub tolerance: Frequency,
+}
+
+/// What `solve_avoiding_boundary` did about an integer boundary
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum BoundaryAdjustment {
+    /// The plain solution was already integer or far enough from a boundary
+    None,
+    /// Another channel divider reaches the target exactly, away from any
+    /// boundary
+    Divider {
+        /// Path the plain solution would have taken
+        from: OutputPath,
+    },
+    /// The fraction was moved onto or away from the boundary, moving the
+    /// output by this many Hz
+    Nudged {
+        /// Output reached less the target
+        offset_hz: i64,
+    },
+    /// Neither fix works within the tolerance; the plain solution is kept
+    Unavoidable,
+}
+
+/// Distance of a plan's VCO from the nearest integer multiple of the PFD;
+/// zero for an integer plan
+fn boundary_distance(plan: &FrequencyPlan, pfd: Frequency) -> Frequency {
+    let steps = plan.num.min(plan.den - plan.num) as u128;
+    Frequency((steps * pfd.0 as u128 / plan.den as u128) as u64)
+}
+
+/// `solve`, steering clear of the worst fractional spurs.
+///
+/// Fractional-N spurs are strongest when the VCO sits just off an integer
+/// multiple of the PFD, with NUM close to 0 or to DEN. When the plain
+/// solution is closer than `avoid.min_offset` to such a boundary, another
+/// channel divider reaching the target exactly is tried first, the one
+/// putting the VCO nearest the center of a core. Failing that, the fraction
+/// is nudged either onto the boundary (integer N, which has no fractional
+/// spurs) or out to `avoid.min_offset`, whichever moves the output less,
+/// provided it stays within `avoid.tolerance` of the target.
+pub fn solve_avoiding_boundary(
+    fref: Frequency,
+    regs: &[u32; 113],
+    target: Frequency,
+    avoid: &BoundaryAvoidance,
+) -> Result<(FrequencyPlan, BoundaryAdjustment), Error> {
+    let plan = solve(fref, regs, target)?;
+    let pfd = pfd_frequency(fref, regs);
+    let clear =
+        |plan: &FrequencyPlan| plan.num == 0 || boundary_distance(plan, pfd) >= avoid.min_offset;
+    if clear(&plan) {
+        return Ok((plan, BoundaryAdjustment::None));
+    }
+
+    let mut best: Option<(FrequencyPlan, u32)> = None;
+    for (chdiv, &ratio) in CHDIV_TABLE.iter().enumerate() {
+        let path = OutputPath::Divider(chdiv as u8);
+        let max = if ratio == 2 { VCO_MAX } else { VCO_MAX_DIVIDED };
+        if path == plan.path || target.0.saturating_mul(ratio as u64) > max.0 {
+            continue;
+        }
+        let other = match solve_path(fref, regs, target, path, Rounding::Nearest) {
+            Ok(other) if other.output == target && clear(&other) => other,
+            _ => continue,
+        };
+        if let Some((_, offset)) = core_offset(other.vco.0) {
+            if best.is_none_or(|(_, b)| offset < b) {
+                best = Some((other, offset));
+            }
+        }
+    }
+    if let Some((other, _)) = best {
+        defmt::info!(
+            "Moved to CHDIV {} to avoid an integer boundary",
+            other.path.encoding().chdiv
+        );
+        return Ok((other, BoundaryAdjustment::Divider { from: plan.path }));
+    }
+
+    // Numerator steps making up min_offset, rounded up
+    let den = plan.den as u128;
+    let steps = (avoid.min_offset.0 as u128 * den).div_ceil(pfd.0.max(1) as u128);
+    let (n, num) = (plan.n, plan.num as u128);
+    let candidates = if num < den - num {
+        [(n, 0), (n, steps)]
+    } else {
+        [(n + 1, 0), (n, den.saturating_sub(steps))]
+    };
+    let mut nudged: Option<(FrequencyPlan, i64)> = None;
+    for (n, num) in candidates {
+        if num >= den || (num != 0 && 2 * steps > den) || n >= 1 << 19 {
+            continue;
+        }
+        let other = plan_for(fref, regs, plan.path, n, num as u32, plan.den);
+        if check_vco_range(other.vco).is_err() {
+            continue;
+        }
+        let offset = other.output.0 as i64 - target.0 as i64;
+        if offset.unsigned_abs() <= avoid.tolerance.0
+            && nudged.is_none_or(|(_, o)| offset.unsigned_abs() < o.unsigned_abs())
+        {
+            nudged = Some((other, offset));
+        }
+    }
+    match nudged {
+        Some((other, offset_hz)) => {
+            defmt::info!("Moved {} Hz to avoid an integer boundary", offset_hz);
+            Ok((other, BoundaryAdjustment::Nudged { offset_hz }))
+        }
+        None => {
+            defmt::warn!("{} Hz is close to an integer boundary", target.0);
+            Ok((plan, BoundaryAdjustment::Unavoidable))
+        }
+    }
+}
+
+/// Write a solved plan into a register map.
+///
+/// OUTA is switched to the plan's path; OUTB follows it unless it is
+/// currently on SYSREF or high impedance. The channel divider is switched
+/// off when the plan takes the output straight from the VCO. A plan whose
+/// numerator is not below its denominator is rejected with
+/// `Error::OutOfRange` before anything is written.
+pub fn apply_plan(regs: &mut [u32; 113], plan: &FrequencyPlan) -> Result<(), Error> {
+    fields::set_frac(regs, plan.num, plan.den)?;
+    fields::set_pll_n(regs, plan.n);
+    plan.path.encoding().apply(regs);
+    let mux = match plan.path {
+        OutputPath::Vco => fields::OUT_MUX_VCO,
+        OutputPath::Divider(_) => fields::OUT_MUX_CHDIV,
+    };
+    fields::OUTA_MUX.set(regs, mux);
+    if fields::OUTB_MUX.get(regs) <= fields::OUT_MUX_VCO {
+        fields::OUTB_MUX.set(regs, mux);
+    }
+    Ok(())
+}
+
+/// `set_frequency` on `REG_MAP` at compile time, for products that only
+/// ever run at one frequency:
+///
+/// ```ignore
+/// static MAP: [u32; 113] = set_frequency_const(REF, Frequency::from_mhz(100));
+/// ```
+///
+/// Only integer-N plans are supported; a target that needs a fractional N,
+/// or cannot be reached at all, fails the build.
+pub const fn set_frequency_const(fref: Frequency, target: Frequency) -> [u32; 113] {
+    // Output path, as in `choose_divider`
+    let mut chdiv = None;
+    if target.0 < VCO_MIN.0 || target.0 > VCO_MAX.0 {
+        chdiv = best_divider(target.0).0;
+        assert!(chdiv.is_some(), "target frequency cannot be produced");
+    }
+    let vco = match chdiv {
+        Some(i) => target.0 * CHDIV_TABLE[i] as u64,
+        None => target.0,
+    };
+
+    let mut regs = REG_MAP;
+    let (pfd_num, pfd_den) = pfd_ratio(&regs);
+    let total_num = vco as u128 * pfd_den;
+    let total_den = fref.0 as u128 * pfd_num;
+    assert!(total_den != 0, "reference frequency is zero");
+    assert!(
+        total_num.is_multiple_of(total_den),
+        "target needs a fractional N divider"
+    );
+    let n = total_num / total_den;
+    assert!(n < 1 << 19, "N divider out of range");
+
+    // As in `apply_plan` for an integer plan
+    fields::set_pll_n(&mut regs, n as u32);
+    assert!(fields::set_frac(&mut regs, 0, 1).is_ok());
+    let (path, mux) = match chdiv {
+        Some(i) => (OutputPath::Divider(i as u8), fields::OUT_MUX_CHDIV),
+        None => (OutputPath::Vco, fields::OUT_MUX_VCO),
+    };
+    path.encoding().apply(&mut regs);
+    fields::OUTA_MUX.set(&mut regs, mux);
+    if fields::OUTB_MUX.get(&regs) <= fields::OUT_MUX_VCO {
+        fields::OUTB_MUX.set(&mut regs, mux);
+    }
+    regs
+}
+
+/// Solve for `target` and write the result into `regs`, which must already
+/// hold the reference path for a reference of `fref`.
+///
+/// This only touches the map in RAM; `lmx2594::change_frequency` retunes a
+/// running device, holding the outputs muted by default.
+pub fn set_frequency(
+    fref: Frequency,
+    regs: &mut [u32; 113],
+    target: Frequency,
+) -> Result<FrequencyPlan, Error> {
+    let plan = solve(fref, regs, target)?;
+    apply_plan(regs, &plan)?;
+    Ok(plan)
+}
+
+/// Set the highest frequency not above `target`, returning the frequency
+/// reached. For outputs that must stay under a hardware or regulatory
+/// limit; see `set_frequency`.
+pub fn set_frequency_floor(
+    fref: Frequency,
+    regs: &mut [u32; 113],
+    target: Frequency,
+) -> Result<Frequency, Error> {
+    let plan = solve_rounded(fref, regs, target, Rounding::Down)?;
+    apply_plan(regs, &plan)?;
+    Ok(plan.output)
+}
+
+/// Set the lowest frequency not below `target`, returning the frequency
+/// reached; see `set_frequency`
+pub fn set_frequency_ceil(
+    fref: Frequency,
+    regs: &mut [u32; 113],
+    target: Frequency,
+) -> Result<Frequency, Error> {
+    let plan = solve_rounded(fref, regs, target, Rounding::Up)?;
+    apply_plan(regs, &plan)?;
+    Ok(plan.output)
+}
+
+/// Whether the solver may use the fractional part of the N divider
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum SolveMode {
+    /// Use whatever reaches the target, leaving MASH_ORDER as it is
+    #[default]
+    Auto,
+    /// Integer N only, for the lowest spurs. Targets that need a fraction
+    /// are rejected, and the modulator is switched off.
+    Integer,
+    /// Keep the sigma-delta modulator running even when the target is an
+    /// exact integer multiple of the PFD, e.g. for its dithering
+    Fractional,
+}
+
+/// MASH order set by `SolveMode::Fractional` if the map is in integer mode
+pub const DEFAULT_MASH_ORDER: u32 = 3;
+
+/// Order of the sigma-delta modulator, MASH_ORDER
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum MashOrder {
+    /// Modulator off: N is an integer and NUM is ignored
+    Integer,
+    /// First order modulator
+    First,
+    /// Second order modulator
+    Second,
+    /// Third order modulator, `DEFAULT_MASH_ORDER`
+    Third,
+    /// Fourth order modulator, which lowers the PFD limit, see
+    /// `check_fractional_pfd`
+    Fourth,
+}
+
+impl MashOrder {
+    /// MASH_ORDER value
+    pub const fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Fraction left in the map by `apply_mash_order` in integer mode, as
+/// `(NUM, DEN)`; the same as the solver gives an integer plan
+pub const INTEGER_FRAC: (u32, u32) = (0, 1);
+
+/// Set the MASH order in a register map.
+///
+/// Switching to `MashOrder::Integer` also sets NUM and DEN to
+/// `INTEGER_FRAC`, so that a stale fraction left over from fractional mode
+/// neither shows up in `output_frequency` nor comes back into effect when
+/// the modulator is switched on again.
+pub fn apply_mash_order(regs: &mut [u32; 113], order: MashOrder) {
+    fields::MASH_ORDER.set(regs, order.code());
+    if order == MashOrder::Integer {
+        let (num, den) = INTEGER_FRAC;
+        // Always below DEN, so cannot fail
+        fields::set_frac(regs, num, den).ok();
+    }
+}
+
+/// `solve`, honouring a preference for integer or fractional mode
+pub fn solve_with_mode(
+    fref: Frequency,
+    regs: &[u32; 113],
+    target: Frequency,
+    mode: SolveMode,
+) -> Result<FrequencyPlan, Error> {
+    let plan = solve(fref, regs, target)?;
+    match mode {
+        SolveMode::Integer if plan.num != 0 => {
+            defmt::warn!("{} Hz needs fractional mode", target.0);
+            Err(Error::InvalidFrequency(target))
+        }
+        SolveMode::Fractional if plan.num == 0 => {
+            check_fractional_pfd(pfd_frequency(fref, regs), regs)?;
+            Ok(plan)
+        }
+        _ => Ok(plan),
+    }
+}
+
+/// `set_frequency`, honouring a preference for integer or fractional mode.
+/// MASH_ORDER is set to match: 0 for `SolveMode::Integer`, and
+/// `DEFAULT_MASH_ORDER` for `SolveMode::Fractional` if it was 0.
+pub fn set_frequency_with_mode(
+    fref: Frequency,
+    regs: &mut [u32; 113],
+    target: Frequency,
+    mode: SolveMode,
+) -> Result<FrequencyPlan, Error> {
+    let plan = solve_with_mode(fref, regs, target, mode)?;
+    apply_plan(regs, &plan)?;
+    match mode {
+        SolveMode::Auto => {}
+        SolveMode::Integer => fields::MASH_ORDER.set(regs, 0),
+        SolveMode::Fractional => {
+            if fields::MASH_ORDER.get(regs) == 0 {
+                fields::MASH_ORDER.set(regs, DEFAULT_MASH_ORDER);
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// The plan `set_clock_output` picked, with the settings that decide its
+/// jitter
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct ClockPlan {
+    /// The dividers chosen
+    pub plan: FrequencyPlan,
+    /// Ratio between the VCO and output frequencies, 1 straight from the VCO
+    pub divide_ratio: u32,
+    /// Whether N is an integer, so the modulator is off and there are no
+    /// fractional spurs
+    pub integer: bool,
+    /// Index into `VCO_CORES` of the core covering the VCO
+    pub core: usize,
+    /// Distance of the VCO from the center of that core, as for
+    /// `DividerChoice::core_offset`
+    pub core_offset: u32,
+}
+
+/// Solve for `target` as a clock rather than an RF carrier.
+///
+/// `solve` takes the VCO directly whenever the target is in range and
+/// otherwise the divider nearest the center of a core, whatever the
+/// fraction comes out as. For a clock, spurs and jitter matter more than
+/// the VCO's position, so every path reaching `target` exactly is tried and
+/// the best kept: an integer N first, then a path through the channel
+/// divider, then the VCO nearest the center of a core. If no path reaches
+/// `target` exactly, this is the same as `solve`.
+pub fn solve_clock(
+    fref: Frequency,
+    regs: &[u32; 113],
+    target: Frequency,
+) -> Result<ClockPlan, Error> {
+    let fallback = solve(fref, regs, target);
+    let paths = core::iter::once(OutputPath::Vco)
+        .chain((0..CHDIV_TABLE.len()).map(|chdiv| OutputPath::Divider(chdiv as u8)));
+    let mut best: Option<(FrequencyPlan, (bool, bool, u32))> = None;
+    for path in paths {
+        let ratio = path.divide_ratio();
+        let max = if ratio <= 2 { VCO_MAX } else { VCO_MAX_DIVIDED };
+        if target.0.saturating_mul(ratio as u64) > max.0 {
+            continue;
+        }
+        let plan = match solve_path(fref, regs, target, path, Rounding::Nearest) {
+            Ok(plan) if plan.output == target => plan,
+            _ => continue,
+        };
+        if let Some((_, offset)) = core_offset(plan.vco.0) {
+            let rank = (plan.num != 0, path == OutputPath::Vco, offset);
+            if best.is_none_or(|(_, b)| rank < b) {
+                best = Some((plan, rank));
+            }
+        }
+    }
+    let plan = match best {
+        Some((plan, _)) => plan,
+        None => fallback?,
+    };
+    let (core, core_offset) = core_offset(plan.vco.0).ok_or(Error::VcoOutOfRange(plan.vco))?;
+    Ok(ClockPlan {
+        plan,
+        divide_ratio: plan.path.divide_ratio(),
+        integer: plan.num == 0,
+        core,
+        core_offset,
+    })
+}
+
+/// `set_frequency` for a clock output, see `solve_clock`. When the plan is
+/// integer the modulator is switched off as well, as for
+/// `SolveMode::Integer`.
+pub fn set_clock_output(
+    fref: Frequency,
+    regs: &mut [u32; 113],
+    target: Frequency,
+) -> Result<ClockPlan, Error> {
+    let clock = solve_clock(fref, regs, target)?;
+    apply_plan(regs, &clock.plan)?;
+    if clock.integer {
+        apply_mash_order(regs, MashOrder::Integer);
+    }
+    defmt::info!(
+        "Clock of {} Hz: divide by {}, {} N",
+        clock.plan.output.0,
+        clock.divide_ratio,
+        if clock.integer {
+            "integer"
+        } else {
+            "fractional"
+        }
+    );
+    Ok(clock)
+}
+
+/// Set OUTA and OUTB to `outa` and `outb` respectively.
+///
+/// Both outputs share the one VCO and the one channel divider; each output
+/// can only choose between the two. So they can either be equal, or one of
+/// them must be a VCO frequency and the other that frequency divided by a
+/// channel divider value. Anything else is rejected with
+/// `Error::IncompatibleOutputs`.
+pub fn set_output_frequencies(
+    fref: Frequency,
+    regs: &mut [u32; 113],
+    outa: Frequency,
+    outb: Frequency,
+) -> Result<FrequencyPlan, Error> {
+    if outa == outb {
+        let plan = solve(fref, regs, outa)?;
+        apply_plan(regs, &plan)?;
+        fields::OUTB_MUX.set(regs, fields::OUTA_MUX.get(regs));
+        return Ok(plan);
+    }
+
+    let incompatible = Error::IncompatibleOutputs(outa, outb);
+    let (vco, divided) = if outa > outb {
+        (outa, outb)
+    } else {
+        (outb, outa)
+    };
+    if divided.0 == 0 || !vco.0.is_multiple_of(divided.0) {
+        return Err(incompatible);
+    }
+    let ratio = vco.0 / divided.0;
+    let chdiv = match ChDivEncoding::from_ratio(ratio.min(u32::MAX as u64) as u32) {
+        Some(chdiv) if chdiv.div2 => chdiv,
+        _ => return Err(incompatible),
+    };
+    let max = if ratio == 2 { VCO_MAX } else { VCO_MAX_DIVIDED };
+    if !(VCO_MIN..=max).contains(&vco) {
+        return Err(incompatible);
+    }
+
+    let plan = solve(fref, regs, vco)?;
+    apply_plan(regs, &plan)?;
+    chdiv.apply(regs);
+    let (outa_mux, outb_mux) = if outa > outb {
+        (fields::OUT_MUX_VCO, fields::OUT_MUX_CHDIV)
+    } else {
+        (fields::OUT_MUX_CHDIV, fields::OUT_MUX_VCO)
+    };
+    fields::OUTA_MUX.set(regs, outa_mux);
+    fields::OUTB_MUX.set(regs, outb_mux);
+    Ok(plan)
+}
+
+/// One of the seven VCO cores, with its datasheet frequency and Kvco range
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct VcoCore {
+    pub min: Frequency,
+    pub max: Frequency,
+    /// Kvco at the bottom of the core's range, in MHz/V
+    pub kvco_min: u32,
+    /// Kvco at the top of the core's range, in MHz/V
+    pub kvco_max: u32,
+}
+
+/// The VCO cores, VCO1 first
+pub static VCO_CORES: [VcoCore; 7] = [
+    VcoCore {
+        min: Frequency::from_mhz(7_500),
+        max: Frequency::from_mhz(8_600),
+        kvco_min: 73,
+        kvco_max: 114,
+    },
+    VcoCore {
+        min: Frequency::from_mhz(8_600),
+        max: Frequency::from_mhz(9_800),
+        kvco_min: 61,
+        kvco_max: 121,
+    },
+    VcoCore {
+        min: Frequency::from_mhz(9_800),
+        max: Frequency::from_mhz(10_800),
+        kvco_min: 98,
+        kvco_max: 132,
+    },
+    VcoCore {
+        min: Frequency::from_mhz(10_800),
+        max: Frequency::from_mhz(12_000),
+        kvco_min: 106,
+        kvco_max: 141,
+    },
+    VcoCore {
+        min: Frequency::from_mhz(12_000),
+        max: Frequency::from_mhz(12_900),
+        kvco_min: 170,
+        kvco_max: 215,
+    },
+    VcoCore {
+        min: Frequency::from_mhz(12_900),
+        max: Frequency::from_mhz(13_900),
+        kvco_min: 172,
+        kvco_max: 218,
+    },
+    VcoCore {
+        min: Frequency::from_mhz(13_900),
+        max: Frequency::from_mhz(15_000),
+        kvco_min: 182,
+        kvco_max: 239,
+    },
+];
+
+/// Index into `VCO_CORES` of the core covering `vco`, if any
+pub fn vco_core(vco: Frequency) -> Option<usize> {
+    VCO_CORES
+        .iter()
+        .position(|c| (c.min..=c.max).contains(&vco))
+}
+
+/// Kvco at `vco` in MHz/V, interpolated linearly across its core's range
+pub fn kvco_estimate(vco: Frequency) -> Option<u32> {
+    let core = &VCO_CORES[vco_core(vco)?];
+    let span = core.max.0 - core.min.0;
+    let pos = vco.0 - core.min.0;
+    let slope = (core.kvco_max - core.kvco_min) as u64;
+    Some(core.kvco_min + (slope * pos / span) as u32)
+}
+
+/// Charge pump current in uA selected by a CPG value
+pub fn charge_pump_ua(cpg: u32) -> u32 {
+    // CPG[0] and CPG[1] each add 6 mA, CPG[2] adds 3 mA
+    (cpg & 1) * 6_000 + ((cpg >> 1) & 1) * 6_000 + ((cpg >> 2) & 1) * 3_000
+}
+
+/// What an external loop filter design needs to know about the PLL
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct LoopParameters {
+    /// Phase detector frequency
+    pub pfd: Frequency,
+    /// Charge pump current in uA
+    pub charge_pump_ua: u32,
+    /// Integer part of the N divider
+    pub n: u32,
+    /// Fractional numerator
+    pub num: u32,
+    /// Fractional denominator
+    pub den: u32,
+    /// VCO frequency
+    pub vco: Frequency,
+    /// Number of the VCO core covering `vco` (1-7), 0 if out of range
+    pub vco_core: u8,
+    /// Kvco estimate in MHz/V, 0 if the VCO is out of range
+    pub kvco_mhz_per_v: u32,
+}
+
+/// Collect the loop filter design parameters for a reference of `fref`
+pub fn loop_parameters(fref: Frequency, regs: &[u32; 113]) -> LoopParameters {
+    let vco = vco_frequency_unchecked(fref, regs);
+    LoopParameters {
+        pfd: pfd_frequency(fref, regs),
+        charge_pump_ua: charge_pump_ua(fields::CPG.get(regs)),
+        n: fields::pll_n(regs),
+        num: fields::pll_num(regs),
+        den: fields::pll_den(regs),
+        vco,
+        vco_core: vco_core(vco).map_or(0, |i| i as u8 + 1),
+        kvco_mhz_per_v: kvco_estimate(vco).unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::REG_MAP;
+
+    /// `REG_MAP` with a 100 MHz PFD from a 100 MHz reference
+    fn map_100mhz_pfd() -> [u32; 113] {
+        let mut regs = REG_MAP;
+        RefPath {
+            doubler: false,
+            mult: 1,
+            r_pre: 1,
+            r: 1,
+        }
+        .apply(&mut regs);
+        regs
+    }
+
+    #[test]
+    fn frequency_units_convert_to_hz() {
+        assert_eq!(Frequency::from_hz(2_500_000_000).as_hz(), 2_500_000_000);
+        assert_eq!(
+            Frequency::from_khz(2_500_000),
+            Frequency::from_hz(2_500_000_000)
+        );
+        assert_eq!(
+            Frequency::from_mhz(2_500),
+            Frequency::from_hz(2_500_000_000)
+        );
+        assert_eq!(Frequency::from_ghz(15), VCO_MAX);
+        assert!(Frequency::from_mhz(1) < Frequency::from_ghz(1));
+        assert_eq!(Frequency::from_mhz(100).to_string(), "100000000 Hz");
+        assert_eq!(
+            core::mem::size_of::<Frequency>(),
+            core::mem::size_of::<u64>()
+        );
+
+        // The solver takes and reports the wrapper
+        let fref = Frequency::from_mhz(100);
+        let mut regs = map_100mhz_pfd();
+        let plan = set_frequency(fref, &mut regs, Frequency::from_ghz(10)).unwrap();
+        assert_eq!(plan.output, Frequency::from_mhz(10_000));
+        assert_eq!(plan.vco.as_hz(), 10_000_000_000);
+    }
+
+    #[test]
+    fn vco_frequency_rejects_n_outside_the_vco_range() {
+        let fref = Frequency::from_mhz(100);
+        let mut regs = map_100mhz_pfd();
+        fields::set_frac(&mut regs, 0, 1).unwrap();
+        for (n, in_range) in [
+            (70, false),
+            (74, false),
+            (75, true),
+            (150, true),
+            (151, false),
+        ] {
+            fields::set_pll_n(&mut regs, n);
+            let vco = Frequency::from_mhz(100 * n as u64);
+            assert_eq!(vco_frequency_unchecked(fref, &regs), vco);
+            let expected = if in_range {
+                Ok(vco)
+            } else {
+                Err(Error::VcoOutOfRange(vco))
+            };
+            assert_eq!(vco_frequency(fref, &regs), expected, "N = {}", n);
+        }
+        assert_eq!(check_vco_range(VCO_MIN), Ok(VCO_MIN));
+        assert_eq!(check_vco_range(VCO_MAX), Ok(VCO_MAX));
+        let below = Frequency(VCO_MIN.0 - 1);
+        assert_eq!(check_vco_range(below), Err(Error::VcoOutOfRange(below)));
+    }
+
+    #[test]
+    fn set_pfd_writes_a_path_reaching_the_target() {
+        let fref = Frequency::from_mhz(100);
+        // The multiplier gets to 200 MHz from 50 MHz before the doubler is
+        // tried
+        let cases = [
+            (50, (false, 1, 1, 2)),
+            (100, (false, 1, 1, 1)),
+            (200, (false, 4, 2, 1)),
+            (1, (false, 1, 1, 100)),
+        ];
+        for (mhz, expected) in cases {
+            let target = Frequency::from_mhz(mhz);
+            let mut regs = map_100mhz_pfd();
+            let path = set_pfd(fref, &mut regs, target).unwrap();
+            assert_eq!((path.doubler, path.mult, path.r_pre, path.r), expected);
+            assert_eq!(RefPath::from_regs(&regs), path);
+            assert_eq!(pfd_frequency(fref, &regs), target, "{} MHz", mhz);
+        }
+
+        // Out of range, not an exact ratio, or too fast for a fractional N:
+        // rejected, leaving the map alone
+        let mut regs = map_100mhz_pfd();
+        fields::set_frac(&mut regs, 1, 3).unwrap();
+        let before = regs;
+        for target in [
+            Frequency::from_mhz(450),
+            Frequency::from_hz(33_333_333),
+            Frequency::from_mhz(400),
+        ] {
+            assert!(set_pfd(fref, &mut regs, target).is_err(), "{}", target);
+            assert_eq!(regs, before);
+        }
+    }
+
+    #[test]
+    fn loop_parameters_come_from_the_map() {
+        let fref = Frequency::from_mhz(100);
+        let mut regs = map_100mhz_pfd();
+        set_frequency(fref, &mut regs, Frequency::from_mhz(10_000)).unwrap();
+        // VCO3 covers 9.8-10.8 GHz at 98-132 MHz/V
+        let expected = LoopParameters {
+            pfd: fref,
+            charge_pump_ua: 15_000,
+            n: 100,
+            num: 0,
+            den: fields::pll_den(&regs),
+            vco: Frequency::from_mhz(10_000),
+            vco_core: 3,
+            kvco_mhz_per_v: 98 + 34 * 200 / 1_000,
+        };
+        assert_eq!(loop_parameters(fref, &regs), expected);
+
+        fields::CPG.set(&mut regs, 4);
+        fields::set_pll_n(&mut regs, 200);
+        let params = loop_parameters(fref, &regs);
+        assert_eq!(params.charge_pump_ua, 3_000);
+        assert_eq!((params.vco_core, params.kvco_mhz_per_v), (0, 0));
+    }
+
+    #[test]
+    fn fractional_plans_respect_the_lower_pfd_limit() {
+        let mut regs = map_100mhz_pfd();
+        apply_mash_order(&mut regs, MashOrder::Third);
+        let too_fast = Frequency::from_mhz(400);
+        assert_eq!(check_fractional_pfd(PFD_MAX_FRAC, &regs), Ok(()));
+        assert_eq!(
+            check_fractional_pfd(too_fast, &regs),
+            Err(Error::InvalidFrequency(too_fast))
+        );
+        // The fourth order modulator is slower still
+        apply_mash_order(&mut regs, MashOrder::Fourth);
+        assert_eq!(
+            check_fractional_pfd(PFD_MAX_FRAC, &regs),
+            Err(Error::InvalidFrequency(PFD_MAX_FRAC))
+        );
+        apply_mash_order(&mut regs, MashOrder::Third);
+
+        // A 400 MHz PFD is fine for integer N, but not for a fraction
+        let fref = too_fast;
+        let integer = Frequency::from_mhz(10_000);
+        assert_eq!(solve(fref, &regs, integer).unwrap().num, 0);
+        let fractional = Frequency::from_mhz(10_001);
+        assert!(matches!(
+            solve(fref, &regs, fractional),
+            Err(Error::UnreachableWithReference { .. })
+        ));
+        assert_eq!(
+            solve_with_mode(fref, &regs, integer, SolveMode::Fractional),
+            Err(Error::InvalidFrequency(too_fast))
+        );
+        // At 100 MHz the same fraction is allowed
+        assert_ne!(
+            solve(Frequency::from_mhz(100), &regs, fractional)
+                .unwrap()
+                .num,
+            0
+        );
+    }
+
+    #[test]
+    fn suggested_references_reach_the_target_in_integer_mode() {
+        // 10 GHz from the VCO at a 100 MHz PFD: a 100 MHz reference, direct
+        let suggestion = suggest_reference(Frequency::from_mhz(10_000), Frequency::from_mhz(100));
+        let suggestion = suggestion.unwrap();
+        assert_eq!(suggestion.reference, Frequency::from_mhz(100));
+        assert_eq!(suggestion.pfd, Frequency::from_mhz(100));
+        assert_eq!((suggestion.n, suggestion.output), (100, OutputPath::Vco));
+        assert_eq!(suggestion.path.pfd(suggestion.reference), suggestion.pfd);
+
+        // N = 4000 gives 2.5 MHz, below OSCin's range: the smallest
+        // reference that divides down to it is twice that
+        let suggestion = suggest_reference(Frequency::from_mhz(10_000), Frequency::from_mhz(3));
+        let suggestion = suggestion.unwrap();
+        assert_eq!(
+            (suggestion.n, suggestion.pfd),
+            (4_000, Frequency::from_khz(2_500))
+        );
+        assert_eq!(suggestion.reference, OSC_IN_MIN);
+        assert_eq!((suggestion.path.r_pre, suggestion.path.r), (1, 2));
+
+        // A divided target: the PFD and N still give its VCO frequency
+        let target = Frequency::from_mhz(2_500);
+        let suggestion = suggest_reference(target, Frequency::from_mhz(100)).unwrap();
+        let vco = suggestion.pfd.as_hz() * suggestion.n as u64;
+        assert_eq!(
+            vco,
+            target.as_hz() * suggestion.output.divide_ratio() as u64
+        );
+        assert_eq!(suggestion.path.pfd(suggestion.reference), suggestion.pfd);
+
+        let too_slow = Frequency::from_khz(100);
+        assert_eq!(
+            suggest_reference(target, too_slow),
+            Err(Error::InvalidFrequency(too_slow))
+        );
+    }
+
+    #[test]
+    fn ref_path_strategies_pick_their_own_path() {
+        let fref = Frequency::from_mhz(50);
+        let max_pfd = Frequency::from_mhz(250);
+        let solve = |strategy| {
+            let path = solve_ref_path_with(fref, max_pfd, &RefPathConfig { strategy }).unwrap();
+            (path, path.pfd(fref))
+        };
+        let path = |doubler, mult| RefPath {
+            doubler,
+            mult,
+            r_pre: 1,
+            r: 1,
+        };
+
+        // Only the multiplier reaches 250 MHz; without it the doubler gives
+        // the most, and the plain reference the least
+        assert_eq!(
+            solve(RefPathStrategy::MaxPfd),
+            (path(false, 5), Frequency::from_mhz(250))
+        );
+        assert_eq!(
+            solve(RefPathStrategy::LowestSpur),
+            (path(true, 1), Frequency::from_mhz(100))
+        );
+        assert_eq!(
+            solve(RefPathStrategy::MinMultiplier),
+            (path(false, 1), Frequency::from_mhz(50))
+        );
+        assert_eq!(RefPathConfig::default().strategy, RefPathStrategy::MaxPfd);
+
+        // Below the reference, doubling first lands exactly on 20 MHz,
+        // while dividing alone falls short
+        let below = |strategy| {
+            let config = RefPathConfig { strategy };
+            let path = solve_ref_path_with(fref, Frequency::from_mhz(20), &config).unwrap();
+            (path.doubler, path.r, path.pfd(fref))
+        };
+        assert_eq!(
+            below(RefPathStrategy::MaxPfd),
+            (true, 5, Frequency::from_mhz(20))
+        );
+        assert_eq!(
+            below(RefPathStrategy::MinMultiplier),
+            (false, 3, Frequency::from_hz(16_666_666))
+        );
+    }
+
+    #[test]
+    fn largest_n_and_fraction_do_not_overflow() {
+        // The fastest reference path and the largest N, NUM and DEN: the
+        // VCO works out to about 1e16 Hz, far beyond the u32 range
+        let fref = Frequency::from_hz(1_400_000_000);
+        let mut regs = REG_MAP;
+        RefPath {
+            doubler: true,
+            mult: 7,
+            r_pre: 1,
+            r: 1,
+        }
+        .apply(&mut regs);
+        fields::set_pll_n(&mut regs, (1 << 19) - 1);
+        fields::set_frac(&mut regs, u32::MAX - 1, u32::MAX).unwrap();
+        assert_eq!(fields::pll_n(&regs), (1 << 19) - 1);
+        fields::OUTA_MUX.set(&mut regs, fields::OUT_MUX_VCO);
+
+        // 19.6 GHz * (524287 + (2^32 - 2) / (2^32 - 1)), rounded down
+        assert_eq!(
+            vco_frequency_unchecked(fref, &regs),
+            Frequency::from_hz(10_276_044_799_999_995)
+        );
+        let resolution = frequency_resolution_hz(fref, &regs).unwrap();
+        assert_eq!(resolution.as_uhz(), 4_563_481);
+        let params = loop_parameters(fref, &regs);
+        assert_eq!((params.num, params.den), (u32::MAX - 1, u32::MAX));
+    }
+
+    #[test]
+    fn chdiv_encoding_round_trips_every_ratio() {
+        for (code, &ratio) in CHDIV_VALUES.iter().enumerate() {
+            let encoding = ChDivEncoding::from_ratio(ratio).unwrap();
+            assert_eq!(
+                encoding,
+                ChDivEncoding {
+                    chdiv: code as u8,
+                    div2: true
+                }
+            );
+            let mut regs = REG_MAP;
+            encoding.apply(&mut regs);
+            assert_eq!((regs[75] >> 6) & 0x1f, code as u32);
+            assert_eq!(regs[31] & 1 << 14, 1 << 14);
+            assert_eq!(ChDivEncoding::from_regs(&regs), encoding);
+            assert_eq!(ChDivEncoding::from_regs(&regs).ratio(), Some(ratio));
+            assert_eq!(OutputPath::Divider(code as u8).encoding(), encoding);
+        }
+
+        let mut regs = REG_MAP;
+        ChDivEncoding::from_ratio(768).unwrap().apply(&mut regs);
+        ChDivEncoding::from_ratio(1).unwrap().apply(&mut regs);
+        assert_eq!(regs[31] & 1 << 14, 0);
+        assert_eq!(ChDivEncoding::from_regs(&regs).ratio(), Some(1));
+        assert_eq!(OutputPath::Vco.encoding(), ChDivEncoding::BYPASS);
+
+        for ratio in [0, 3, 5, 10, 1024] {
+            assert_eq!(ChDivEncoding::from_ratio(ratio), None);
+        }
+        let invalid = ChDivEncoding {
+            chdiv: 18,
+            div2: true,
+        };
+        assert_eq!(invalid.ratio(), None);
+    }
+
+    #[test]
+    fn reference_sources_double_in_the_right_place() {
+        let fosc = Frequency::from_mhz(50);
+        let cases = [
+            (ReferenceSource::Direct, 50, 0),
+            (ReferenceSource::InternalDoubler, 50, 1),
+            (ReferenceSource::ExternalDoubled, 100, 0),
+        ];
+        for (source, osc_in, osc_2x) in cases {
+            let mut regs = map_100mhz_pfd();
+            // PLL_R = 2, so each doubling shows up in the PFD
+            fields::PLL_R.set(&mut regs, 2);
+            source.apply(&mut regs);
+            assert_eq!(source.check(fosc), Ok(()), "{:?}", source);
+            assert_eq!(fields::OSC_2X.get(&regs), osc_2x, "{:?}", source);
+            assert_eq!(source.osc_in(fosc), Frequency::from_mhz(osc_in));
+            let pfd = source.pfd(fosc, &regs);
+            let expected = match source {
+                ReferenceSource::Direct => 25,
+                _ => 50,
+            };
+            assert_eq!(pfd, Frequency::from_mhz(expected), "{:?}", source);
+            // The same as the register map alone gives from OSCin
+            assert_eq!(pfd_frequency(source.osc_in(fosc), &regs), pfd);
+        }
+
+        // Only the internal doubler is limited to 200 MHz
+        let fosc = Frequency::from_mhz(250);
+        assert_eq!(ReferenceSource::Direct.check(fosc), Ok(()));
+        assert_eq!(ReferenceSource::ExternalDoubled.check(fosc), Ok(()));
+        assert_eq!(
+            ReferenceSource::InternalDoubler.check(fosc),
+            Err(Error::InvalidFrequency(fosc))
+        );
+        assert_eq!(
+            ReferenceSource::InternalDoubler.check(DOUBLER_MAX_INPUT),
+            Ok(())
+        );
+        // Doubling on the board brings a slow oscillator into OSCin's range
+        let fosc = Frequency::from_mhz(4);
+        assert!(ReferenceSource::Direct.check(fosc).is_err());
+        assert_eq!(ReferenceSource::ExternalDoubled.check(fosc), Ok(()));
+    }
+
+    #[test]
+    fn outputs_share_the_vco_or_are_rejected() {
+        let fref = Frequency::from_mhz(100);
+        let (vco, divided) = (Frequency::from_mhz(10_000), Frequency::from_mhz(2_500));
+
+        // OUTB a divided copy of OUTA, and the other way round
+        for (outa, outb) in [(vco, divided), (divided, vco)] {
+            let mut regs = map_100mhz_pfd();
+            set_output_frequencies(fref, &mut regs, outa, outb).unwrap();
+            assert_eq!(channel_frequency(fref, &regs, Output::A), Some(outa));
+            assert_eq!(channel_frequency(fref, &regs, Output::B), Some(outb));
+        }
+        let mut regs = map_100mhz_pfd();
+        set_output_frequencies(fref, &mut regs, divided, divided).unwrap();
+        assert_eq!(channel_frequency(fref, &regs, Output::B), Some(divided));
+
+        // Not a divider ratio; a ratio, but 3 is not a CHDIV value; a
+        // divider ratio, but from a VCO above the divided limit
+        let rejected = [
+            (Frequency::from_mhz(10_000), Frequency::from_mhz(3_000)),
+            (Frequency::from_mhz(9_000), Frequency::from_mhz(3_000)),
+            (Frequency::from_mhz(12_000), Frequency::from_mhz(3_000)),
+        ];
+        for (outa, outb) in rejected {
+            let mut regs = map_100mhz_pfd();
+            let before = regs;
+            assert_eq!(
+                set_output_frequencies(fref, &mut regs, outa, outb),
+                Err(Error::IncompatibleOutputs(outa, outb))
+            );
+            assert_eq!(regs, before);
+        }
+    }
+
+    #[test]
+    fn ten_ghz_bypasses_the_channel_divider() {
+        let fref = Frequency::from_mhz(100);
+        let mut regs = map_100mhz_pfd();
+        // `REG_MAP` comes out of the divider
+        assert_ne!(fields::CHDIV_DIV2.get(&regs), 0);
+        assert_eq!(fields::OUTA_MUX.get(&regs), fields::OUT_MUX_CHDIV);
+
+        let target = Frequency::from_mhz(10_000);
+        let plan = set_frequency(fref, &mut regs, target).unwrap();
+        assert_eq!(plan.path, OutputPath::Vco);
+        assert_eq!((plan.n, plan.num), (100, 0));
+        assert_eq!(fields::OUTA_MUX.get(&regs), fields::OUT_MUX_VCO);
+        assert_eq!(fields::CHDIV_DIV2.get(&regs), 0);
+        assert_eq!(ChDivEncoding::from_regs(&regs).ratio(), Some(1));
+        assert_eq!(output_frequency(fref, &regs), Some(target));
+    }
+
+    #[test]
+    fn floor_and_ceil_round_an_inexact_fraction_apart() {
+        // A 1.4 GHz reference through PLL_R_PRE = 25 and MULT = 4 needs a
+        // denominator of about 5.6e9 for an exact fraction, too big for DEN
+        let fref = Frequency::from_hz(1_399_999_997);
+        let mut regs = REG_MAP;
+        RefPath {
+            doubler: false,
+            mult: 4,
+            r_pre: 25,
+            r: 1,
+        }
+        .apply(&mut regs);
+        let target = Frequency::from_hz(10_000_000_001);
+        let exact = |plan: &FrequencyPlan| {
+            let (num, den) = pfd_ratio(&regs);
+            let step = plan.n as u128 * plan.den as u128 + plan.num as u128;
+            // VCO frequency in Hz, as a numerator and denominator
+            (fref.0 as u128 * num * step, den * plan.den as u128)
+        };
+        let scaled = |den: u128| target.0 as u128 * den;
+
+        let down = solve_rounded(fref, &regs, target, Rounding::Down).unwrap();
+        let up = solve_rounded(fref, &regs, target, Rounding::Up).unwrap();
+        assert_eq!((down.n, down.den), (up.n, up.den));
+        assert_eq!(up.num, down.num + 1);
+        let (below, den) = exact(&down);
+        assert!(below < scaled(den));
+        let (above, den) = exact(&up);
+        assert!(above > scaled(den));
+
+        let mut floor_regs = regs;
+        let floor = set_frequency_floor(fref, &mut floor_regs, target).unwrap();
+        let mut ceil_regs = regs;
+        let ceil = set_frequency_ceil(fref, &mut ceil_regs, target).unwrap();
+        assert!(floor <= target && target <= ceil);
+        assert_eq!(fields::pll_num(&floor_regs), down.num);
+        assert_eq!(fields::pll_num(&ceil_regs), up.num);
+    }
+
+    #[test]
+    fn each_channel_follows_its_own_mux() {
+        let fref = Frequency::from_mhz(100);
+        let mut regs = map_100mhz_pfd();
+        let (vco, divided) = (Frequency::from_mhz(10_000), Frequency::from_mhz(2_500));
+        set_output_frequencies(fref, &mut regs, vco, divided).unwrap();
+        assert_eq!(channel_frequency(fref, &regs, Output::A), Some(vco));
+        assert_eq!(channel_frequency(fref, &regs, Output::B), Some(divided));
+
+        set_output_frequencies(fref, &mut regs, divided, vco).unwrap();
+        assert_eq!(channel_frequency(fref, &regs, Output::A), Some(divided));
+        assert_eq!(channel_frequency(fref, &regs, Output::B), Some(vco));
+        assert_eq!(output_frequency(fref, &regs), Some(divided));
+
+        fields::OUTB_MUX.set(&mut regs, fields::OUT_MUX_SYSREF);
+        assert_eq!(channel_frequency(fref, &regs, Output::B), None);
+    }
+
+    #[test]
+    fn resolution_is_one_num_lsb_through_the_divider() {
+        let fref = Frequency::from_mhz(100);
+        let mut regs = map_100mhz_pfd();
+        fields::set_pll_den(&mut regs, 1000).unwrap();
+        fields::OUTA_MUX.set(&mut regs, fields::OUT_MUX_CHDIV);
+        fields::CHDIV.set(&mut regs, 1);
+        let step = frequency_resolution_hz(fref, &regs).unwrap();
+        assert_eq!((step.num, step.den), (25_000, 1));
+
+        fields::set_pll_den(&mut regs, 3).unwrap();
+        fields::OUTA_MUX.set(&mut regs, fields::OUT_MUX_VCO);
+        let step = frequency_resolution_hz(fref, &regs).unwrap();
+        assert_eq!((step.num, step.den), (100_000_000, 3));
+        assert_eq!(step.as_uhz(), 33_333_333_333_333);
+    }
+
+    #[test]
+    fn resolution_needs_an_active_output() {
+        let mut regs = map_100mhz_pfd();
+        fields::OUTA_MUX.set(&mut regs, fields::OUT_MUX_HIGH_Z);
+        assert_eq!(
+            frequency_resolution_hz(Frequency::from_mhz(100), &regs),
+            None
+        );
+    }
+
+    #[test]
+    fn divider_keeps_the_vco_centered() {
+        // 1.9 GHz is 7.6 GHz through /4, at the bottom edge of VCO1, or
+        // 11.4 GHz through /6, near the middle of VCO4
+        let choice = choose_divider(Frequency::from_mhz(1_900)).unwrap();
+        assert_eq!(choice.path, OutputPath::Divider(2));
+        assert_eq!(choice.vco, Frequency::from_mhz(11_400));
+        assert_eq!(choice.core, 3);
+        assert_eq!(
+            choice.reason,
+            DividerReason::BestCentered { alternatives: 1 }
+        );
+
+        let choice = choose_divider(Frequency::from_mhz(10_000)).unwrap();
+        assert_eq!(choice.reason, DividerReason::VcoInRange);
+    }
+
+    #[test]
+    fn near_boundary_targets_are_moved_clear() {
+        let fref = Frequency::from_mhz(100);
+        let regs = map_100mhz_pfd();
+        let avoid = BoundaryAvoidance {
+            min_offset: Frequency::from_mhz(1),
+            tolerance: Frequency::from_khz(1),
+        };
+
+        // 100 Hz above N = 100 on the VCO: snapped onto the boundary
+        let target = Frequency::from_hz(10_000_000_100);
+        let (plan, adjustment) = solve_avoiding_boundary(fref, &regs, target, &avoid).unwrap();
+        assert_eq!(adjustment, BoundaryAdjustment::Nudged { offset_hz: -100 });
+        assert_eq!((plan.n, plan.num), (100, 0));
+        assert_eq!(plan.output, Frequency::from_ghz(10));
+
+        // Out of tolerance, the plain solution is kept and reported
+        let strict = BoundaryAvoidance {
+            tolerance: Frequency::from_hz(10),
+            ..avoid
+        };
+        let (plan, adjustment) = solve_avoiding_boundary(fref, &regs, target, &strict).unwrap();
+        assert_eq!(adjustment, BoundaryAdjustment::Unavoidable);
+        assert_eq!(plan, solve(fref, &regs, target).unwrap());
+
+        // Just below a boundary, pushed down to exactly the minimum offset
+        // when snapping up would move further
+        let loose = BoundaryAvoidance {
+            min_offset: Frequency::from_hz(500),
+            tolerance: Frequency::from_khz(1),
+        };
+        let target = Frequency::from_hz(9_999_999_700);
+        let (plan, adjustment) = solve_avoiding_boundary(fref, &regs, target, &loose).unwrap();
+        assert_eq!(adjustment, BoundaryAdjustment::Nudged { offset_hz: -200 });
+        assert_eq!(plan.output, Frequency::from_hz(9_999_999_500));
+
+        // Already clear of any boundary
+        let target = Frequency::from_hz(10_050_000_000);
+        let (_, adjustment) = solve_avoiding_boundary(fref, &regs, target, &avoid).unwrap();
+        assert_eq!(adjustment, BoundaryAdjustment::None);
+
+        // 80 Hz off a boundary through CHDIV 8, but clear of one through
+        // CHDIV 6, which reaches the target exactly
+        let target = Frequency::from_hz(1_262_500_010);
+        let plain = solve(fref, &regs, target).unwrap();
+        let (plan, adjustment) = solve_avoiding_boundary(fref, &regs, target, &avoid).unwrap();
+        assert_eq!(plain.path.divide_ratio(), 8);
+        assert_eq!(adjustment, BoundaryAdjustment::Divider { from: plain.path });
+        assert_eq!((plan.path.divide_ratio(), plan.output), (6, target));
+    }
+
+    #[test]
+    fn ratios_approximate_to_the_known_best_fractions() {
+        // pi to 16 digits, under caps either side of 113
+        let (pi, scale) = (3_141_592_653_589_793, 1_000_000_000_000_000);
+        assert_eq!(approximate_ratio(pi, scale, 112), (333, 106));
+        assert_eq!(approximate_ratio(pi, scale, 1_000), (355, 113));
+        assert_eq!(approximate_ratio(pi, scale, 1), (3, 1));
+        // Consecutive Fibonacci numbers converge on the golden ratio
+        assert_eq!(approximate_ratio(1_346_269, 832_040, 100), (144, 89));
+        // A semiconvergent beats the last convergent: 1/3 is not allowed,
+        // and 1/2 is closer than 0/1
+        assert_eq!(approximate_ratio(1, 3, 2), (1, 2));
+        // Exact fractions come back in lowest terms
+        assert_eq!(approximate_ratio(6, 8, 100), (3, 4));
+        assert_eq!(approximate_ratio(0, 7, 100), (0, 1));
+
+        // No overflow at the extremes of u64
+        let max = u64::MAX;
+        assert_eq!(approximate_ratio(max - 1, max, max), (max - 1, max));
+        assert_eq!(approximate_ratio(max - 1, max, 1_000), (1, 1));
+        assert_eq!(approximate_ratio(1, max, 1_000), (0, 1));
+        assert_eq!(approximate_ratio(max, 1, max), (max, 1));
+        assert_eq!(approximate_ratio(max, 2, max), (max, 2));
+        assert_eq!(approximate_ratio(1, 0, 10), (0, 1));
+
+        // Under a 32-bit DEN, a fraction too fine for it still gets the
+        // closest step rather than rounding to zero
+        let den_max = u32::MAX as u64;
+        assert_eq!(approximate_ratio(1, 7_000_000_000, den_max), (1, den_max));
+    }
+
+    #[test]
+    fn achievable_range_spans_the_dividers_and_vco() {
+        // 7.5 GHz / 768 up to 15 GHz from a 100 MHz PFD
+        let regs = map_100mhz_pfd();
+        let fref = Frequency::from_mhz(100);
+        let (min, max) = achievable_range(fref, &regs).unwrap();
+        assert_eq!((min, max), (Frequency::from_hz(9_765_625), VCO_MAX));
+        assert!(solve(fref, &regs, min).is_ok());
+        assert!(solve(fref, &regs, max).is_ok());
+        assert!(solve(fref, &regs, Frequency::from_hz(min.as_hz() - 1)).is_err());
+
+        // A 20 kHz PFD tops out where N runs out, at 2^19 * 20 kHz
+        let fref = Frequency::from_khz(20);
+        let (_, max) = achievable_range(fref, &regs).unwrap();
+        assert_eq!(max, Frequency::from_hz(10_485_759_999));
+        // and a 10 kHz one cannot reach the VCO range at all
+        assert_eq!(achievable_range(Frequency::from_khz(10), &regs), None);
+    }
+
+    #[test]
+    fn clock_targets_prefer_an_integer_divided_path() {
+        let regs = map_100mhz_pfd();
+        let fref = Frequency::from_mhz(100);
+
+        // 156.25 MHz: the best-centered divider, 72, needs N = 112.5, but
+        // 48 and 64 are integer
+        let target = Frequency::from_hz(156_250_000);
+        let carrier = solve(fref, &regs, target).unwrap();
+        assert_eq!((carrier.path.divide_ratio(), carrier.num != 0), (72, true));
+        let mut clock_regs = regs;
+        let clock = set_clock_output(fref, &mut clock_regs, target).unwrap();
+        assert!(clock.integer);
+        assert_eq!((clock.divide_ratio, clock.plan.n), (64, 100));
+        assert_eq!(output_frequency(fref, &clock_regs), Some(target));
+        assert_eq!(fields::MASH_ORDER.get(&clock_regs), 0);
+
+        // 7.5 GHz is integer either way; the clock goes through CHDIV = 2
+        let target = Frequency::from_mhz(7_500);
+        assert_eq!(solve(fref, &regs, target).unwrap().path, OutputPath::Vco);
+        let clock = solve_clock(fref, &regs, target).unwrap();
+        assert_eq!(clock.plan.path, OutputPath::Divider(0));
+        assert_eq!((clock.plan.n, clock.plan.output), (150, target));
+
+        // With no integer path it is the plain solution
+        let target = Frequency::from_hz(10_000_000_001);
+        let clock = solve_clock(fref, &regs, target).unwrap();
+        assert_eq!(clock.plan, solve(fref, &regs, target).unwrap());
+        assert!(!clock.integer);
+    }
+
+    #[test]
+    fn unreachable_targets_report_the_nearest_achievable() {
+        // A 400 MHz PFD is too fast for fractional mode, so 10.1 GHz
+        // (N = 25.25) is out of reach; N = 25 gives the nearest, 10 GHz
+        let regs = map_100mhz_pfd();
+        let fref = Frequency::from_mhz(400);
+        let unreachable = |target, nearest| Error::UnreachableWithReference {
+            target,
+            nearest_achievable: nearest,
+        };
+        let target = Frequency::from_mhz(10_100);
+        assert_eq!(
+            solve(fref, &regs, target),
+            Err(unreachable(target, Frequency::from_ghz(10)))
+        );
+        // Through CHDIV = 2 the nearest is halved too, and N = 26 is closer
+        let target = Frequency::from_mhz(5_150);
+        assert_eq!(
+            solve(fref, &regs, target),
+            Err(unreachable(target, Frequency::from_mhz(5_200)))
+        );
+        assert!(solve(fref, &regs, Frequency::from_ghz(10)).is_ok());
+
+        // A 20 kHz PFD runs out of N at 2^19 - 1
+        let fref = Frequency::from_khz(20);
+        let target = Frequency::from_ghz(11);
+        assert_eq!(
+            solve(fref, &regs, target),
+            Err(unreachable(target, Frequency::from_hz(10_485_740_000)))
+        );
+    }
+
+    #[test]
+    fn const_maps_match_the_runtime_solver() {
+        let fref = Frequency::from_mhz(100);
+        let pfd = pfd_frequency(fref, &REG_MAP);
+        let runtime = |target| {
+            let mut regs = REG_MAP;
+            set_frequency(fref, &mut regs, target).unwrap();
+            regs
+        };
+        // Integer N straight from the VCO, and through the channel divider
+        let vco = Frequency::from_hz(pfd.0 * (10_000_000_000 / pfd.0));
+        let divided = Frequency::from_hz(pfd.0 * (1_000_000_000 / pfd.0));
+        for target in [vco, divided] {
+            assert_eq!(set_frequency_const(fref, target), runtime(target));
+        }
+        assert_eq!(output_frequency(fref, &runtime(divided)), Some(divided));
+        assert_ne!(runtime(divided)[75], REG_MAP[75]);
+    }
+}
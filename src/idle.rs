@@ -0,0 +1,309 @@
+//! What the firmware does between events.
+//!
+//! The main loop wakes on USB activity or a timer alarm, runs one `Idle`
+//! step for whatever is due, and goes back to sleep. Everything the step
+//! touches is passed in, so the loop body can be exercised on the host
+//! with mock peripherals.
+
+use crate::command::{self, DeviceState, LineBuffer, Response};
+use crate::driver::Lmx2594Driver;
+use crate::lmx2594::{ChipSelect, LockAction, LockMonitor, ProgramTiming};
+use core::fmt::Debug;
+use embedded_hal::{
+    blocking::{
+        delay::DelayMs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+
+/// Which idle duties the main loop performs
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct IdleConfig {
+    /// Sleep until the next event instead of spinning. The core waits for
+    /// an event with SEVONPEND set, so the USB and timer interrupts wake it
+    /// without needing handlers.
+    pub sleep: bool,
+    /// How often to check for loss of lock and recalibrate, or `None` to
+    /// leave it unmonitored. Monitoring reads the lock status back over
+    /// MUXout, so it needs MUXout wired to SPI0 RX.
+    pub lock_poll_ms: Option<u32>,
+    /// Run command lines received on the control interface
+    pub serve_commands: bool,
+}
+
+impl IdleConfig {
+    /// Sleep and serve commands, without lock monitoring
+    pub const fn new() -> Self {
+        IdleConfig {
+            sleep: true,
+            lock_poll_ms: None,
+            serve_commands: true,
+        }
+    }
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the firmware does once bring-up has finished
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum AfterLock {
+    /// Leave the device as programmed and ignore the control interface,
+    /// sleeping between USB events
+    Hold,
+    /// Serve the control interface, polling it continuously for the lowest
+    /// response latency
+    Serve,
+    /// Serve the control interface, sleeping until each command arrives
+    #[default]
+    WaitForCommand,
+}
+
+impl AfterLock {
+    /// The idle duties this policy calls for, without lock monitoring
+    pub const fn idle_config(self) -> IdleConfig {
+        let (sleep, serve_commands) = match self {
+            AfterLock::Hold => (true, false),
+            AfterLock::Serve => (false, true),
+            AfterLock::WaitForCommand => (true, true),
+        };
+        IdleConfig {
+            sleep,
+            lock_poll_ms: None,
+            serve_commands,
+        }
+    }
+}
+
+/// State carried by the main loop from one wake-up to the next
+pub struct Idle {
+    config: IdleConfig,
+    monitor: Option<LockMonitor>,
+    line: LineBuffer,
+    response: Response,
+}
+
+impl Idle {
+    pub const fn new(config: IdleConfig) -> Self {
+        let monitor = match config.lock_poll_ms {
+            Some(interval_ms) => Some(LockMonitor::new(interval_ms)),
+            None => None,
+        };
+        Idle {
+            config,
+            monitor,
+            line: LineBuffer::new(),
+            response: Response::new(),
+        }
+    }
+
+    pub fn config(&self) -> &IdleConfig {
+        &self.config
+    }
+
+    /// Time on the `poll_lock` clock when the next lock poll is due, for
+    /// scheduling a wake-up, or `None` if lock is not monitored
+    pub fn next_poll_ms(&self) -> Option<u64> {
+        self.monitor.as_ref().map(LockMonitor::next_poll_ms)
+    }
+
+    /// Check the lock status if a poll is due at `now_ms`, on any monotonic
+    /// millisecond clock, and recalibrate if lock has been lost
+    pub fn poll_lock<S, C, P, D>(
+        &mut self,
+        lmx: &mut Lmx2594Driver<S, C, P>,
+        delay: &mut D,
+        state: &DeviceState,
+        timing: &ProgramTiming,
+        now_ms: u64,
+    ) where
+        S: Transfer<u8> + Write<u8>,
+        <S as Transfer<u8>>::Error: Debug,
+        <S as Write<u8>>::Error: Debug,
+        C: ChipSelect,
+        P: OutputPin,
+        P::Error: Debug,
+        D: DelayMs<u32>,
+    {
+        let monitor = match self.monitor.as_mut() {
+            Some(monitor) => monitor,
+            None => return,
+        };
+        if !monitor.poll_due(now_ms) {
+            return;
+        }
+        match lmx.is_locked(state.regs[0]) {
+            Ok(locked) => {
+                if monitor.update(locked) == LockAction::Recalibrate {
+                    if let Err(e) = lmx.recalibrate(delay, state.regs[0], timing) {
+                        defmt::warn!("Recalibration failed: {}", e);
+                    }
+                }
+            }
+            Err(e) => defmt::warn!("Lock status poll failed: {}", e),
+        }
+    }
+
+    /// Run the command lines in `input`, received on the control interface,
+    /// passing each response line to `send`.
+    ///
+    /// Returns how many bytes were used. This is all of `input` unless a
+    /// command left something for the firmware to do first: a `load`
+    /// switching the input over to a register map image, in which case the
+    /// rest belongs to the image, a `freq` retune or a `factory` reset.
+    pub fn serve<S, C, P, F>(
+        &mut self,
+        input: &[u8],
+        lmx: &mut Lmx2594Driver<S, C, P>,
+        state: &mut DeviceState,
+        mut send: F,
+    ) -> usize
+    where
+        S: Transfer<u8> + Write<u8>,
+        <S as Transfer<u8>>::Error: Debug,
+        <S as Write<u8>>::Error: Debug,
+        C: ChipSelect,
+        P: OutputPin,
+        P::Error: Debug,
+        F: FnMut(&[u8]),
+    {
+        if !self.config.serve_commands {
+            return input.len();
+        }
+        let mut used = 0;
+        for &byte in input {
+            used += 1;
+            let text = match self.line.push(byte) {
+                Some(text) => text,
+                None => continue,
+            };
+            self.response.clear();
+            command::handle_line(text, lmx, state, &mut self.response).ok();
+            send(self.response.as_bytes());
+            if state.reset_requested || state.retune.is_some() {
+                break;
+            }
+            #[cfg(feature = "map-upload")]
+            if state.loading {
+                break;
+            }
+        }
+        used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{self, MUXOUT_LD_SEL};
+    use crate::frequency::SolveMode;
+    use crate::lmx2594::{FCAL_EN_OFF, FCAL_EN_ON, REG_MAP};
+    use crate::mock;
+
+    const LOCKED: u16 = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+
+    #[test]
+    fn after_lock_policies_pick_their_idle_duties() {
+        let duties = |policy: AfterLock| {
+            let config = policy.idle_config();
+            (config.sleep, config.serve_commands, config.lock_poll_ms)
+        };
+        assert_eq!(duties(AfterLock::Hold), (true, false, None));
+        assert_eq!(duties(AfterLock::Serve), (false, true, None));
+        assert_eq!(duties(AfterLock::WaitForCommand), (true, true, None));
+        assert_eq!(AfterLock::default().idle_config(), IdleConfig::new());
+
+        // Holding drops command lines without answering them
+        let mut lmx = Lmx2594Driver::new(
+            mock::Spi::new(&[]),
+            mock::Pin::new(&[]),
+            mock::Pin::new(&[]),
+        );
+        let mut state = DeviceState::new(REG_MAP);
+        let mut idle = Idle::new(AfterLock::Hold.idle_config());
+        let mut sent = Vec::new();
+        let used = idle.serve(b"mode int\r\n", &mut lmx, &mut state, |r| {
+            sent.extend_from_slice(r)
+        });
+        assert_eq!((used, sent.len()), (10, 0));
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+
+    #[test]
+    fn serves_commands_and_polls_lock_when_due() {
+        let mut regs = REG_MAP;
+        regs[0] = FCAL_EN_OFF & !MUXOUT_LD_SEL.mask();
+        let mut state = DeviceState::new(regs);
+        let spi = mock::Spi::new(&[mock::read(110, LOCKED)]);
+        let cs = mock::Pin::new(&mock::cs_frames(1));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+        let mut delay = mock::Delay::new();
+        let timing = ProgramTiming::default();
+
+        let mut idle = Idle::new(IdleConfig {
+            lock_poll_ms: Some(100),
+            ..IdleConfig::new()
+        });
+        assert_eq!(idle.next_poll_ms(), Some(0));
+        idle.poll_lock(&mut lmx, &mut delay, &state, &timing, 0);
+        assert_eq!(idle.next_poll_ms(), Some(100));
+        // Not due yet, so nothing goes out on the bus
+        idle.poll_lock(&mut lmx, &mut delay, &state, &timing, 99);
+
+        let mut sent = Vec::new();
+        let used = idle.serve(b"mode i", &mut lmx, &mut state, |r| {
+            sent.extend_from_slice(r)
+        });
+        assert_eq!((used, sent.len()), (6, 0));
+        idle.serve(b"nt\r\n", &mut lmx, &mut state, |r| {
+            sent.extend_from_slice(r)
+        });
+        assert_eq!(sent, b"mode int\r\n");
+        assert_eq!(state.mode, SolveMode::Integer);
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+
+    #[test]
+    fn lost_lock_is_recalibrated_until_it_returns() {
+        let mut regs = REG_MAP;
+        regs[0] = FCAL_EN_OFF & !MUXOUT_LD_SEL.mask();
+        let state = DeviceState::new(regs);
+        let recal = mock::writes([FCAL_EN_ON & !MUXOUT_LD_SEL.mask(), regs[0]]);
+        let mut transactions = vec![mock::read(110, LOCKED), mock::read(110, 0)];
+        transactions.extend(recal.iter().cloned());
+        transactions.push(mock::read(110, 0));
+        transactions.extend(recal.iter().cloned());
+        transactions.extend([mock::read(110, LOCKED), mock::read(110, LOCKED)]);
+        let spi = mock::Spi::new(&transactions);
+        let cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+        let mut lmx = Lmx2594Driver::new(spi, cs, mock::Pin::new(&[]));
+        let mut delay = mock::Delay::new();
+        let timing = ProgramTiming::default();
+
+        let mut idle = Idle::new(IdleConfig {
+            lock_poll_ms: Some(10),
+            ..IdleConfig::new()
+        });
+        // Locked, lost (recalibrate), still lost (again), back, still locked
+        for now_ms in [0, 10, 20, 30, 40] {
+            idle.poll_lock(&mut lmx, &mut delay, &state, &timing, now_ms);
+        }
+        assert_eq!(idle.monitor.map(|m| m.recoveries()), Some(1));
+
+        let (mut spi, mut cs, mut ce) = lmx.free();
+        spi.done();
+        cs.done();
+        ce.done();
+    }
+}
@@ -0,0 +1,40 @@
+//! Driver for the LMX2594 wideband synthesizer, shared by the Pico firmware
+//! in `main.rs`.
+//!
+//! Everything here is written against the `embedded-hal` traits rather than
+//! the rp2040 peripherals directly, apart from the DMA backend in `dma`, the
+//! flash storage in `flash` and the bus lock in `spinlock`.
+
+#![cfg_attr(not(test), no_std)]
+
+// Host-side tooling may allocate; the firmware never does
+#[cfg(all(feature = "host", not(test)))]
+extern crate std;
+
+pub mod bitbang;
+pub mod blob;
+pub mod command;
+pub mod dma;
+pub mod driver;
+pub mod fields;
+#[cfg(feature = "map-upload")]
+pub mod flash;
+pub mod frequency;
+pub mod idle;
+pub mod lmx2594;
+pub mod log;
+#[cfg(test)]
+mod mock;
+#[cfg(feature = "morse")]
+pub mod morse;
+pub mod noise;
+pub mod power;
+pub mod profile;
+pub mod ramp;
+pub mod recovery;
+pub mod refdetect;
+pub mod spinlock;
+pub mod startup;
+pub mod sync;
+pub mod sysref;
+pub mod tics;
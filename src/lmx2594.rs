@@ -8,10 +8,12 @@
 //! 5. Wait 10 ms
 //! 6. Program R0 one more time with FCAL_EN = 1 to ensure VCO cal
 //!    is run from a stable state
+//!
 //! Recommended changing frequency sequence:
 //! 1. Change N-divider value
 //! 2. Change PLL numerator and denominator
 //! 3. Program FCAL_EN = 1
+//!
 //! General programming remarks
 //! 1. Registers without field names in register map must be programmed as shown
 //! 2. Not all registers need to be programmed:
@@ -19,11 +21,18 @@
 //!    * R79-R106 need to be programmed only if ramping function RAMP_EN is used
 //!    * R0-R78 must always be programmed (lines 35-113 in TICS Pro hex dump)
 
-use embedded_hal::{digital::v2::OutputPin, prelude::_embedded_hal_blocking_spi_Write};
-use rp_pico::hal::{
-    gpio::{bank0::Gpio5, Output, Pin, PushPull},
-    pac::SPI0,
-    spi::{Enabled, Spi},
+use crate::fields::{self, FCAL_EN, MUXOUT_LD_SEL};
+use crate::frequency::{self, Frequency, FrequencyPlan, MashOrder, SolveMode};
+use crate::log::{DefmtLogger, Logger, Stage};
+use crate::power;
+use core::fmt::{self, Debug};
+use embedded_hal::{
+    blocking::{
+        delay::{DelayMs, DelayUs},
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+    spi::{Mode, MODE_0},
 };
 
 pub static REG_MAP: [u32; 113] = [
@@ -142,22 +151,704 @@ pub static REG_MAP: [u32; 113] = [
     0x700000, // 112
 ];
 
+// The map is hand-maintained hex, so fail the build if a value has been
+// transcribed into the wrong slot
+const _: () = check_addresses(&REG_MAP);
+
+/// Panic unless every register's address byte matches its index
+const fn check_addresses(regs: &[u32; 113]) {
+    let mut i = 0;
+    while i < regs.len() {
+        assert!(regs[i] >> 16 == i as u32, "REG_MAP entry at wrong address");
+        i += 1;
+    }
+}
+
+/// Reserved bits 13:10 and 4 of R0, which must always be written as in
+/// `R0_RESERVED`. Bit 9 between them is OUT_MUTE, a setting.
+pub const R0_RESERVED_MASK: u32 = 0x3c10;
+/// Required value of the `R0_RESERVED_MASK` bits: 1001 in bits 13:10, and
+/// bit 4 set
+pub const R0_RESERVED: u32 = 0x2410;
+
+/// Why `validate_map` rejected a register map
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ValidationError {
+    /// Every word is zero, or every word is all ones as erased flash reads
+    Blank,
+    /// The word at this index is not addressed to the register at that
+    /// index
+    Misplaced(u8),
+    /// R0 has its reserved bits wrong; this is the word
+    BadR0(u32),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Blank => f.write_str("map is blank"),
+            ValidationError::Misplaced(i) => write!(f, "word {} is not addressed to R{}", i, i),
+            ValidationError::BadR0(word) => {
+                write!(f, "R0 {:#08x} has its reserved bits wrong", word)
+            }
+        }
+    }
+}
+
+/// Check that `regs`, indexed by address from R0, looks like a register
+/// map at all, rather than blank or corrupted storage.
+///
+/// These are sanity checks, not a review of the settings: a map that is
+/// blank, has a word out of place or has R0's reserved bits wrong cannot
+/// have come from TICS Pro or this crate, and programming it would leave
+/// the device in an unknown state.
+pub fn validate_map(regs: &[u32]) -> Result<(), ValidationError> {
+    let blank = |fill| regs.iter().all(|&word| word & 0xff_ffff == fill);
+    if !regs.is_empty() && (blank(0) || blank(0xff_ffff)) {
+        return Err(ValidationError::Blank);
+    }
+    if let Some(i) = (0..regs.len()).find(|&i| regs[i] >> 16 != i as u32) {
+        return Err(ValidationError::Misplaced(i as u8));
+    }
+    match regs.first() {
+        Some(&r0) if r0 & R0_RESERVED_MASK != R0_RESERVED => Err(ValidationError::BadR0(r0)),
+        _ => Ok(()),
+    }
+}
+
+/// Number of `REG_MAP` words with a nonzero data field
+pub const PROGRAMMED_WORDS: usize = count_programmed(&REG_MAP);
+
+const fn count_programmed(regs: &[u32; 113]) -> usize {
+    let (mut i, mut n) = (0, 0);
+    while i < regs.len() {
+        if regs[i] & 0xffff != 0 {
+            n += 1;
+        }
+        i += 1;
+    }
+    n
+}
+
+/// The words of `regs` with a nonzero data field, in address order. The
+/// rest are fully given by their address, so `expand_map` restores them;
+/// in `REG_MAP` these are the readback registers and most of the ramp
+/// registers. `N` must be the number of such words.
+pub const fn compact_map<const N: usize>(regs: &[u32; 113]) -> [u32; N] {
+    let mut words = [0; N];
+    let (mut i, mut n) = (0, 0);
+    while i < regs.len() {
+        if regs[i] & 0xffff != 0 {
+            assert!(n < N, "more programmed words than N");
+            words[n] = regs[i];
+            n += 1;
+        }
+        i += 1;
+    }
+    assert!(n == N, "fewer programmed words than N");
+    words
+}
+
+/// Rebuild a full map from `compact_map`'s words. Words addressing a
+/// register outside of the map are ignored.
+pub fn expand_map(words: &[u32]) -> [u32; 113] {
+    let mut regs = [0; 113];
+    for (addr, r) in regs.iter_mut().enumerate() {
+        *r = (addr as u32) << 16;
+    }
+    for &word in words {
+        if let Some(r) = regs.get_mut((word >> 16) as usize) {
+            *r = word;
+        }
+    }
+    regs
+}
+
+/// `REG_MAP` as stored in flash with the `compact-map` feature
+#[cfg(feature = "compact-map")]
+static COMPACT_MAP: [u32; PROGRAMMED_WORDS] = compact_map(&REG_MAP);
+
+/// A copy of `REG_MAP`, for starting a register map at runtime.
+///
+/// With the `compact-map` feature this is rebuilt from `compact_map`'s
+/// words, so that `REG_MAP` itself is only used at compile time and is
+/// left out of the binary.
+pub fn default_map() -> [u32; 113] {
+    #[cfg(feature = "compact-map")]
+    {
+        expand_map(&COMPACT_MAP)
+    }
+    #[cfg(not(feature = "compact-map"))]
+    {
+        REG_MAP
+    }
+}
+
+/// Reserved-register values recommended for one LMX2594 silicon revision.
+///
+/// Parts with different date codes can call for different values in the
+/// registers the map says to "program as shown". A profile lists the full
+/// 24-bit words that differ from `REG_MAP`; they are applied to the
+/// baseline before any user settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct SiliconProfile {
+    pub name: &'static str,
+    /// Register words replacing the baseline, address in the top byte
+    pub overrides: &'static [u32],
+}
+
+impl SiliconProfile {
+    /// The baseline register map with this profile's overrides applied.
+    ///
+    /// Overrides addressing a register outside of the map are ignored.
+    pub fn baseline(&self) -> [u32; 113] {
+        let mut regs = default_map();
+        for &word in self.overrides {
+            if let Some(r) = regs.get_mut((word >> 16) as usize) {
+                *r = word;
+            }
+        }
+        regs
+    }
+
+    /// The profile in `PROFILES` called `name`
+    pub fn by_name(name: &str) -> Option<&'static SiliconProfile> {
+        PROFILES.iter().find(|p| p.name == name)
+    }
+}
+
+/// The silicon `REG_MAP` was exported from with TICS Pro, needing no
+/// overrides
+pub static SAMPLED_SILICON: SiliconProfile = SiliconProfile {
+    name: "sampled",
+    overrides: &[],
+};
+
+/// Earlier parts, programmed with the reserved values printed in the
+/// datasheet's register map table, which has R25 as 0x0624 where later
+/// TICS Pro exports use 0x0C2B
+pub static DATASHEET_SILICON: SiliconProfile = SiliconProfile {
+    name: "datasheet",
+    overrides: &[0x190624],
+};
+
+/// Every known profile, for selecting one by name
+pub static PROFILES: [SiliconProfile; 2] = [SAMPLED_SILICON, DATASHEET_SILICON];
+
+/// Write `regs` in the format of a TICS Pro hex register dump: one
+/// `R<addr>\t0x<word>` line per register, R112 first
+pub fn write_hex_dump<W: fmt::Write>(regs: &[u32; 113], out: &mut W) -> fmt::Result {
+    for (addr, word) in regs.iter().enumerate().rev() {
+        write!(out, "R{}\t{:#08X}\r\n", addr, word)?;
+    }
+    Ok(())
+}
+
+/// A short identifier for `regs`, for telling which configuration a unit
+/// is running from its boot log.
+///
+/// This is the CRC-16/CCITT-FALSE of the words in address order, each as
+/// its three MSB-first bytes. It only names a map; it is not the integrity check on
+/// stored images, which is the CRC-32 in `blob`.
+pub fn map_id(regs: &[u32; 113]) -> u16 {
+    let mut crc = 0xffffu16;
+    for byte in regs.iter().flat_map(|r| r.reg()) {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            let mask = (crc >> 15).wrapping_neg();
+            crc = (crc << 1) ^ (0x1021 & mask);
+        }
+    }
+    crc
+}
+
+/// A full register map, as saved and loaded by host-side tooling.
+///
+/// With the `serde` feature on a std target this serializes as a sequence
+/// of the 113 24-bit words, address in the top byte, and loading rejects a
+/// map whose words are out of place.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct RegisterMap(pub [u32; 113]);
+
+impl Default for RegisterMap {
+    fn default() -> Self {
+        RegisterMap(default_map())
+    }
+}
+
+impl From<[u32; 113]> for RegisterMap {
+    fn from(regs: [u32; 113]) -> Self {
+        RegisterMap(regs)
+    }
+}
+
+impl From<RegisterMap> for [u32; 113] {
+    fn from(map: RegisterMap) -> Self {
+        map.0
+    }
+}
+
+#[cfg(all(feature = "serde", not(target_os = "none")))]
+impl serde::Serialize for RegisterMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+#[cfg(all(feature = "serde", not(target_os = "none")))]
+impl<'de> serde::Deserialize<'de> for RegisterMap {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error as _, SeqAccess, Visitor};
+
+        struct MapVisitor;
+
+        impl<'de> Visitor<'de> for MapVisitor {
+            type Value = RegisterMap;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a sequence of 113 register words")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RegisterMap, A::Error> {
+                let mut regs = [0; 113];
+                for (i, r) in regs.iter_mut().enumerate() {
+                    let word: u32 = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+                    if word >> 16 != i as u32 {
+                        return Err(A::Error::custom(format_args!(
+                            "word {:#08x} at index {} has the wrong address",
+                            word, i
+                        )));
+                    }
+                    *r = word;
+                }
+                if seq.next_element::<u32>()?.is_some() {
+                    return Err(A::Error::invalid_length(114, &self));
+                }
+                Ok(RegisterMap(regs))
+            }
+        }
+
+        deserializer.deserialize_seq(MapVisitor)
+    }
+}
+
+/// Set of register addresses, one bit per register in the map
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub struct FailedAddresses(u128);
+
+impl FailedAddresses {
+    /// Record `addr`, which must be a register in the map
+    pub fn insert(&mut self, addr: u8) {
+        self.0 |= 1 << addr;
+    }
+
+    pub fn contains(&self, addr: u8) -> bool {
+        addr < 128 && self.0 & (1 << addr) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Number of addresses in the set
+    pub fn len(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// The addresses in the set, lowest first
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..REG_MAP.len() as u8).filter(move |&addr| self.contains(addr))
+    }
+}
+
+/// Errors reported by the driver
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Error {
+    /// The VCO frequency lies outside of its 7.5-15 GHz range
+    VcoOutOfRange(Frequency),
+    /// The requested frequency cannot be produced
+    InvalidFrequency(Frequency),
+    /// The requested frequency cannot be produced from the current reference
+    /// path: N would overflow, or the PFD is too fast for the fraction it
+    /// needs. A different reference or PFD would reach it; otherwise
+    /// `nearest_achievable` is the closest frequency this one can reach.
+    UnreachableWithReference {
+        /// The frequency asked for
+        target: Frequency,
+        /// The nearest frequency reachable with the current reference path
+        nearest_achievable: Frequency,
+    },
+    /// OUTA and OUTB cannot run at these two frequencies at once, since they
+    /// share the VCO
+    IncompatibleOutputs(Frequency, Frequency),
+    /// There is no register at this address
+    InvalidAddress(u8),
+    /// Nothing answered on MUXout, so the device is missing or miswired
+    NotPresent,
+    /// The PLL did not report lock in time
+    LockTimeout,
+    /// A power calibration table is empty or out of order
+    InvalidCalibration,
+    /// The SPI write to this register failed, see `ErrorPolicy::AbortOnError`
+    WriteFailed(u8),
+    /// The SPI writes to these registers failed, see
+    /// `ErrorPolicy::BestEffort`
+    WritesFailed(FailedAddresses),
+    /// The RampCLK PWM divider or wrap value is out of range
+    InvalidRampClock,
+    /// A register read back differently from what was just written to it,
+    /// see `ProgramOptions::verify`
+    VerifyFailed(u8),
+    /// An output drive setting is out of range, see `power::set_output_drive`
+    InvalidDrive(u8),
+    /// Pin 1 is wired as chip enable, so it cannot set the ramp direction
+    Pin1IsChipEnable,
+    /// A SYSREF burst length is out of range, see `sysref::MAX_PULSES`
+    InvalidPulseCount(u8),
+    /// There is no DMA channel with this number, see `dma::Spi0Dma::new`
+    InvalidDmaChannel(usize),
+    /// Programming would take this many ms, more than
+    /// `ProgramTiming::budget_ms`
+    OverBudget(u64),
+    /// The fractional numerator would not be below the denominator, see
+    /// `fields::set_frac`
+    OutOfRange,
+    /// The SPI transfer reading this register back failed
+    ReadFailed(u8),
+    /// A control GPIO (CE, RAMPDIR or SysRefReq) could not be driven
+    PinFailed,
+    /// A VCO calibration was asked for with RESET set in R0, which would
+    /// hold the device in reset instead
+    ResetAsserted,
+    /// A VCO calibration was asked for with POWERDOWN set in R0, which
+    /// would keep the device powered down instead
+    PoweredDown,
+    /// The VCO calibration never ran, which points at a missing reference
+    /// clock on OSCin rather than a bad register map
+    NoReference,
+    /// A VCO amplitude code is out of range, see `set_vco_daciset`
+    InvalidDaciset(u16),
+    /// A register map failed the sanity checks in `validate_map`
+    InvalidMap(ValidationError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::VcoOutOfRange(vco) => write!(f, "VCO frequency {} out of range", vco),
+            Error::InvalidFrequency(freq) => write!(f, "cannot produce {}", freq),
+            Error::UnreachableWithReference {
+                target,
+                nearest_achievable,
+            } => write!(
+                f,
+                "cannot produce {} from this reference; the nearest is {}",
+                target, nearest_achievable
+            ),
+            Error::IncompatibleOutputs(a, b) => write!(
+                f,
+                "OUTA {} and OUTB {} cannot share a VCO; one must be the other divided by CHDIV",
+                a, b
+            ),
+            Error::InvalidAddress(addr) => write!(f, "no register at address {}", addr),
+            Error::NotPresent => f.write_str("no device responded"),
+            Error::LockTimeout => f.write_str("timed out waiting for lock"),
+            Error::InvalidCalibration => f.write_str("invalid power calibration table"),
+            Error::WriteFailed(addr) => write!(f, "SPI write to R{} failed", addr),
+            Error::WritesFailed(failed) => {
+                f.write_str("SPI writes failed to")?;
+                for addr in failed.iter() {
+                    write!(f, " R{}", addr)?;
+                }
+                Ok(())
+            }
+            Error::InvalidRampClock => f.write_str("RampCLK divider out of range"),
+            Error::VerifyFailed(addr) => write!(f, "R{} did not read back as written", addr),
+            Error::InvalidDrive(level) => write!(f, "output drive level {} out of range", level),
+            Error::Pin1IsChipEnable => f.write_str("pin 1 is chip enable, not RAMPDIR"),
+            Error::InvalidPulseCount(count) => write!(f, "cannot send {} SYSREF pulses", count),
+            Error::InvalidDmaChannel(channel) => write!(f, "no DMA channel {}", channel),
+            Error::OverBudget(ms) => write!(f, "programming would take {} ms, over budget", ms),
+            Error::OutOfRange => f.write_str("PLL_NUM must be less than PLL_DEN"),
+            Error::ReadFailed(addr) => write!(f, "SPI readback of R{} failed", addr),
+            Error::PinFailed => f.write_str("could not drive a control pin"),
+            Error::ResetAsserted => f.write_str("cannot calibrate while RESET is set in R0"),
+            Error::PoweredDown => f.write_str("cannot calibrate while POWERDOWN is set in R0"),
+            Error::NoReference => f.write_str("no reference clock on OSCin"),
+            Error::InvalidDaciset(code) => write!(f, "VCO_DACISET {} out of range", code),
+            Error::InvalidMap(e) => write!(f, "invalid register map: {}", e),
+        }
+    }
+}
+
+impl Error {
+    /// Whether the error points at the bus rather than at the request, so
+    /// that retrying after a `recovery::Recovery` step may help
+    pub fn is_bus_fault(&self) -> bool {
+        matches!(
+            self,
+            Error::WriteFailed(_)
+                | Error::WritesFailed(_)
+                | Error::ReadFailed(_)
+                | Error::VerifyFailed(_)
+                | Error::NotPresent
+        )
+    }
+}
+
+/// The SPI mode uWire needs.
+///
+/// The LMX2594 clocks SDI in on the rising edge of SCK with SCK idling low,
+/// i.e. CPOL = 0 and CPHA = 0. In any other mode every bit is sampled half
+/// a clock off, so writes are silently garbled rather than rejected.
+pub const SPI_MODE: Mode = MODE_0;
+
+/// Set in the address byte to read a register instead of writing it
+pub(crate) const READ: u8 = 0x80;
+
 pub static FCAL_EN_OFF: u32 = 0x002414;
 pub static FCAL_EN_ON: u32 = REG_MAP[0]; //0x00241c
 pub static RESET_ON: u32 = 0x00241e;
 pub static RESET_OFF: u32 = REG_MAP[0];
 
+/// Drives the uWire chip select (CSB) around each 24-bit register write.
+///
+/// The LMX2594 latches a register on the rising edge of CSB, so CSB must
+/// stay low for all 24 bits of a word and go high between words. Any GPIO
+/// configured as a push-pull output implements this trait, toggling the pin
+/// by hand around each write; this is the default and always frames the
+/// words correctly.
+///
+/// GPIO errors cannot be reported from here (the rp2040's are Infallible),
+/// so the GPIO implementations ignore them. A CSB that failed to toggle
+/// shows up as a failed readback or verification instead.
+pub trait ChipSelect {
+    /// Assert CSB (drive low) before a register write
+    fn select(&mut self);
+    /// Deassert CSB (drive high), latching the register
+    fn deselect(&mut self);
+}
+
+impl<P> ChipSelect for P
+where
+    P: OutputPin,
+    P::Error: Debug,
+{
+    fn select(&mut self) {
+        self.set_low().ok();
+    }
+
+    fn deselect(&mut self) {
+        self.set_high().ok();
+    }
+}
+
+/// A manually driven CSB behind an inverting buffer or level shifter, so
+/// the GPIO is driven high to select the device.
+pub struct InvertedCs<P>(pub P);
+
+impl<P> ChipSelect for InvertedCs<P>
+where
+    P: OutputPin,
+    P::Error: Debug,
+{
+    fn select(&mut self) {
+        self.0.set_high().ok();
+    }
+
+    fn deselect(&mut self) {
+        self.0.set_low().ok();
+    }
+}
+
+/// A chip select that keeps consecutive CSB frames at least `gap_us` apart,
+/// for a long or marginal uWire bus that garbles writes sent back to back.
+///
+/// This is independent of `ProgramTiming::settle_ms`: it applies to every
+/// access, including the single writes of `mute`, `fast_hop` and readback,
+/// and it is timed on its own `delay`. Wrap the chip select handed to
+/// `Lmx2594Driver::new` to enforce it; without the wrapper there is no
+/// limit.
+pub struct SpacedCs<C, D> {
+    cs: C,
+    delay: D,
+    gap_us: u32,
+    framed: bool,
+}
+
+impl<C, D> SpacedCs<C, D> {
+    pub fn new(cs: C, delay: D, gap_us: u32) -> Self {
+        SpacedCs {
+            cs,
+            delay,
+            gap_us,
+            framed: false,
+        }
+    }
+
+    /// Give back the chip select and the delay
+    pub fn free(self) -> (C, D) {
+        (self.cs, self.delay)
+    }
+}
+
+impl<C, D> ChipSelect for SpacedCs<C, D>
+where
+    C: ChipSelect,
+    D: DelayUs<u32>,
+{
+    fn select(&mut self) {
+        if self.framed && self.gap_us > 0 {
+            self.delay.delay_us(self.gap_us);
+        }
+        self.framed = false;
+        self.cs.select();
+    }
+
+    fn deselect(&mut self) {
+        self.cs.deselect();
+        self.framed = true;
+    }
+}
+
+/// Exclusive use of an SPI bus that both RP2040 cores drive.
+///
+/// `acquire` blocks until no one else holds the bus, and every `acquire` is
+/// followed by exactly one `release`; the lock need not be re-entrant. See
+/// `spinlock::SpinlockBus` for one on the RP2040's hardware spinlocks.
+pub trait BusLock {
+    /// Wait for the bus and take it
+    fn acquire(&mut self);
+    /// Hand the bus back
+    fn release(&mut self);
+}
+
+/// A chip select that holds `lock` for the whole of each CSB frame, for an
+/// SPI bus shared between the two cores.
+///
+/// The locking model is one frame at a time: the lock is taken just before
+/// CSB is asserted and released just after it is deasserted, so another
+/// core can never clock its own bytes into the middle of a 24-bit word, but
+/// it can use the bus between two words. That is all the LMX2594 needs,
+/// since each register is latched on its own; a sequence such as
+/// `program_all` is not atomic as a whole and needs no lock of its own, as
+/// only this core ever addresses this device. The other core must frame
+/// its own transfers with the same lock. Wrap the chip select handed to
+/// `Lmx2594Driver::new`; without the wrapper there is no locking.
+pub struct LockedCs<C, L> {
+    cs: C,
+    lock: L,
+}
+
+impl<C, L> LockedCs<C, L> {
+    pub fn new(cs: C, lock: L) -> Self {
+        LockedCs { cs, lock }
+    }
+
+    /// Give back the chip select and the lock
+    pub fn free(self) -> (C, L) {
+        (self.cs, self.lock)
+    }
+}
+
+impl<C, L> ChipSelect for LockedCs<C, L>
+where
+    C: ChipSelect,
+    L: BusLock,
+{
+    fn select(&mut self) {
+        self.lock.acquire();
+        self.cs.select();
+    }
+
+    fn deselect(&mut self) {
+        self.cs.deselect();
+        self.lock.release();
+    }
+}
+
+/// CSB is wired to the SPI peripheral's own CSn pin, which must be put in
+/// `FunctionSpi` mode; framing is then left entirely to the peripheral.
+///
+/// This frees a GPIO, but the tradeoff is that the RP2040 SPI block in
+/// MODE_0 releases CSn between every 8-bit frame, so a 24-bit uWire word
+/// is only latched correctly if the board stretches CSB across the three
+/// bytes (e.g. through glue logic). Prefer manual GPIO control otherwise.
+pub struct HardwareCs;
+
+impl ChipSelect for HardwareCs {
+    fn select(&mut self) {}
+
+    fn deselect(&mut self) {}
+}
+
+/// The order in which the three bytes of a register word leave the SPI
+/// peripheral.
+///
+/// uWire is MSB-first, and that is the only order the LMX2594 accepts on its
+/// own pins. The other orders are NOT standard: they only exist to undo a
+/// level shifter or FPGA between the RP2040 and the device that reorders the
+/// stream, without reworking the board. Pick one with the `wire-reversed-bytes`
+/// or `wire-reversed-bits` feature; see `WIRE_ORDER`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum WireOrder {
+    /// The documented uWire order, as returned by `Lmx2594::reg`
+    MsbFirst,
+    /// The three bytes in reverse order, each still MSB-first
+    ReversedBytes,
+    /// The whole 24-bit word reversed, i.e. sent LSB-first
+    ReversedBits,
+}
+
+impl WireOrder {
+    /// The bytes to hand to the SPI peripheral for `word`
+    pub const fn pack(self, word: u32) -> [u8; 3] {
+        let [_, u1, u2, u3] = word.to_be_bytes();
+        match self {
+            WireOrder::MsbFirst => [u1, u2, u3],
+            WireOrder::ReversedBytes => [u3, u2, u1],
+            WireOrder::ReversedBits => [u3.reverse_bits(), u2.reverse_bits(), u1.reverse_bits()],
+        }
+    }
+}
+
+#[cfg(all(feature = "wire-reversed-bytes", feature = "wire-reversed-bits"))]
+compile_error!("at most one of wire-reversed-bytes and wire-reversed-bits can be enabled");
+
+/// The byte order register writes are sent in. MSB-first unless one of the
+/// non-standard `wire-reversed-*` features is enabled.
+#[cfg(not(any(feature = "wire-reversed-bytes", feature = "wire-reversed-bits")))]
+pub const WIRE_ORDER: WireOrder = WireOrder::MsbFirst;
+#[cfg(feature = "wire-reversed-bytes")]
+pub const WIRE_ORDER: WireOrder = WireOrder::ReversedBytes;
+#[cfg(feature = "wire-reversed-bits")]
+pub const WIRE_ORDER: WireOrder = WireOrder::ReversedBits;
+
 /// Manage the 24-bit registers of the LMX2594
 pub trait Lmx2594 {
-    /// Return the three bytes of the 24-bit register stored as a u32
+    /// Return the three bytes of the 24-bit register stored as a u32, in
+    /// the documented MSB-first order. Writes go out in `WIRE_ORDER`.
     fn reg(&self) -> [u8; 3];
-    /// Write the 24-bit register
-    fn write_reg(
+    /// Write the 24-bit register, logging any SPI error rather than
+    /// returning it
+    fn write_reg<S, C>(&self, spi: &mut S, spi_cs: &mut C, buf: &mut [u8; 3])
+    where
+        S: Write<u8>,
+        S::Error: Debug,
+        C: ChipSelect;
+    /// Write the 24-bit register, returning any SPI error instead of
+    /// panicking. CSB is released either way.
+    fn try_write_reg<S, C>(
         &self,
-        spi: &mut Spi<Enabled, SPI0, 8>,
-        spi_cs: &mut Pin<Gpio5, Output<PushPull>>,
+        spi: &mut S,
+        spi_cs: &mut C,
         buf: &mut [u8; 3],
-    );
+    ) -> Result<(), S::Error>
+    where
+        S: Write<u8>,
+        C: ChipSelect;
 }
 
 // We store the 24-bit register values as u32
@@ -167,16 +858,3631 @@ impl Lmx2594 for u32 {
         [u1, u2, u3]
     }
 
-    /// Write register to device. All Results are Infallible
-    fn write_reg(
+    /// Write register to device, logging a failure rather than returning
+    /// it. Everything in this crate goes through `try_write_reg` instead and
+    /// reports the failure as `Error::WriteFailed`.
+    fn write_reg<S, C>(&self, spi: &mut S, spi_cs: &mut C, buf: &mut [u8; 3])
+    where
+        S: Write<u8>,
+        S::Error: Debug,
+        C: ChipSelect,
+    {
+        if let Err(e) = self.try_write_reg(spi, spi_cs, buf) {
+            defmt::error!(
+                "SPI write to R{} failed: {}",
+                self >> 16,
+                defmt::Debug2Format(&e)
+            );
+        }
+    }
+
+    fn try_write_reg<S, C>(
         &self,
-        spi: &mut Spi<Enabled, SPI0, 8>,
-        spi_cs: &mut Pin<Gpio5, Output<PushPull>>,
+        spi: &mut S,
+        spi_cs: &mut C,
         buf: &mut [u8; 3],
-    ) {
-        spi_cs.set_low().unwrap();
-        *buf = self.reg();
-        spi.write(buf).unwrap();
-        spi_cs.set_high().unwrap();
+    ) -> Result<(), S::Error>
+    where
+        S: Write<u8>,
+        C: ChipSelect,
+    {
+        spi_cs.select();
+        *buf = WIRE_ORDER.pack(*self);
+        let result = spi.write(buf);
+        spi_cs.deselect();
+        result
+    }
+}
+
+/// Write `word`, returning a failed SPI write as `Error::WriteFailed`
+pub(crate) fn write_word<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    word: u32,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    word.try_write_reg(spi, spi_cs, buf).map_err(|e| {
+        defmt::debug!("SPI error: {}", defmt::Debug2Format(&e));
+        Error::WriteFailed((word >> 16) as u8)
+    })
+}
+
+/// Read a register back over MUXout.
+///
+/// MUXout only carries readback data while MUXOUT_LD_SEL = 0 in R0; use
+/// `readback` if it may still be configured as the lock detect output.
+pub fn read_reg<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    addr: u8,
+) -> Result<u16, Error>
+where
+    S: Transfer<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    if addr as usize >= REG_MAP.len() {
+        return Err(Error::InvalidAddress(addr));
+    }
+    *buf = [READ | addr, 0, 0];
+    spi_cs.select();
+    let value = match spi.transfer(buf) {
+        Ok(data) => Ok(u16::from_be_bytes([data[1], data[2]])),
+        Err(e) => {
+            defmt::debug!("SPI error: {}", defmt::Debug2Format(&e));
+            Err(Error::ReadFailed(addr))
+        }
+    };
+    spi_cs.deselect();
+    value
+}
+
+/// Read a register back, given `r0`, the value R0 was last programmed with.
+///
+/// If `r0` selects lock detect on MUXout, R0 is temporarily rewritten in
+/// readback mode and restored afterwards. FCAL_EN is cleared in both writes
+/// so that reading does not trigger a VCO calibration.
+pub fn readback<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    r0: u32,
+    addr: u8,
+) -> Result<u16, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+{
+    if addr as usize >= REG_MAP.len() {
+        return Err(Error::InvalidAddress(addr));
+    }
+    let r0 = r0 & !FCAL_EN.mask();
+    let lock_detect = r0 & MUXOUT_LD_SEL.mask() != 0;
+    if lock_detect {
+        write_word(spi, spi_cs, buf, r0 & !MUXOUT_LD_SEL.mask())?;
+    }
+    let value = read_reg(spi, spi_cs, buf, addr);
+    if lock_detect {
+        write_word(spi, spi_cs, buf, r0)?;
+    }
+    value
+}
+
+/// Lock detect state from RB_LD_VTUNE
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum LockDetect {
+    /// Unlocked, with the tuning voltage at its low rail
+    VtuneLow,
+    /// No valid reading, e.g. during a calibration
+    Invalid,
+    /// Locked
+    Locked,
+    /// Unlocked, with the tuning voltage at its high rail
+    VtuneHigh,
+}
+
+impl LockDetect {
+    /// Decode an RB_LD_VTUNE value
+    pub fn from_code(code: u32) -> Self {
+        match code & 0b11 {
+            0 => LockDetect::VtuneLow,
+            1 => LockDetect::Invalid,
+            2 => LockDetect::Locked,
+            _ => LockDetect::VtuneHigh,
+        }
+    }
+}
+
+/// Why the PLL is or is not locked, as far as the readback can tell
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum LockDiagnosis {
+    /// Locked
+    Locked,
+    /// The VCO calibration never finished: lock detect is still invalid, or
+    /// no VCO core was ever selected. The calibration state machine is
+    /// clocked from OSCin, so this is what a missing reference looks like.
+    NoReference,
+    /// A core was calibrated but the loop sits at a Vtune rail: the
+    /// reference is there, so look at the register map instead
+    BadConfig,
+}
+
+impl LockDiagnosis {
+    /// Classify a readback snapshot. This is a heuristic: a reference that
+    /// dies after the calibration still reads as `BadConfig`.
+    pub fn from_readback(state: &ReadbackState) -> Self {
+        match (state.lock, state.vco_core) {
+            (LockDetect::Locked, _) => LockDiagnosis::Locked,
+            (LockDetect::Invalid, _) | (_, None) => LockDiagnosis::NoReference,
+            _ => LockDiagnosis::BadConfig,
+        }
+    }
+}
+
+/// First of the readback-only registers, R107-R112
+pub const READBACK_FIRST: u8 = 107;
+
+/// Snapshot of the readback-only registers, see `read_all_readback`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReadbackState {
+    /// R107-R109 as read; the datasheet gives them no fields
+    pub reserved: [u16; 3],
+    /// RB_LD_VTUNE
+    pub lock: LockDetect,
+    /// RB_VCO_SEL, as an index into `frequency::VCO_CORES`, or `None` if
+    /// no core has been selected
+    pub vco_core: Option<usize>,
+    /// RB_VCO_CAPCTRL
+    pub capctrl: u8,
+    /// RB_VCO_DACISET
+    pub daciset: u16,
+}
+
+impl ReadbackState {
+    /// Decode R107-R112, in that order
+    pub fn decode(words: [u16; 6]) -> Self {
+        let [r107, r108, r109, r110, r111, r112] = words;
+        ReadbackState {
+            reserved: [r107, r108, r109],
+            lock: LockDetect::from_code(fields::RB_LD_VTUNE.extract(r110.into())),
+            vco_core: (fields::RB_VCO_SEL.extract(r110.into()) as usize).checked_sub(1),
+            capctrl: fields::RB_VCO_CAPCTRL.extract(r111.into()) as u8,
+            daciset: fields::RB_VCO_DACISET.extract(r112.into()) as u16,
+        }
+    }
+}
+
+impl defmt::Format for ReadbackState {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "lock {}, ", self.lock);
+        match self.vco_core {
+            Some(core) => defmt::write!(f, "VCO{}", core + 1),
+            None => defmt::write!(f, "no VCO"),
+        }
+        defmt::write!(
+            f,
+            ", CAPCTRL {=u8}, DACISET {=u16}, R107-R109 {:#06x}",
+            self.capctrl,
+            self.daciset,
+            self.reserved
+        );
+    }
+}
+
+/// Read all of the readback-only registers R107-R112, given `r0`, the value
+/// R0 was last programmed with.
+///
+/// As in `readback`, MUXout is switched to readback mode if `r0` has it on
+/// lock detect, but only once for all six reads.
+pub fn read_all_readback<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    r0: u32,
+) -> Result<ReadbackState, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+{
+    let r0 = r0 & !FCAL_EN.mask();
+    let lock_detect = r0 & MUXOUT_LD_SEL.mask() != 0;
+    if lock_detect {
+        write_word(spi, spi_cs, buf, r0 & !MUXOUT_LD_SEL.mask())?;
+    }
+    let mut words = [0; 6];
+    let mut result = Ok(());
+    for (addr, word) in (READBACK_FIRST..).zip(words.iter_mut()) {
+        match read_reg(spi, spi_cs, buf, addr) {
+            Ok(value) => *word = value,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    if lock_detect {
+        write_word(spi, spi_cs, buf, r0)?;
+    }
+    result.map(|()| ReadbackState::decode(words))
+}
+
+/// What answered on the bus, as far as `read_device_id` can tell
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Identity {
+    /// R0 read back exactly as written. The LMX2594 has no ID or version
+    /// register, so a pin-compatible sibling with the same R0 layout, such
+    /// as the LMX2595, answers the same way.
+    Lmx2594Family,
+    /// Something drove MUXout, but R0 did not read back as written: a part
+    /// with a different register layout, or a marginal bus
+    Unknown,
+}
+
+/// Result of `read_device_id`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct DeviceId {
+    /// R0 as read back
+    pub raw: u16,
+    /// What `raw` says about the part
+    pub identity: Identity,
+}
+
+/// R0 written by `read_device_id`: MUXout in readback mode, and neither
+/// RESET nor FCAL_EN set
+const ID_R0: u32 = FCAL_EN_OFF & !MUXOUT_LD_SEL.mask();
+
+/// Identify the part on the bus as well as readback allows.
+///
+/// Lacking an ID register, this falls back to what `probe` does: R0 is
+/// written and read back. A floating or shorted MUXout line reads as all
+/// zeros or all ones and is reported as `Error::NotPresent`; anything else
+/// is returned raw, with `Identity::Lmx2594Family` if it matches what was
+/// written.
+pub fn read_device_id<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+) -> Result<DeviceId, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+{
+    write_word(spi, spi_cs, buf, ID_R0)?;
+    let raw = read_reg(spi, spi_cs, buf, 0)?;
+    let identity = match raw {
+        0x0000 | 0xffff => {
+            defmt::error!("Probe read R0 = {=u16:#06x}, MUXout is not driven", raw);
+            return Err(Error::NotPresent);
+        }
+        _ if u32::from(raw) == ID_R0 & 0xffff => Identity::Lmx2594Family,
+        _ => Identity::Unknown,
+    };
+    Ok(DeviceId { raw, identity })
+}
+
+/// Check that a live device is on the bus.
+///
+/// R0 is rewritten with MUXout in readback mode (and neither RESET nor
+/// FCAL_EN set) and then read back. A floating or shorted MUXout line reads
+/// as all zeros or all ones instead of the value just written, so a wiring
+/// fault is caught before the register map is programmed into nothing. See
+/// `read_device_id` for telling a different part from a missing one.
+pub fn probe<S, C>(spi: &mut S, spi_cs: &mut C, buf: &mut [u8; 3]) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+{
+    let id = read_device_id(spi, spi_cs, buf)?;
+    if id.identity == Identity::Lmx2594Family {
+        Ok(())
+    } else {
+        defmt::error!(
+            "Probe read R0 = {=u16:#06x}, expected {=u32:#06x}",
+            id.raw,
+            ID_R0 & 0xffff
+        );
+        Err(Error::NotPresent)
+    }
+}
+
+/// Check for a reference clock before programming, by calibrating the VCO
+/// with R0 as in `REG_MAP`, allowing `timing.lock_timeout_ms` for it, and
+/// reading the result back.
+///
+/// Meant to run straight after `reset`, when the rest of the map is at its
+/// power-on defaults: whether those lock does not matter, only whether the
+/// calibration ran at all (see `LockDiagnosis`). A missing reference is
+/// returned as `Error::NoReference`. Needs MUXout wired to SPI0 RX.
+pub fn check_reference<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let r0 = FCAL_EN_ON & !MUXOUT_LD_SEL.mask();
+    write_word(spi, spi_cs, buf, r0)?;
+    delay.delay_ms(timing.lock_timeout_ms);
+    write_word(spi, spi_cs, buf, r0 & !FCAL_EN.mask())?;
+    let state = read_all_readback(spi, spi_cs, buf, r0)?;
+    defmt::debug!("Reference check: {}", state);
+    if LockDiagnosis::from_readback(&state) == LockDiagnosis::NoReference {
+        defmt::error!("VCO calibration never ran; is the reference connected?");
+        return Err(Error::NoReference);
+    }
+    Ok(())
+}
+
+/// Read the lock detect state back from R110, given `r0` as for `readback`
+pub fn is_locked<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    r0: u32,
+) -> Result<bool, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+{
+    let r110 = readback(spi, spi_cs, buf, r0, fields::RB_LD_VTUNE.addr)?;
+    Ok(fields::RB_LD_VTUNE.extract(r110.into()) == fields::RB_LD_VTUNE_LOCKED)
+}
+
+/// Default number of locked reads in a row `wait_for_lock` needs, see
+/// `ProgramTiming::lock_debounce`
+pub const DEFAULT_LOCK_DEBOUNCE: u32 = 3;
+
+/// Poll the lock detect state every millisecond until it has read locked
+/// `debounce` times in a row, giving up after `timeout_ms`.
+///
+/// Lock detect can flicker while the loop is still settling, so a single
+/// locked read is not taken as lock unless `debounce` is 1 (0 counts as 1).
+/// An unlocked read starts the count over.
+pub fn wait_for_lock<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    r0: u32,
+    timeout_ms: u32,
+    debounce: u32,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    poll_for_lock(delay, timeout_ms, debounce, || {
+        is_locked(spi, spi_cs, buf, r0)
+    })
+}
+
+/// The polling loop of `wait_for_lock`, reading the lock state with
+/// `locked`
+pub(crate) fn poll_for_lock<D, F>(
+    delay: &mut D,
+    timeout_ms: u32,
+    debounce: u32,
+    mut locked: F,
+) -> Result<(), Error>
+where
+    D: DelayMs<u32>,
+    F: FnMut() -> Result<bool, Error>,
+{
+    let mut run = 0;
+    for _ in 0..=timeout_ms {
+        if locked()? {
+            run += 1;
+            if run >= debounce.max(1) {
+                return Ok(());
+            }
+        } else {
+            run = 0;
+        }
+        delay.delay_ms(1);
+    }
+    Err(Error::LockTimeout)
+}
+
+/// What `LockMonitor::update` calls for after a lock status poll
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum LockAction {
+    /// Nothing to do
+    None,
+    /// The PLL is unlocked; run `recalibrate`
+    Recalibrate,
+}
+
+/// Watches the lock status from the idle loop and decides when to
+/// recalibrate, so that a long-running synthesizer recovers by itself from
+/// a reference glitch or thermal drift.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct LockMonitor {
+    interval_ms: u32,
+    next_poll_ms: u64,
+    locked: bool,
+    recoveries: u32,
+}
+
+impl LockMonitor {
+    /// Poll every `interval_ms`, starting from a locked device
+    pub const fn new(interval_ms: u32) -> Self {
+        LockMonitor {
+            interval_ms,
+            next_poll_ms: 0,
+            locked: true,
+            recoveries: 0,
+        }
+    }
+
+    /// Whether the lock status is due to be polled at `now_ms`, on any
+    /// monotonic millisecond clock. Schedules the next poll if so.
+    pub fn poll_due(&mut self, now_ms: u64) -> bool {
+        if now_ms < self.next_poll_ms {
+            return false;
+        }
+        self.next_poll_ms = now_ms + self.interval_ms as u64;
+        true
+    }
+
+    /// When the next poll is due, on the clock given to `poll_due`
+    pub fn next_poll_ms(&self) -> u64 {
+        self.next_poll_ms
+    }
+
+    /// Record a polled lock status. Every poll that finds the PLL unlocked
+    /// asks for a recalibration, until lock returns.
+    pub fn update(&mut self, locked: bool) -> LockAction {
+        match (self.locked, locked) {
+            (true, true) => LockAction::None,
+            (true, false) => {
+                defmt::warn!("Lock lost, recalibrating");
+                self.locked = false;
+                self.recoveries += 1;
+                LockAction::Recalibrate
+            }
+            (false, false) => {
+                defmt::warn!("Still unlocked, recalibrating again");
+                LockAction::Recalibrate
+            }
+            (false, true) => {
+                defmt::info!("Lock recovered");
+                self.locked = true;
+                LockAction::None
+            }
+        }
+    }
+
+    /// Number of times lock has been lost since the monitor was created
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+}
+
+/// Order in which `program_all` writes the register map
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum ProgramOrder {
+    /// Highest to lowest address, as recommended by the datasheet
+    #[default]
+    Descending,
+    /// Lowest to highest address. Non-standard, but makes SPI captures line
+    /// up with register numbers when debugging
+    Ascending,
+}
+
+/// Delays used while bringing up and programming the device, in ms
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct ProgramTiming {
+    /// Wait after raising CE before the first register write.
+    ///
+    /// The power-up sequence only asks for power to be applied before RESET
+    /// is programmed, so the minimum is however long the board's supplies
+    /// take to settle once CE is high. 10 ms covers the EVM's regulators;
+    /// increase it for supplies that ramp slowly.
+    pub power_on_ms: u32,
+    /// Wait between writing RESET = 1 and RESET = 0.
+    ///
+    /// The reset is register controlled, and the datasheet asks for no
+    /// width beyond the write itself; the default keeps a conservative
+    /// 10 ms for boards that want margin.
+    pub reset_ms: u32,
+    /// Wait after each register write
+    pub settle_ms: u32,
+    /// Wait after the whole map has been written, before the calibration
+    /// pulse (step 5 of the power-up sequence, 10 ms in the datasheet).
+    /// `program_all` also waits this long after each extra pass.
+    pub post_program_ms: u32,
+    /// Longest wait for the PLL to lock, where bring-up waits for it
+    pub lock_timeout_ms: u32,
+    /// Locked reads in a row that count as lock, see `wait_for_lock`
+    pub lock_debounce: u32,
+    /// Longest `program_all` may spend in delays, or `None` for no limit.
+    /// A map and options whose delays add up to more, see
+    /// `program_time_ms`, are rejected with `Error::OverBudget` before
+    /// anything is written.
+    pub budget_ms: Option<u32>,
+}
+
+impl Default for ProgramTiming {
+    fn default() -> Self {
+        ProgramTiming {
+            power_on_ms: 10,
+            reset_ms: 10,
+            settle_ms: 10,
+            post_program_ms: 10,
+            lock_timeout_ms: 100,
+            lock_debounce: DEFAULT_LOCK_DEBOUNCE,
+            budget_ms: None,
+        }
+    }
+}
+
+/// Total time `program_all` spends in delays programming `words` register
+/// words: `timing.settle_ms` after every word, `timing.post_program_ms`
+/// after every pass, and `timing.settle_ms` after both writes of each
+/// calibration pulse, plus one more for the R0 written first to verify.
+/// SPI transfers add to this.
+pub fn program_time_ms(words: usize, options: &ProgramOptions, timing: &ProgramTiming) -> u64 {
+    let settle_ms = timing.settle_ms as u64;
+    let per_pass = words as u64 * settle_ms + timing.post_program_ms as u64;
+    let readback_mode = if options.verify { settle_ms } else { 0 };
+    readback_mode + options.passes as u64 * per_pass + options.fcal_pulses as u64 * 2 * settle_ms
+}
+
+/// How the outputs are treated while the device is first programmed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum BootPolicy {
+    /// Program the register map as given
+    #[default]
+    Normal,
+    /// Keep both outputs powered down until the PLL has locked, then write
+    /// the output power registers. This limits the peak current drawn at
+    /// startup. The bus rate is not the driver's to set: for thermally
+    /// tight builds the caller should also initialize the SPI at a slow
+    /// clock for bring-up, as the firmware does with `LOW_POWER_SPI_HZ`.
+    LowPower,
+    /// Keep both outputs in high impedance through programming and lock,
+    /// and leave them there until `release_outputs`. For an RF line shared
+    /// with other sources, which the outputs must not drive until the rest
+    /// of the system says so. The caller puts the mux settings aside with
+    /// `HeldOutputs::hold` before bring-up.
+    HighZ,
+}
+
+/// What uWire pin 1 is wired to.
+///
+/// The EVM's pin 1 is RAMPDIR/CE: a jumper connects it either to the
+/// device's chip enable or to its ramp direction input. With it on RAMPDIR,
+/// CE has to be tied high on the board, since the firmware can no longer
+/// power the device up; the pin then carries `ramp::RampDir` instead, and
+/// driving it as a static enable would fight the ramp.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum Pin1Function {
+    /// Chip enable, raised by `bring_up` to power the device on
+    #[default]
+    ChipEnable,
+    /// Ramp direction, set with `ramp::set_ramp_dir`; `bring_up` leaves it
+    /// alone
+    RampDir,
+}
+
+/// What `program_all` does when an SPI write fails
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum ErrorPolicy {
+    /// Stop at the first failed write, returning `Error::WriteFailed`
+    #[default]
+    AbortOnError,
+    /// Carry on with the rest of the map, then return every failed address
+    /// in `Error::WritesFailed`. Useful for diagnosing an intermittent bus.
+    BestEffort,
+}
+
+/// Choices about how the device is programmed, apart from the timing
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct ProgramOptions {
+    /// Order in which the register map is written
+    pub order: ProgramOrder,
+    /// Check that the device answers on the bus before programming it
+    pub probe: bool,
+    /// Number of times the whole map is written before calibrating. One
+    /// is enough normally; some eval setups only take the map reliably
+    /// when it is written twice.
+    pub passes: u32,
+    /// Number of FCAL_EN on/off cycles after the map is written. One is
+    /// enough normally; marginal board/reference setups may need more.
+    pub fcal_pulses: u32,
+    /// How the outputs are handled during bring-up
+    pub boot: BootPolicy,
+    /// Whether a failed SPI write stops programming
+    pub on_error: ErrorPolicy,
+    /// Read each register back straight after writing it, stopping with
+    /// `Error::VerifyFailed` on a mismatch. R0 and the readback-only
+    /// registers are not checked. Needs MUXout wired to SPI0 RX, and keeps
+    /// MUXout in readback mode until the calibration at the end.
+    pub verify: bool,
+    /// What the `ce` pin passed to `bring_up` is wired to
+    pub pin1: Pin1Function,
+    /// Run `check_reference` after the reset, so a missing reference is
+    /// reported as `Error::NoReference` instead of a lock timeout later.
+    /// Needs MUXout wired to SPI0 RX.
+    pub check_reference: bool,
+}
+
+impl Default for ProgramOptions {
+    fn default() -> Self {
+        ProgramOptions {
+            order: ProgramOrder::default(),
+            probe: false,
+            passes: 1,
+            fcal_pulses: 1,
+            boot: BootPolicy::default(),
+            on_error: ErrorPolicy::default(),
+            verify: false,
+            pin1: Pin1Function::default(),
+            check_reference: false,
+        }
+    }
+}
+
+/// Write each register word yielded by `regs`, in the order given, with
+/// `timing.settle_ms` after each one.
+///
+/// Every word must carry a write to a register in the map (R0-R112) in its
+/// address byte. Writing stops at the first word that does not, which is
+/// returned as `Error::InvalidAddress`; the words before it have been
+/// written. Failed SPI writes are handled according to `policy`.
+pub fn write_regs_iter<S, C, D, I>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: I,
+    timing: &ProgramTiming,
+    policy: ErrorPolicy,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+    I: IntoIterator<Item = u32>,
+{
+    let mut logger = DefmtLogger;
+    let mut run = WriteRun::new(timing, policy, None, &mut logger);
+    write_words(spi, spi_cs, delay, buf, regs, &mut run)?;
+    run.finish()
+}
+
+/// Checks a register word against the device after it has been written
+type VerifyFn<S, C> = fn(&mut S, &mut C, &mut [u8; 3], u32) -> Result<(), Error>;
+
+/// Read back the register `word` was just written to and compare. R0 and
+/// the readback-only registers R107-R112 always pass.
+fn verify_word<S, C>(spi: &mut S, spi_cs: &mut C, buf: &mut [u8; 3], word: u32) -> Result<(), Error>
+where
+    S: Transfer<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    let addr = (word >> 16) as u8;
+    if addr == 0 || addr >= 107 {
+        return Ok(());
+    }
+    let value = read_reg(spi, spi_cs, buf, addr)?;
+    if value as u32 != word & 0xffff {
+        defmt::debug!(
+            "R{} read back as {=u16:#06x}, wrote {=u32:#06x}",
+            addr,
+            value,
+            word & 0xffff
+        );
+        return Err(Error::VerifyFailed(addr));
+    }
+    Ok(())
+}
+
+/// Settings and progress of one programming run, shared by every
+/// `write_words` call it makes
+struct WriteRun<'a, S, C, L> {
+    timing: &'a ProgramTiming,
+    policy: ErrorPolicy,
+    verify: Option<VerifyFn<S, C>>,
+    logger: &'a mut L,
+    /// Failed writes so far, under `ErrorPolicy::BestEffort`
+    failed: FailedAddresses,
+}
+
+impl<'a, S, C, L: Logger> WriteRun<'a, S, C, L> {
+    fn new(
+        timing: &'a ProgramTiming,
+        policy: ErrorPolicy,
+        verify: Option<VerifyFn<S, C>>,
+        logger: &'a mut L,
+    ) -> Self {
+        WriteRun {
+            timing,
+            policy,
+            verify,
+            logger,
+            failed: FailedAddresses::default(),
+        }
+    }
+
+    /// Report and return `error`
+    fn fail(&mut self, error: Error) -> Result<(), Error> {
+        self.logger.log_error(&error);
+        Err(error)
+    }
+
+    /// The overall result once every write has been attempted
+    fn finish(mut self) -> Result<(), Error> {
+        if self.failed.is_empty() {
+            Ok(())
+        } else {
+            self.fail(Error::WritesFailed(self.failed))
+        }
+    }
+}
+
+/// Write the words yielded by `regs` as described for `write_regs_iter`,
+/// checking each successful write with the run's verifier, if any
+fn write_words<S, C, D, I, L>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: I,
+    run: &mut WriteRun<'_, S, C, L>,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+    I: IntoIterator<Item = u32>,
+    L: Logger,
+{
+    for r in regs {
+        let addr = r >> 16;
+        if addr as usize >= REG_MAP.len() {
+            return run.fail(Error::InvalidAddress(addr.min(u8::MAX as u32) as u8));
+        }
+        let addr = addr as u8;
+        match r.try_write_reg(spi, spi_cs, buf) {
+            Ok(()) => {
+                run.logger.log_write(addr, r);
+                if let Some(verify) = run.verify {
+                    if let Err(e) = verify(spi, spi_cs, buf, r) {
+                        return run.fail(e);
+                    }
+                }
+            }
+            Err(e) => {
+                defmt::debug!("SPI error: {}", defmt::Debug2Format(&e));
+                match run.policy {
+                    ErrorPolicy::AbortOnError => return run.fail(Error::WriteFailed(addr)),
+                    ErrorPolicy::BestEffort => {
+                        run.logger.log_error(&Error::WriteFailed(addr));
+                        run.failed.insert(addr);
+                    }
+                }
+            }
+        }
+        delay.delay_ms(run.timing.settle_ms);
+    }
+    Ok(())
+}
+
+/// Program only R`start` to R`end` inclusive from the map `regs`, in
+/// descending order like `program_all`, with `timing.settle_ms` after each,
+/// e.g. R44-R46 after changing the outputs in the shadow map.
+///
+/// No calibration is run, apart from the one R0 starts if the range
+/// includes it with FCAL_EN set. A range past R112, or with `start` above
+/// `end`, is rejected with `Error::InvalidAddress` for the offending bound
+/// before anything is written.
+#[allow(clippy::too_many_arguments)]
+pub fn program_range<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+    start: u8,
+    end: u8,
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    if end as usize >= regs.len() {
+        return Err(Error::InvalidAddress(end));
+    }
+    if start > end {
+        return Err(Error::InvalidAddress(start));
+    }
+    let words = regs[start as usize..=end as usize].iter().rev().copied();
+    write_regs_iter(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        words,
+        timing,
+        ErrorPolicy::AbortOnError,
+    )
+}
+
+/// Program every register in `regs`, `options.passes` times over, then run
+/// the VCO calibration (steps 4-6 of the power-up sequence).
+///
+/// Under `ErrorPolicy::BestEffort` the calibration is still run after
+/// failed writes, and the error lists every register that failed. A
+/// verification mismatch always stops programming. Nothing is written if
+/// `regs` fails `validate_map`, or the delays would exceed
+/// `timing.budget_ms`.
+pub fn program_all<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32],
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let mut logger = DefmtLogger;
+    program_all_logged(spi, spi_cs, delay, buf, regs, options, timing, &mut logger)
+}
+
+/// `program_all`, reporting each write and failure to `logger`
+#[allow(clippy::too_many_arguments)]
+pub fn program_all_logged<S, C, D, L>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32],
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+    logger: &mut L,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+    L: Logger,
+{
+    if let Err(e) = validate_map(regs) {
+        let error = Error::InvalidMap(e);
+        logger.log_error(&error);
+        return Err(error);
+    }
+    // The calibration pulses are built from the map's own R0, so check it
+    // can calibrate before writing anything
+    if options.fcal_pulses > 0 {
+        if let Err(error) = check_calibration_r0(regs[0]) {
+            logger.log_error(&error);
+            return Err(error);
+        }
+    }
+    let total_ms = program_time_ms(regs.len(), options, timing);
+    if let Some(budget_ms) = timing.budget_ms {
+        if total_ms > budget_ms as u64 {
+            let error = Error::OverBudget(total_ms);
+            logger.log_error(&error);
+            return Err(error);
+        }
+    }
+    let verify: Option<VerifyFn<S, C>> = if options.verify {
+        Some(verify_word::<S, C>)
+    } else {
+        None
+    };
+    // Verifying reads back over MUXout, which `reset` leaves on lock
+    // detect, so switch it to readback before the first word and hold it
+    // there until the calibration pulses write the real R0
+    let readback_r0 = |r: &u32| match r >> 16 {
+        0 if options.verify => r & !MUXOUT_LD_SEL.mask(),
+        _ => *r,
+    };
+    logger.log_stage(Stage::Program {
+        words: options.passes.saturating_mul(regs.len() as u32),
+    });
+    let mut run = WriteRun::new(timing, options.on_error, verify, logger);
+    if options.verify {
+        let r0 = regs[0] & !(MUXOUT_LD_SEL.mask() | FCAL_EN.mask());
+        write_words(spi, spi_cs, delay, buf, [r0], &mut run)?;
+    }
+    if options.order == ProgramOrder::Ascending {
+        defmt::warn!("Programming registers in ascending order, which is non-standard");
+    }
+    for _ in 0..options.passes {
+        match options.order {
+            ProgramOrder::Descending => {
+                let words = regs.iter().rev().map(readback_r0);
+                write_words(spi, spi_cs, delay, buf, words, &mut run)?
+            }
+            ProgramOrder::Ascending => {
+                let words = regs.iter().map(readback_r0);
+                write_words(spi, spi_cs, delay, buf, words, &mut run)?
+            }
+        }
+        delay.delay_ms(timing.post_program_ms);
+    }
+
+    run.verify = None;
+    run.logger.log_stage(Stage::Calibrate {
+        pulses: options.fcal_pulses,
+    });
+    for _ in 0..options.fcal_pulses {
+        let pulse = [regs[0] | FCAL_EN.mask(), regs[0] & !FCAL_EN.mask()];
+        write_words(spi, spi_cs, delay, buf, pulse, &mut run)?;
+    }
+    run.finish()
+}
+
+/// Reset all registers to their defaults (steps 2-3 of the power-up
+/// sequence), holding RESET for `timing.reset_ms`
+pub fn reset<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    write_word(spi, spi_cs, buf, RESET_ON)?;
+    delay.delay_ms(timing.reset_ms);
+
+    write_word(spi, spi_cs, buf, RESET_OFF)?;
+    delay.delay_ms(timing.settle_ms);
+    Ok(())
+}
+
+/// Run the full power-up sequence: enable the chip, reset it, then
+/// program `regs` and calibrate the VCO
+#[allow(clippy::too_many_arguments)]
+pub fn bring_up<S, C, P, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    ce: &mut P,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    P: OutputPin,
+    P::Error: Debug,
+    D: DelayMs<u32>,
+{
+    let mut logger = DefmtLogger;
+    bring_up_logged(
+        spi,
+        spi_cs,
+        ce,
+        delay,
+        buf,
+        regs,
+        options,
+        timing,
+        &mut logger,
+    )
+}
+
+/// `bring_up`, reporting each stage, write and failure to `logger`
+#[allow(clippy::too_many_arguments)]
+pub fn bring_up_logged<S, C, P, D, L>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    ce: &mut P,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+    logger: &mut L,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    P: OutputPin,
+    P::Error: Debug,
+    D: DelayMs<u32>,
+    L: Logger,
+{
+    // Ensure the ~CS pin is high before power-on
+    spi_cs.deselect();
+    delay.delay_ms(timing.settle_ms);
+
+    // Power on the device, unless CE is tied high and pin 1 is RAMPDIR
+    logger.log_stage(Stage::PowerOn);
+    if options.pin1 == Pin1Function::ChipEnable {
+        ce.set_high().map_err(|_| Error::PinFailed)?;
+    }
+    delay.delay_ms(timing.power_on_ms);
+
+    if options.probe {
+        probe(spi, spi_cs, buf)?;
+    }
+
+    logger.log_stage(Stage::Reset);
+    reset(spi, spi_cs, delay, buf, timing)?;
+    if options.check_reference {
+        check_reference(spi, spi_cs, delay, buf, timing)?;
+    }
+
+    match options.boot {
+        BootPolicy::Normal => {
+            program_all_logged(spi, spi_cs, delay, buf, regs, options, timing, logger)?
+        }
+        BootPolicy::LowPower => {
+            let mut staged = *regs;
+            fields::OUTA_PD.set(&mut staged, 1);
+            fields::OUTB_PD.set(&mut staged, 1);
+            program_all_logged(spi, spi_cs, delay, buf, &staged, options, timing, logger)?;
+
+            logger.log_stage(Stage::LockWait);
+            let locked = wait_for_lock(
+                spi,
+                spi_cs,
+                delay,
+                buf,
+                regs[0],
+                timing.lock_timeout_ms,
+                timing.lock_debounce,
+            );
+            logger.log_stage(Stage::Locked(locked.is_ok()));
+            locked?;
+            // Restore the output power-downs now that the PLL is locked,
+            // unless `regs` had both outputs off anyway
+            if staged[44] != regs[44] {
+                write_word(spi, spi_cs, buf, regs[44])?;
+                delay.delay_ms(timing.settle_ms);
+            }
+        }
+        BootPolicy::HighZ => {
+            let mut staged = *regs;
+            HeldOutputs::hold(&mut staged);
+            program_all_logged(spi, spi_cs, delay, buf, &staged, options, timing, logger)?;
+
+            logger.log_stage(Stage::LockWait);
+            let locked = wait_for_lock(
+                spi,
+                spi_cs,
+                delay,
+                buf,
+                regs[0],
+                timing.lock_timeout_ms,
+                timing.lock_debounce,
+            );
+            logger.log_stage(Stage::Locked(locked.is_ok()));
+            locked?;
+        }
+    }
+    Ok(())
+}
+
+/// Return to the built-in register map: copy `REG_MAP` into `regs`, reset
+/// the device and program the whole map, calibrating the VCO as in
+/// `program_all`. Used to recover from a bad uploaded or edited map.
+pub fn factory_reset<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    *regs = default_map();
+    reset(spi, spi_cs, delay, buf, timing)?;
+    program_all(spi, spi_cs, delay, buf, regs, options, timing)
+}
+
+/// Check that pulsing FCAL_EN in `r0` would calibrate: an `r0` with RESET
+/// set points at a reset that was never cleared (`Error::ResetAsserted`),
+/// and one with POWERDOWN set would leave the device off
+/// (`Error::PoweredDown`)
+fn check_calibration_r0(r0: u32) -> Result<(), Error> {
+    if r0 & fields::RESET.mask() != 0 {
+        defmt::error!(
+            "R0 = {=u32:#08x} holds RESET; clear it before calibrating",
+            r0
+        );
+        return Err(Error::ResetAsserted);
+    }
+    if r0 & fields::POWERDOWN.mask() != 0 {
+        defmt::error!(
+            "R0 = {=u32:#08x} holds POWERDOWN; clear it before calibrating",
+            r0
+        );
+        return Err(Error::PoweredDown);
+    }
+    Ok(())
+}
+
+/// Run a VCO calibration by pulsing FCAL_EN in R0, given `r0`, the value R0
+/// is otherwise programmed with.
+///
+/// An `r0` with RESET or POWERDOWN set is rejected before anything is
+/// written, see `check_calibration_r0`.
+pub fn recalibrate<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    r0: u32,
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    check_calibration_r0(r0)?;
+    write_word(spi, spi_cs, buf, r0 | FCAL_EN.mask())?;
+    delay.delay_ms(timing.settle_ms);
+
+    write_word(spi, spi_cs, buf, r0 & !FCAL_EN.mask())?;
+    delay.delay_ms(timing.settle_ms);
+    Ok(())
+}
+
+/// Highest VCO amplitude code, `fields::VCO_DACISET`
+pub const MAX_DACISET: u16 = 0x1ff;
+
+/// Read the VCO amplitude code the last calibration chose, RB_VCO_DACISET
+/// in R112, given `r0` as for `readback`. This is the baseline to trim
+/// from with `set_vco_daciset`.
+pub fn read_vco_daciset<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    r0: u32,
+) -> Result<u16, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+{
+    let r112 = readback(spi, spi_cs, buf, r0, fields::RB_VCO_DACISET.addr)?;
+    Ok(fields::RB_VCO_DACISET.extract(r112.into()) as u16)
+}
+
+/// Hold the VCO amplitude at `code` rather than the value the calibration
+/// picks, or with `None`, hand it back to the calibration. Records the
+/// change in the shadow map `regs` and writes R16 (VCO_DACISET) and R8
+/// (VCO_DACISET_FORCE).
+///
+/// The amplitude sets the VCO core current, so trimming it a few codes
+/// either side of `read_vco_daciset` can buy some phase noise. It is also
+/// what the calibration tunes for reliable lock over temperature: a forced
+/// code that suits one core, frequency and temperature can leave the VCO
+/// unable to lock or oscillate at another, and it stays forced through
+/// every later calibration and retune until released. Only trim a
+/// characterized board, and recalibrate afterwards.
+///
+/// A `code` above `MAX_DACISET` is rejected with `Error::InvalidDaciset`
+/// before anything is written.
+pub fn set_vco_daciset<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    code: Option<u16>,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    if let Some(code) = code {
+        if code > MAX_DACISET {
+            return Err(Error::InvalidDaciset(code));
+        }
+        fields::VCO_DACISET.set(regs, code.into());
+        write_word(spi, spi_cs, buf, regs[fields::VCO_DACISET.addr as usize])?;
+    }
+    fields::VCO_DACISET_FORCE.set(regs, code.is_some() as u32);
+    write_word(
+        spi,
+        spi_cs,
+        buf,
+        regs[fields::VCO_DACISET_FORCE.addr as usize],
+    )
+}
+
+/// R44 from `regs` with both outputs powered down
+fn outputs_off(regs: &[u32; 113]) -> u32 {
+    regs[44] | fields::OUTA_PD.mask() | fields::OUTB_PD.mask()
+}
+
+/// Power down both outputs, leaving the PLL running and locked so that
+/// `unmute` brings them straight back at the same frequency
+pub fn mute<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    write_word(spi, spi_cs, buf, outputs_off(regs))
+}
+
+/// Restore the output power-down settings of `regs` after `mute`
+pub fn unmute<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    write_word(spi, spi_cs, buf, regs[44])
+}
+
+/// One of the two RF outputs
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Output {
+    A,
+    B,
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Output::A => "OUTA",
+            Output::B => "OUTB",
+        })
+    }
+}
+
+/// Power `output` up or down, recording the change in the shadow map
+/// `regs` and writing R44
+pub fn enable_output<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    output: Output,
+    enable: bool,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    let pd = match output {
+        Output::A => fields::OUTA_PD,
+        Output::B => fields::OUTB_PD,
+    };
+    pd.set(regs, !enable as u32);
+    write_word(spi, spi_cs, buf, regs[pd.addr as usize])
+}
+
+/// What an output is driven from, for `set_output_source`. SYSREF, which
+/// only OUTB can carry, is left to `sysref`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum OutputSource {
+    /// The channel divider
+    ChannelDivider,
+    /// The VCO directly
+    Vco,
+    /// Neither; the output is high impedance
+    HighZ,
+}
+
+impl OutputSource {
+    /// The OUTx_MUX code selecting this source
+    pub const fn mux(self) -> u32 {
+        match self {
+            OutputSource::ChannelDivider => fields::OUT_MUX_CHDIV,
+            OutputSource::Vco => fields::OUT_MUX_VCO,
+            OutputSource::HighZ => fields::OUT_MUX_HIGH_Z,
+        }
+    }
+}
+
+/// Switch `output` to `source`, recording the change in the shadow map
+/// `regs` and writing R45 (OUTA) or R46 (OUTB)
+pub fn set_output_source<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    output: Output,
+    source: OutputSource,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    let mux = match output {
+        Output::A => fields::OUTA_MUX,
+        Output::B => fields::OUTB_MUX,
+    };
+    mux.set(regs, source.mux());
+    write_word(spi, spi_cs, buf, regs[mux.addr as usize])
+}
+
+/// Give OUTA and OUTB the same drive level, source and power-down state,
+/// through `power::set_output_drive`, `set_output_source` and
+/// `enable_output` for each in turn.
+///
+/// Both outputs then tap the VCO at the same point, so they always carry
+/// the same frequency and can never ask for two different VCO frequencies.
+/// A `drive` above `power::MAX_CODE` is rejected with `Error::InvalidDrive`
+/// before anything is written.
+pub fn set_both_outputs<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    drive: u8,
+    source: OutputSource,
+    enabled: bool,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    for output in [Output::A, Output::B] {
+        power::set_output_drive(spi, spi_cs, buf, regs, output, drive)?;
+    }
+    for output in [Output::A, Output::B] {
+        set_output_source(spi, spi_cs, buf, regs, output, source)?;
+        enable_output(spi, spi_cs, buf, regs, output, enabled)?;
+    }
+    Ok(())
+}
+
+/// OUTA_MUX and OUTB_MUX put aside while `BootPolicy::HighZ` keeps the
+/// outputs in high impedance
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct HeldOutputs {
+    outa_mux: u32,
+    outb_mux: u32,
+}
+
+impl HeldOutputs {
+    /// Put the output mux settings in `regs` aside, leaving both outputs
+    /// on high impedance in `regs`
+    pub fn hold(regs: &mut [u32; 113]) -> Self {
+        let held = HeldOutputs {
+            outa_mux: fields::OUTA_MUX.get(regs),
+            outb_mux: fields::OUTB_MUX.get(regs),
+        };
+        fields::OUTA_MUX.set(regs, fields::OUT_MUX_HIGH_Z);
+        fields::OUTB_MUX.set(regs, fields::OUT_MUX_HIGH_Z);
+        held
+    }
+}
+
+/// Switch both outputs from high impedance back to the sources in `held`,
+/// recording them in the shadow map `regs` and writing R45 (OUTA) and R46
+/// (OUTB). Call it once the PLL has locked and the shared line is free.
+pub fn release_outputs<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    held: HeldOutputs,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    for (mux, code) in [
+        (fields::OUTA_MUX, held.outa_mux),
+        (fields::OUTB_MUX, held.outb_mux),
+    ] {
+        mux.set(regs, code);
+        write_word(spi, spi_cs, buf, regs[mux.addr as usize])?;
+    }
+    Ok(())
+}
+
+/// Set the MASH order, recording the change in the shadow map `regs` and
+/// writing R44.
+///
+/// Switching to `MashOrder::Integer` also zeroes NUM and sets DEN to 1
+/// (see `frequency::apply_mash_order`), writing R43-R42 and R39-R38 as well.
+pub fn set_mash_order<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    order: MashOrder,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    frequency::apply_mash_order(regs, order);
+    write_word(spi, spi_cs, buf, regs[fields::MASH_ORDER.addr as usize])?;
+    if order == MashOrder::Integer {
+        for addr in [43, 42, 39, 38] {
+            write_word(spi, spi_cs, buf, regs[addr])?;
+        }
+    }
+    Ok(())
+}
+
+/// Drive the device to its lowest current draw: both outputs and then the
+/// whole chip are powered down. Unlike `mute`, the PLL loses lock, so use
+/// `wake_and_lock` to come back.
+pub fn shutdown<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    mute(spi, spi_cs, buf, regs)?;
+    let r0 = (regs[0] | fields::POWERDOWN.mask()) & !FCAL_EN.mask();
+    write_word(spi, spi_cs, buf, r0)
+}
+
+/// Undo `shutdown`: power the chip up, recalibrate the VCO, wait for lock
+/// and only then restore the outputs
+pub fn wake_and_lock<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let r0 = regs[0] & !fields::POWERDOWN.mask();
+    write_word(spi, spi_cs, buf, r0 & !FCAL_EN.mask())?;
+    delay.delay_ms(timing.power_on_ms);
+
+    recalibrate(spi, spi_cs, delay, buf, r0, timing)?;
+    wait_for_lock(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        r0,
+        timing.lock_timeout_ms,
+        timing.lock_debounce,
+    )?;
+    unmute(spi, spi_cs, buf, regs)?;
+    Ok(())
+}
+
+/// Registers rewritten by `change_frequency`, in the order they are
+/// written: channel divider, MASH order and output muxes, then N, then the
+/// fraction
+static RETUNE_REGS: [usize; 11] = [31, 75, 44, 45, 46, 34, 36, 38, 39, 42, 43];
+
+/// What happens to the outputs while `change_frequency` retunes
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum RetunePolicy {
+    /// Mute both outputs until the PLL has relocked, so nothing downstream
+    /// sees the frequency slew through the N/NUM/DEN updates
+    #[default]
+    HoldOutputs,
+    /// Leave the outputs running throughout
+    Live,
+    /// Write the new dividers with the outputs running, but skip the VCO
+    /// calibration and the wait for lock, for staging several changes and
+    /// calibrating once with `commit_calibration` at the end. Until then
+    /// the PLL runs on the old calibration and may not lock.
+    Deferred,
+}
+
+/// Retune a running device to `target` with a reference of `fref`,
+/// following the recommended frequency change sequence: N, then NUM/DEN,
+/// then a VCO calibration. `mode` is passed on to the solver.
+///
+/// The new plan is recorded in the shadow map `regs`. Under
+/// `RetunePolicy::HoldOutputs` the outputs are only restored once lock
+/// has been confirmed; if it is not, they stay muted and
+/// `Error::LockTimeout` is returned.
+#[allow(clippy::too_many_arguments)]
+pub fn change_frequency<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    fref: Frequency,
+    target: Frequency,
+    mode: SolveMode,
+    policy: RetunePolicy,
+    timing: &ProgramTiming,
+) -> Result<FrequencyPlan, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let mut logger = DefmtLogger;
+    change_frequency_logged(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        regs,
+        fref,
+        target,
+        mode,
+        policy,
+        timing,
+        &mut logger,
+    )
+}
+
+/// `change_frequency`, reporting the new output frequency or the failure
+/// to `logger`
+#[allow(clippy::too_many_arguments)]
+pub fn change_frequency_logged<S, C, D, L>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    fref: Frequency,
+    target: Frequency,
+    mode: SolveMode,
+    policy: RetunePolicy,
+    timing: &ProgramTiming,
+    logger: &mut L,
+) -> Result<FrequencyPlan, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+    L: Logger,
+{
+    let result = retune(
+        spi, spi_cs, delay, buf, regs, fref, target, mode, policy, timing,
+    );
+    match &result {
+        Ok(plan) => logger.log_freq("Retuned to", plan.output),
+        Err(e) => logger.log_error(e),
+    }
+    result
+}
+
+/// The body of `change_frequency`
+#[allow(clippy::too_many_arguments)]
+fn retune<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    fref: Frequency,
+    target: Frequency,
+    mode: SolveMode,
+    policy: RetunePolicy,
+    timing: &ProgramTiming,
+) -> Result<FrequencyPlan, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let plan = frequency::set_frequency_with_mode(fref, regs, target, mode)?;
+
+    let hold = policy == RetunePolicy::HoldOutputs;
+    if hold {
+        mute(spi, spi_cs, buf, regs)?;
+    }
+    // R44 also holds the output power-downs, which must stay set while held
+    let words = RETUNE_REGS.iter().map(|&addr| match addr {
+        44 if hold => outputs_off(regs),
+        _ => regs[addr],
+    });
+    write_regs_iter(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        words,
+        timing,
+        ErrorPolicy::AbortOnError,
+    )?;
+    if policy == RetunePolicy::Deferred {
+        return Ok(plan);
+    }
+    recalibrate(spi, spi_cs, delay, buf, regs[0], timing)?;
+    wait_for_lock(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        regs[0],
+        timing.lock_timeout_ms,
+        timing.lock_debounce,
+    )?;
+    if hold {
+        unmute(spi, spi_cs, buf, regs)?;
+    }
+    Ok(plan)
+}
+
+/// Run the VCO calibration left out by `RetunePolicy::Deferred` and wait
+/// for lock, once the last of the staged changes is written.
+pub fn commit_calibration<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+    timing: &ProgramTiming,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    recalibrate(spi, spi_cs, delay, buf, regs[0], timing)?;
+    wait_for_lock(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        regs[0],
+        timing.lock_timeout_ms,
+        timing.lock_debounce,
+    )
+}
+
+/// Poll `is_locked` back to back until it reports lock, returning how long
+/// that took in µs on the monotonic clock `now_us`, or `Error::LockTimeout`
+/// once `timeout_us` has gone by without it
+pub fn time_to_lock<F, T>(mut is_locked: F, mut now_us: T, timeout_us: u64) -> Result<u32, Error>
+where
+    F: FnMut() -> Result<bool, Error>,
+    T: FnMut() -> u64,
+{
+    let start = now_us();
+    loop {
+        let locked = is_locked()?;
+        let elapsed = now_us().saturating_sub(start);
+        if locked {
+            return Ok(elapsed.min(u32::MAX as u64) as u32);
+        }
+        if elapsed >= timeout_us {
+            return Err(Error::LockTimeout);
+        }
+    }
+}
+
+/// Retune to `target` like `change_frequency` with `RetunePolicy::Live`,
+/// and measure the lock time: from just after the write that starts the
+/// VCO calibration until lock detect first reads as locked, in µs on the
+/// monotonic clock `now_us`, e.g. the RP2040 timer.
+///
+/// The lock status is read back over MUXout as fast as the bus allows, so
+/// the result is only as fine as one readback. Gives up with
+/// `Error::LockTimeout` after `timing.lock_timeout_ms`.
+#[allow(clippy::too_many_arguments)]
+pub fn measure_lock_time<S, C, D, T>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    fref: Frequency,
+    target: Frequency,
+    timing: &ProgramTiming,
+    now_us: T,
+) -> Result<u32, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+    T: FnMut() -> u64,
+{
+    frequency::set_frequency(fref, regs, target)?;
+    let words = RETUNE_REGS.iter().map(|&addr| regs[addr]);
+    write_regs_iter(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        words,
+        timing,
+        ErrorPolicy::AbortOnError,
+    )?;
+    let r0 = regs[0];
+    check_calibration_r0(r0)?;
+    write_word(spi, spi_cs, buf, r0 | FCAL_EN.mask())?;
+    let timeout_us = u64::from(timing.lock_timeout_ms) * 1000;
+    let elapsed = time_to_lock(|| is_locked(spi, spi_cs, buf, r0), now_us, timeout_us);
+    write_word(spi, spi_cs, buf, r0 & !FCAL_EN.mask())?;
+    let elapsed = elapsed?;
+    defmt::info!("Locked to {} in {=u32} us", target, elapsed);
+    Ok(elapsed)
+}
+
+/// Registers rewritten by `fast_hop`, in the order they are written: N,
+/// then the fraction
+static HOP_REGS: [usize; 6] = [34, 36, 38, 39, 42, 43];
+
+/// How `fast_hop` reached its target
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Hop {
+    /// Only N, NUM and DEN were rewritten before the calibration
+    Fast(FrequencyPlan),
+    /// The hop needed more than that, so it fell back to `change_frequency`
+    Full(FrequencyPlan),
+}
+
+impl Hop {
+    /// The plan that was programmed
+    pub fn plan(&self) -> &FrequencyPlan {
+        match self {
+            Hop::Fast(plan) | Hop::Full(plan) => plan,
+        }
+    }
+}
+
+/// `regs` retuned to `target`, if that changes nothing but N, NUM and DEN
+/// and keeps the VCO on the same core
+fn plan_fast_hop(
+    fref: Frequency,
+    regs: &[u32; 113],
+    target: Frequency,
+) -> Result<Option<([u32; 113], FrequencyPlan)>, Error> {
+    let plan = frequency::solve(fref, regs, target)?;
+    let mut hopped = *regs;
+    frequency::apply_plan(&mut hopped, &plan)?;
+    let core = frequency::vco_frequency(fref, regs)
+        .ok()
+        .and_then(frequency::vco_core);
+    let same_core = core.is_some() && core == frequency::vco_core(plan.vco);
+    let same_config =
+        (0..regs.len()).all(|addr| HOP_REGS.contains(&addr) || hopped[addr] == regs[addr]);
+    Ok(if same_core && same_config {
+        Some((hopped, plan))
+    } else {
+        None
+    })
+}
+
+/// Retune a running device to `target` as quickly as possible: write only
+/// N, NUM and DEN, then pulse FCAL_EN, leaving the outputs running.
+///
+/// This holds for hops within one VCO core that keep the output path and
+/// channel divider. Any other hop falls back to `change_frequency` with
+/// `policy`, which is reported as `Hop::Full`. A fast hop does not wait for
+/// lock; follow it with `wait_for_lock` where that matters. Under
+/// `RetunePolicy::Deferred` it skips the calibration too.
+#[allow(clippy::too_many_arguments)]
+pub fn fast_hop<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    fref: Frequency,
+    target: Frequency,
+    policy: RetunePolicy,
+    timing: &ProgramTiming,
+) -> Result<Hop, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let (hopped, plan) = match plan_fast_hop(fref, regs, target)? {
+        Some(hop) => hop,
+        None => {
+            defmt::debug!("{} Hz is out of band for a fast hop", target.as_hz());
+            let plan = change_frequency(
+                spi,
+                spi_cs,
+                delay,
+                buf,
+                regs,
+                fref,
+                target,
+                SolveMode::Auto,
+                policy,
+                timing,
+            )?;
+            return Ok(Hop::Full(plan));
+        }
+    };
+    *regs = hopped;
+    let words = HOP_REGS.iter().map(|&addr| regs[addr]);
+    write_regs_iter(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        words,
+        timing,
+        ErrorPolicy::AbortOnError,
+    )?;
+    if policy != RetunePolicy::Deferred {
+        recalibrate(spi, spi_cs, delay, buf, regs[0], timing)?;
+    }
+    Ok(Hop::Fast(plan))
+}
+
+/// What `program_and_verify` asked for next to what the device reports
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct VerifyReport {
+    /// The plan that was programmed
+    pub plan: FrequencyPlan,
+    /// Whether the PLL reported lock within `timing.lock_timeout_ms`
+    pub locked: bool,
+    /// VCO core covering `plan.vco`, as an index into
+    /// `frequency::VCO_CORES`
+    pub expected_core: Option<usize>,
+    /// VCO core the calibration picked, read back from RB_VCO_SEL, as an
+    /// index into `frequency::VCO_CORES`
+    pub vco_core: Option<usize>,
+    /// Capacitor bank code the calibration picked
+    pub capctrl: u8,
+    /// Amplitude code the calibration picked
+    pub daciset: u16,
+    /// N divider read back from R34 and R36, which differs from `plan.n`
+    /// if a bus error corrupted either write
+    pub n: u32,
+}
+
+impl VerifyReport {
+    /// Whether the device holds the intended N and locked on the intended
+    /// VCO core.
+    ///
+    /// Neighbouring cores overlap at their edges, so a plan right on a
+    /// boundary may legitimately calibrate onto the next core up. A wrong
+    /// N that still locks, e.g. off by a few counts, is only caught by the
+    /// N readback; a grossly wrong one also shows up as a wrong core or no
+    /// lock at all.
+    pub fn ok(&self) -> bool {
+        self.n == self.plan.n
+            && self.locked
+            && self.vco_core.is_some()
+            && self.vco_core == self.expected_core
+    }
+}
+
+/// Read the N divider back from R34 and R36, given `r0` as for `readback`
+pub fn read_n<S, C>(spi: &mut S, spi_cs: &mut C, buf: &mut [u8; 3], r0: u32) -> Result<u32, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+{
+    let mut regs = [0; 113];
+    for field in [fields::PLL_N_HI, fields::PLL_N_LO] {
+        let addr = field.addr as usize;
+        regs[addr] = readback(spi, spi_cs, buf, r0, field.addr)? as u32;
+    }
+    Ok(fields::pll_n(&regs))
+}
+
+/// Solve for `target`, program the whole map, wait for lock and read back
+/// what the VCO calibration settled on, for one call that says whether
+/// retuning worked.
+///
+/// The plan is recorded in the shadow map `regs`. Failing to lock is
+/// reported in the result rather than as `Error::LockTimeout`; errors are
+/// only returned when the target cannot be solved or the device cannot be
+/// programmed or read.
+#[allow(clippy::too_many_arguments)]
+pub fn program_and_verify<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    fref: Frequency,
+    target: Frequency,
+    mode: SolveMode,
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+) -> Result<VerifyReport, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let plan = frequency::set_frequency_with_mode(fref, regs, target, mode)?;
+    program_all(spi, spi_cs, delay, buf, regs, options, timing)?;
+    let r0 = regs[0];
+    let locked = match wait_for_lock(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        r0,
+        timing.lock_timeout_ms,
+        timing.lock_debounce,
+    ) {
+        Ok(()) => true,
+        Err(Error::LockTimeout) => false,
+        Err(e) => return Err(e),
+    };
+
+    let r110 = readback(spi, spi_cs, buf, r0, fields::RB_VCO_SEL.addr)?;
+    let r111 = readback(spi, spi_cs, buf, r0, fields::RB_VCO_CAPCTRL.addr)?;
+    let r112 = readback(spi, spi_cs, buf, r0, fields::RB_VCO_DACISET.addr)?;
+    let n = read_n(spi, spi_cs, buf, r0)?;
+    if n != plan.n {
+        defmt::warn!(
+            "N read back as {} instead of {}; R34/R36 may be corrupt",
+            n,
+            plan.n
+        );
+    }
+    let report = VerifyReport {
+        plan,
+        locked,
+        expected_core: frequency::vco_core(plan.vco),
+        vco_core: (fields::RB_VCO_SEL.extract(r110.into()) as usize).checked_sub(1),
+        capctrl: fields::RB_VCO_CAPCTRL.extract(r111.into()) as u8,
+        daciset: fields::RB_VCO_DACISET.extract(r112.into()) as u16,
+        n,
+    };
+    if !report.ok() {
+        defmt::warn!(
+            "Programming {} Hz did not verify: {}",
+            target.as_hz(),
+            report
+        );
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::Field;
+    use crate::mock;
+    use core::cell::Cell;
+    use embedded_hal_mock::MockError;
+    use std::io;
+
+    #[test]
+    fn reg_map_words_sit_at_their_addresses() {
+        for (addr, &word) in REG_MAP.iter().enumerate() {
+            assert_eq!(word >> 16, addr as u32, "R{} is out of place", addr);
+            assert_eq!(word >> 24, 0, "R{} is wider than 24 bits", addr);
+        }
+    }
+
+    #[test]
+    fn silicon_profiles_only_change_their_reserved_registers() {
+        assert_eq!(SAMPLED_SILICON.baseline(), REG_MAP);
+
+        let profile = SiliconProfile::by_name("datasheet").unwrap();
+        assert_eq!(profile, &DATASHEET_SILICON);
+        let regs = profile.baseline();
+        assert_eq!((REG_MAP[25], regs[25]), (0x190c2b, 0x190624));
+        let changed = (0..113).filter(|&addr| regs[addr] != REG_MAP[addr]);
+        assert!(changed.eq([25]));
+
+        assert_eq!(SiliconProfile::by_name("sampled"), Some(&SAMPLED_SILICON));
+        assert_eq!(SiliconProfile::by_name("rev-z"), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn register_map_round_trips_through_json() {
+        let mut regs = REG_MAP;
+        fields::OUTA_PWR.set(&mut regs, 12);
+        let json = serde_json::to_string(&RegisterMap(regs)).unwrap();
+        assert!(json.starts_with(&format!("[{},{},", REG_MAP[0], REG_MAP[1])));
+        let loaded: RegisterMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, RegisterMap(regs));
+
+        // Out of place, short and long maps are rejected
+        let mut swapped = regs;
+        swapped.swap(1, 2);
+        let json = serde_json::to_string(&RegisterMap(swapped)).unwrap();
+        assert!(serde_json::from_str::<RegisterMap>(&json).is_err());
+        let short = serde_json::to_string(&regs[..112]).unwrap();
+        assert!(serde_json::from_str::<RegisterMap>(&short).is_err());
+        let long = serde_json::to_string(&[&regs[..], &[0x710000]].concat()).unwrap();
+        assert!(serde_json::from_str::<RegisterMap>(&long).is_err());
+    }
+
+    #[test]
+    fn program_all_writes_map_descending_then_calibrates() {
+        let expected: Vec<u32> = REG_MAP
+            .iter()
+            .rev()
+            .copied()
+            .chain([FCAL_EN_ON, FCAL_EN_OFF])
+            .collect();
+        let mut spi = mock::Spi::new(&mock::writes(expected.iter().copied()));
+        let mut cs = mock::Pin::new(&mock::cs_frames(expected.len()));
+
+        program_all(
+            &mut spi,
+            &mut cs,
+            &mut mock::Delay::new(),
+            &mut [0; 3],
+            &REG_MAP,
+            &ProgramOptions::default(),
+            &ProgramTiming::default(),
+        )
+        .unwrap();
+
+        spi.done();
+        cs.done();
+    }
+
+    #[test]
+    fn program_all_calibrates_with_the_maps_own_r0() {
+        // Lock detect off MUXout, outputs muted until lock, phase sync on
+        let mut regs = REG_MAP;
+        regs[0] &= !MUXOUT_LD_SEL.mask();
+        regs[0] |= 1 << 9 | fields::VCO_PHASE_SYNC.mask();
+        let pulse = [regs[0] | FCAL_EN.mask(), regs[0] & !FCAL_EN.mask()];
+        let expected: Vec<u32> = regs.iter().rev().copied().chain(pulse).collect();
+        let mut spi = mock::Spi::new(&mock::writes(expected.iter().copied()));
+        let mut cs = mock::Pin::new(&mock::cs_frames(expected.len()));
+        let program = |spi: &mut mock::Spi, cs: &mut mock::Pin, regs: &[u32; 113]| {
+            program_all(
+                spi,
+                cs,
+                &mut mock::Delay::new(),
+                &mut [0; 3],
+                regs,
+                &ProgramOptions::default(),
+                &ProgramTiming::default(),
+            )
+        };
+        program(&mut spi, &mut cs, &regs).unwrap();
+        spi.done();
+        cs.done();
+
+        // An R0 that cannot calibrate is refused before anything is written
+        let mut spi = mock::Spi::new(&[]);
+        let mut cs = mock::Pin::new(&[]);
+        for (field, error) in [
+            (fields::RESET, Error::ResetAsserted),
+            (fields::POWERDOWN, Error::PoweredDown),
+        ] {
+            let mut bad = regs;
+            field.set(&mut bad, 1);
+            assert_eq!(program(&mut spi, &mut cs, &bad), Err(error));
+        }
+        spi.done();
+        cs.done();
+    }
+
+    fn read_id(r0: u16) -> Result<DeviceId, Error> {
+        let mut transactions = mock::writes([ID_R0]);
+        transactions.push(mock::read(0, r0));
+        let mut spi = mock::Spi::new(&transactions);
+        let mut cs = mock::Pin::new(&mock::cs_frames(2));
+        let id = read_device_id(&mut spi, &mut cs, &mut [0; 3]);
+        spi.done();
+        cs.done();
+        id
+    }
+
+    #[test]
+    fn compact_map_rebuilds_the_full_map() {
+        let words: [u32; PROGRAMMED_WORDS] = compact_map(&REG_MAP);
+        assert_eq!(PROGRAMMED_WORDS, 64);
+        assert!(words.iter().all(|w| w & 0xffff != 0));
+        assert!(words.windows(2).all(|w| w[0] >> 16 < w[1] >> 16));
+        assert_eq!(expand_map(&words), REG_MAP);
+        assert_eq!(default_map(), REG_MAP);
+    }
+
+    #[test]
+    fn readback_registers_decode_into_named_fields() {
+        let r0 = REG_MAP[0];
+        let r110 = (fields::RB_LD_VTUNE_LOCKED as u16) << 9 | 4 << 5;
+        let values = [0x1, 0x2, 0x3, r110, 0xb7, 0x12c];
+        let mut transactions = mock::writes([r0 & !FCAL_EN.mask() & !MUXOUT_LD_SEL.mask()]);
+        for (addr, &value) in (READBACK_FIRST..).zip(values.iter()) {
+            transactions.push(mock::read(addr, value));
+        }
+        transactions.extend(mock::writes([r0 & !FCAL_EN.mask()]));
+        let mut spi = mock::Spi::new(&transactions);
+        let mut cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+
+        let state = read_all_readback(&mut spi, &mut cs, &mut [0; 3], r0).unwrap();
+        spi.done();
+        cs.done();
+        assert_eq!(
+            state,
+            ReadbackState {
+                reserved: [0x1, 0x2, 0x3],
+                lock: LockDetect::Locked,
+                vco_core: Some(3),
+                capctrl: 0xb7,
+                daciset: 0x12c,
+            }
+        );
+        let unlocked = ReadbackState::decode([0, 0, 0, 3 << 9, 0, 0]);
+        assert_eq!(
+            (unlocked.lock, unlocked.vco_core),
+            (LockDetect::VtuneHigh, None)
+        );
+    }
+
+    #[test]
+    fn recalibrate_refuses_while_reset_is_set() {
+        let mut spi = mock::Spi::new(&[]);
+        let mut cs = mock::Pin::new(&[]);
+        let result = recalibrate(
+            &mut spi,
+            &mut cs,
+            &mut mock::Delay::new(),
+            &mut [0; 3],
+            RESET_ON,
+            &ProgramTiming::default(),
+        );
+        assert_eq!(result, Err(Error::ResetAsserted));
+        spi.done();
+        cs.done();
+    }
+
+    #[test]
+    fn wire_orders_pack_the_documented_and_reversed_streams() {
+        let word = 0x24_0a5c;
+        assert_eq!(WireOrder::MsbFirst.pack(word), word.reg());
+        assert_eq!(WireOrder::MsbFirst.pack(word), [0x24, 0x0a, 0x5c]);
+        assert_eq!(WireOrder::ReversedBytes.pack(word), [0x5c, 0x0a, 0x24]);
+        assert_eq!(WireOrder::ReversedBits.pack(word), [0x3a, 0x50, 0x24]);
+        let [_, rest @ ..] = (word.reverse_bits() >> 8).to_be_bytes();
+        assert_eq!(WireOrder::ReversedBits.pack(word), rest);
+
+        let mut spi = mock::Spi::new(&mock::writes([word]));
+        let mut cs = mock::Pin::new(&mock::cs_frames(1));
+        word.write_reg(&mut spi, &mut cs, &mut [0; 3]);
+        spi.done();
+        cs.done();
+    }
+
+    #[test]
+    fn missing_reference_is_told_apart_from_a_bad_map() {
+        let r110 = |lock: u32, vco_sel: u32| {
+            (lock << fields::RB_LD_VTUNE.shift | vco_sel << fields::RB_VCO_SEL.shift) as u16
+        };
+        let diagnose =
+            |r110| LockDiagnosis::from_readback(&ReadbackState::decode([0, 0, 0, r110, 0, 0]));
+        assert_eq!(
+            diagnose(r110(fields::RB_LD_VTUNE_LOCKED, 4)),
+            LockDiagnosis::Locked
+        );
+        assert_eq!(diagnose(r110(1, 4)), LockDiagnosis::NoReference);
+        assert_eq!(diagnose(r110(0, 0)), LockDiagnosis::NoReference);
+        assert_eq!(diagnose(r110(3, 0)), LockDiagnosis::NoReference);
+        assert_eq!(diagnose(r110(0, 7)), LockDiagnosis::BadConfig);
+        assert_eq!(diagnose(r110(3, 1)), LockDiagnosis::BadConfig);
+
+        for (value, expected) in [(r110(0, 0), Err(Error::NoReference)), (r110(3, 2), Ok(()))] {
+            let trace = mock::Trace::with_readback(value);
+            let result = check_reference(
+                &mut trace.spi(),
+                &mut HardwareCs,
+                &mut trace.delay(),
+                &mut [0; 3],
+                &ProgramTiming::default(),
+            );
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn lock_time_counts_until_the_first_locked_poll() {
+        // Each poll takes 40 us on the clock; the PLL locks on the fifth
+        let clock = Cell::new(1_000);
+        let now_us = || {
+            clock.set(clock.get() + 40);
+            clock.get()
+        };
+        let mut polls = 0;
+        let locks_after_five = || {
+            polls += 1;
+            Ok(polls >= 5)
+        };
+        assert_eq!(time_to_lock(locks_after_five, now_us, 10_000), Ok(200));
+        assert_eq!(polls, 5);
+
+        assert_eq!(
+            time_to_lock(|| Ok(false), now_us, 1_000),
+            Err(Error::LockTimeout)
+        );
+        let failing = || Err(Error::ReadFailed(110));
+        assert_eq!(
+            time_to_lock(failing, now_us, 1_000),
+            Err(Error::ReadFailed(110))
+        );
+    }
+
+    #[test]
+    fn forced_daciset_is_encoded_in_r16_and_r8() {
+        let mut regs = REG_MAP;
+        let transactions = mock::writes([0x10012c, 0x086000, 0x082000]);
+        let mut spi = mock::Spi::new(&transactions);
+        let mut cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+        let mut buf = [0; 3];
+        set_vco_daciset(&mut spi, &mut cs, &mut buf, &mut regs, Some(300)).unwrap();
+        assert_eq!((regs[16], regs[8]), (0x10012c, 0x086000));
+        set_vco_daciset(&mut spi, &mut cs, &mut buf, &mut regs, None).unwrap();
+        // Released, with the trimmed code left in place for next time
+        assert_eq!((regs[16], regs[8]), (0x10012c, REG_MAP[8]));
+        let before = regs;
+        assert_eq!(
+            set_vco_daciset(&mut spi, &mut cs, &mut buf, &mut regs, Some(512)),
+            Err(Error::InvalidDaciset(512))
+        );
+        assert_eq!(regs, before);
+        spi.done();
+        cs.done();
+
+        let transactions = mock::readback(REG_MAP[0], 112, 0x12c);
+        let mut spi = mock::Spi::new(&transactions);
+        let mut cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+        assert_eq!(
+            read_vco_daciset(&mut spi, &mut cs, &mut buf, REG_MAP[0]),
+            Ok(300)
+        );
+        spi.done();
+        cs.done();
+    }
+
+    #[test]
+    fn both_outputs_end_up_mirrored() {
+        let trace = mock::Trace::new();
+        let mut regs = REG_MAP;
+        fields::OUTB_MUX.set(&mut regs, fields::OUT_MUX_SYSREF);
+        fields::OUTB_PD.set(&mut regs, 1);
+        let (mut spi, mut buf) = (trace.spi(), [0; 3]);
+        set_both_outputs(
+            &mut spi,
+            &mut HardwareCs,
+            &mut buf,
+            &mut regs,
+            31,
+            OutputSource::Vco,
+            true,
+        )
+        .unwrap();
+
+        let pairs = [
+            (fields::OUTA_PWR, fields::OUTB_PWR),
+            (fields::OUTA_MUX, fields::OUTB_MUX),
+            (fields::OUTA_PD, fields::OUTB_PD),
+        ];
+        for (a, b) in pairs {
+            assert_eq!(a.get(&regs), b.get(&regs));
+        }
+        assert_eq!(fields::OUTB_MUX.get(&regs), fields::OUT_MUX_VCO);
+        assert_eq!(fields::OUTB_PWR.get(&regs), 31);
+        assert_eq!(fields::OUTB_PD.get(&regs), 0);
+        // The device was left with what the shadow map holds
+        let mut last = [None; 3];
+        for event in trace.events() {
+            if let mock::Event::Write(word) = event {
+                last[(word >> 16) as usize - 44] = Some(word);
+            }
+        }
+        assert_eq!(last, [Some(regs[44]), Some(regs[45]), Some(regs[46])]);
+
+        let before = regs;
+        let result = set_both_outputs(
+            &mut spi,
+            &mut HardwareCs,
+            &mut buf,
+            &mut regs,
+            64,
+            OutputSource::HighZ,
+            false,
+        );
+        assert_eq!(result, Err(Error::InvalidDrive(64)));
+        assert_eq!(regs, before);
+    }
+
+    #[test]
+    fn each_order_writes_addresses_in_sequence() {
+        let descending: Vec<u32> = (0..113).rev().collect();
+        let ascending: Vec<u32> = (0..113).collect();
+        for (order, expected) in [
+            (ProgramOrder::Descending, descending),
+            (ProgramOrder::Ascending, ascending),
+        ] {
+            let options = ProgramOptions {
+                order,
+                ..ProgramOptions::default()
+            };
+            let trace = mock::Trace::new();
+            let (mut spi, mut delay) = (trace.spi(), trace.delay());
+            program_all(
+                &mut spi,
+                &mut HardwareCs,
+                &mut delay,
+                &mut [0; 3],
+                &REG_MAP,
+                &options,
+                &ProgramTiming::default(),
+            )
+            .unwrap();
+            let addresses: Vec<_> = trace
+                .events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    mock::Event::Write(word) => Some(word >> 16),
+                    _ => None,
+                })
+                .collect();
+            // The map, then the calibration pulse on R0
+            assert_eq!(addresses[..113], expected[..], "{:?}", order);
+            assert_eq!(addresses[113..], [0, 0], "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn error_policy_aborts_or_lists_the_failed_writes() {
+        let written = |trace: &mock::Trace| -> Vec<u32> {
+            trace
+                .events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    mock::Event::Write(word) => Some(word >> 16),
+                    _ => None,
+                })
+                .collect()
+        };
+        let timing = ProgramTiming::default();
+        let abort = ProgramOptions::default();
+        let best_effort = ProgramOptions {
+            on_error: ErrorPolicy::BestEffort,
+            ..abort
+        };
+
+        // Nothing after the first failure, R78 in descending order
+        let trace = mock::Trace::with_failing_writes(&[44, 78]);
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        let result = program_all(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut [0; 3],
+            &REG_MAP,
+            &abort,
+            &timing,
+        );
+        assert_eq!(result, Err(Error::WriteFailed(78)));
+        assert_eq!(written(&trace), (79..113).rev().collect::<Vec<_>>());
+
+        // Everything else, including the calibration
+        let trace = mock::Trace::with_failing_writes(&[44, 78]);
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        let result = program_all(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut [0; 3],
+            &REG_MAP,
+            &best_effort,
+            &timing,
+        );
+        let failed = match result {
+            Err(Error::WritesFailed(failed)) => failed,
+            other => panic!("expected WritesFailed, got {:?}", other),
+        };
+        assert_eq!(failed.iter().collect::<Vec<_>>(), [44, 78]);
+        let expected: Vec<u32> = (0..113)
+            .rev()
+            .filter(|&addr| addr != 44 && addr != 78)
+            .chain([0, 0])
+            .collect();
+        assert_eq!(written(&trace), expected);
+    }
+
+    #[test]
+    fn two_passes_write_the_map_twice_then_calibrate_once() {
+        let options = ProgramOptions {
+            passes: 2,
+            ..ProgramOptions::default()
+        };
+        let timing = ProgramTiming {
+            post_program_ms: 17,
+            ..ProgramTiming::default()
+        };
+        let trace = mock::Trace::new();
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        program_all(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut [0; 3],
+            &REG_MAP,
+            &options,
+            &timing,
+        )
+        .unwrap();
+
+        let block: Vec<_> = REG_MAP.iter().rev().copied().collect();
+        let mut expected = Vec::new();
+        for _ in 0..2 {
+            expected.extend(block.iter().map(|&w| mock::Event::Write(w)));
+            expected.push(mock::Event::Delay(17));
+        }
+        expected.extend([
+            mock::Event::Write(FCAL_EN_ON),
+            mock::Event::Write(FCAL_EN_OFF),
+        ]);
+        // Leave out the settle delays after each write
+        let events: Vec<_> = trace
+            .events()
+            .into_iter()
+            .filter(|e| *e != mock::Event::Delay(timing.settle_ms))
+            .collect();
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn each_fcal_pulse_is_an_on_off_pair() {
+        for pulses in [0, 1, 3] {
+            let options = ProgramOptions {
+                fcal_pulses: pulses,
+                ..ProgramOptions::default()
+            };
+            let timing = ProgramTiming {
+                settle_ms: 3,
+                post_program_ms: 17,
+                ..ProgramTiming::default()
+            };
+            let trace = mock::Trace::new();
+            let (mut spi, mut delay) = (trace.spi(), trace.delay());
+            program_all(
+                &mut spi,
+                &mut HardwareCs,
+                &mut delay,
+                &mut [0; 3],
+                &REG_MAP,
+                &options,
+                &timing,
+            )
+            .unwrap();
+            // Everything after the map: the post-program wait, then the
+            // pulses, each write followed by the settle delay
+            let events = trace.events();
+            let start = events
+                .iter()
+                .position(|e| *e == mock::Event::Delay(17))
+                .unwrap();
+            use mock::Event::{Delay, Write};
+            let pulse = [Write(FCAL_EN_ON), Delay(3), Write(FCAL_EN_OFF), Delay(3)];
+            let expected: Vec<_> = (0..pulses).flat_map(|_| pulse).collect();
+            assert_eq!(events[start + 1..], expected[..], "{} pulses", pulses);
+        }
+    }
+
+    #[test]
+    fn write_regs_iter_writes_exactly_what_it_is_given() {
+        let timing = ProgramTiming {
+            settle_ms: 4,
+            ..ProgramTiming::default()
+        };
+        let words = [REG_MAP[36], REG_MAP[44], REG_MAP[12]];
+        let mut spi = mock::Spi::new(&mock::writes(words));
+        let mut cs = mock::Pin::new(&mock::cs_frames(3));
+        let trace = mock::Trace::new();
+        let mut delay = trace.delay();
+        write_regs_iter(
+            &mut spi,
+            &mut cs,
+            &mut delay,
+            &mut [0; 3],
+            words.iter().copied(),
+            &timing,
+            ErrorPolicy::AbortOnError,
+        )
+        .unwrap();
+        spi.done();
+        cs.done();
+        assert_eq!(trace.events(), [mock::Event::Delay(4); 3]);
+
+        // Past the map, or with the read bit set: stop there
+        for (bad, addr) in [(0x710000, 113), (0x80_0000 | REG_MAP[45], 0x80 | 45)] {
+            let mut spi = mock::Spi::new(&mock::writes([REG_MAP[44]]));
+            let mut cs = mock::Pin::new(&mock::cs_frames(1));
+            let result = write_regs_iter(
+                &mut spi,
+                &mut cs,
+                &mut mock::Delay::new(),
+                &mut [0; 3],
+                [REG_MAP[44], bad, REG_MAP[46]],
+                &timing,
+                ErrorPolicy::AbortOnError,
+            );
+            assert_eq!(result, Err(Error::InvalidAddress(addr)));
+            spi.done();
+            cs.done();
+        }
+    }
+
+    #[test]
+    fn program_range_writes_only_the_range() {
+        let timing = ProgramTiming::default();
+        let trace = mock::Trace::new();
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        let mut buf = [0; 3];
+        program_range(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut buf,
+            &REG_MAP,
+            44,
+            46,
+            &timing,
+        )
+        .unwrap();
+        let writes: Vec<_> = trace
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                mock::Event::Write(word) => Some(word),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(writes, [REG_MAP[46], REG_MAP[45], REG_MAP[44]]);
+
+        for (start, end, bad) in [(46, 44, 46), (100, 113, 113), (0, 255, 255)] {
+            let result = program_range(
+                &mut spi,
+                &mut HardwareCs,
+                &mut delay,
+                &mut buf,
+                &REG_MAP,
+                start,
+                end,
+                &timing,
+            );
+            assert_eq!(result, Err(Error::InvalidAddress(bad)));
+        }
+        assert_eq!(trace.events().len(), 6);
+    }
+
+    #[test]
+    fn spaced_cs_waits_between_consecutive_frames() {
+        let trace = mock::Trace::new();
+        let mut cs = SpacedCs::new(trace.pin(), trace.delay(), 50);
+        let mut spi = trace.spi();
+        let mut buf = [0; 3];
+        for word in [REG_MAP[44], REG_MAP[45]] {
+            word.write_reg(&mut spi, &mut cs, &mut buf);
+        }
+        use mock::Event::{DelayUs, Pin, Write};
+        assert_eq!(
+            trace.events(),
+            [
+                Pin(false),
+                Write(REG_MAP[44]),
+                Pin(true),
+                DelayUs(50),
+                Pin(false),
+                Write(REG_MAP[45]),
+                Pin(true),
+            ]
+        );
+
+        // A gap of 0 is no limit
+        let trace = mock::Trace::new();
+        let mut cs = SpacedCs::new(HardwareCs, trace.delay(), 0);
+        for word in [REG_MAP[44], REG_MAP[45]] {
+            word.write_reg(&mut trace.spi(), &mut cs, &mut buf);
+        }
+        assert_eq!(trace.events(), [Write(REG_MAP[44]), Write(REG_MAP[45])]);
+    }
+
+    #[test]
+    fn locked_cs_holds_the_bus_for_each_frame() {
+        let trace = mock::Trace::with_readback(0x2410);
+        let mut cs = LockedCs::new(trace.pin(), trace.lock());
+        let mut spi = trace.spi();
+        let mut buf = [0; 3];
+        REG_MAP[44].write_reg(&mut spi, &mut cs, &mut buf);
+        assert_eq!(read_reg(&mut spi, &mut cs, &mut buf, 0), Ok(0x2410));
+        use mock::Event::{Lock, Pin, Read, Write};
+        assert_eq!(
+            trace.events(),
+            [
+                Lock(true),
+                Pin(false),
+                Write(REG_MAP[44]),
+                Pin(true),
+                Lock(false),
+                Lock(true),
+                Pin(false),
+                Read(0),
+                Pin(true),
+                Lock(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_holds_reset_for_the_configured_width() {
+        let timing = ProgramTiming {
+            reset_ms: 23,
+            settle_ms: 2,
+            ..ProgramTiming::default()
+        };
+        let trace = mock::Trace::new();
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        reset(&mut spi, &mut HardwareCs, &mut delay, &mut [0; 3], &timing).unwrap();
+        use mock::Event::{Delay, Write};
+        assert_eq!(
+            trace.events(),
+            [Write(RESET_ON), Delay(23), Write(RESET_OFF), Delay(2)]
+        );
+    }
+
+    #[test]
+    fn shutdown_powers_down_outputs_and_chip_and_wake_undoes_it() {
+        let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+        let trace = mock::Trace::with_readback(locked);
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        let mut buf = [0; 3];
+        let written = |from: usize| -> Vec<u32> {
+            trace.events()[from..]
+                .iter()
+                .filter_map(|e| match *e {
+                    mock::Event::Write(word) => Some(word),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        shutdown(&mut spi, &mut HardwareCs, &mut buf, &REG_MAP).unwrap();
+        let words = written(0);
+        let (r44, r0) = (words[words.len() - 2], words[words.len() - 1]);
+        let mut device = REG_MAP;
+        device[44] = r44;
+        assert_eq!(r44 >> 16, 44);
+        assert_eq!(fields::OUTA_PD.get(&device), 1);
+        assert_eq!(fields::OUTB_PD.get(&device), 1);
+        assert_eq!(r0 >> 16, 0);
+        assert_ne!(r0 & fields::POWERDOWN.mask(), 0);
+        assert_eq!(r0 & FCAL_EN.mask(), 0);
+
+        let woken = trace.events().len();
+        wake_and_lock(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut buf,
+            &REG_MAP,
+            &ProgramTiming::default(),
+        )
+        .unwrap();
+        let words = written(woken);
+        assert_eq!(words[0] >> 16, 0);
+        assert_eq!(words[0] & fields::POWERDOWN.mask(), 0);
+        assert!(words.contains(&FCAL_EN_ON));
+        assert_eq!(words[words.len() - 1], REG_MAP[44]);
+    }
+
+    #[test]
+    fn holding_outputs_brackets_the_frequency_writes() {
+        let fref = Frequency::from_mhz(100);
+        let target = Frequency::from_mhz(10_000);
+        let mut expected = REG_MAP;
+        frequency::set_frequency(fref, &mut expected, target).unwrap();
+        let retune_with = |readback: u16, policy| {
+            let trace = mock::Trace::with_readback(readback);
+            let mut regs = REG_MAP;
+            let result = change_frequency(
+                &mut trace.spi(),
+                &mut HardwareCs,
+                &mut trace.delay(),
+                &mut [0; 3],
+                &mut regs,
+                fref,
+                target,
+                SolveMode::Auto,
+                policy,
+                &ProgramTiming {
+                    lock_timeout_ms: 3,
+                    ..ProgramTiming::default()
+                },
+            );
+            (result.map(|plan| plan.output), trace.events())
+        };
+        let writes = |events: &[mock::Event]| -> Vec<u32> {
+            events
+                .iter()
+                .filter_map(|e| match *e {
+                    mock::Event::Write(word) => Some(word),
+                    _ => None,
+                })
+                .collect()
+        };
+        let retune_words = |r44| {
+            RETUNE_REGS
+                .iter()
+                .map(|&addr| if addr == 44 { r44 } else { expected[addr] })
+                .collect::<Vec<_>>()
+        };
+        let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+
+        // Muted before the first frequency write, held muted through R44,
+        // and unmuted only after the last lock read
+        let (result, events) = retune_with(locked, RetunePolicy::HoldOutputs);
+        assert_eq!(result, Ok(target));
+        let words = writes(&events);
+        assert_eq!(words[0], outputs_off(&REG_MAP));
+        assert_eq!(
+            words[1..=RETUNE_REGS.len()],
+            retune_words(outputs_off(&expected))
+        );
+        assert_eq!(words.last(), Some(&expected[44]));
+        let last_read = events
+            .iter()
+            .rposition(|e| matches!(e, mock::Event::Read(_)));
+        let unmuted = events.len() - 1;
+        assert!(last_read.unwrap() < unmuted);
+
+        // Without a lock the outputs stay muted
+        let (result, events) = retune_with(0, RetunePolicy::HoldOutputs);
+        assert_eq!(result, Err(Error::LockTimeout));
+        assert!(!writes(&events).contains(&expected[44]));
+
+        // Live leaves them alone: R44 goes out once, as planned
+        let (result, events) = retune_with(locked, RetunePolicy::Live);
+        assert_eq!(result, Ok(target));
+        let words = writes(&events);
+        assert_eq!(words[..RETUNE_REGS.len()], retune_words(expected[44]));
+        assert_eq!(words.iter().filter(|&&w| w >> 16 == 44).count(), 1);
+    }
+
+    #[test]
+    fn power_on_delay_comes_before_the_first_write() {
+        let timing = ProgramTiming {
+            power_on_ms: 37,
+            ..ProgramTiming::default()
+        };
+        let trace = mock::Trace::new();
+        let (mut spi, mut delay, mut ce) = (trace.spi(), trace.delay(), trace.pin());
+        bring_up(
+            &mut spi,
+            &mut HardwareCs,
+            &mut ce,
+            &mut delay,
+            &mut [0; 3],
+            &REG_MAP,
+            &ProgramOptions::default(),
+            &timing,
+        )
+        .unwrap();
+        let events = trace.events();
+        let first_write = events
+            .iter()
+            .position(|e| matches!(e, mock::Event::Write(_)))
+            .unwrap();
+        use mock::Event::{Delay, Pin, Write};
+        assert_eq!(
+            events[first_write - 2..=first_write],
+            [Pin(true), Delay(37), Write(RESET_ON)]
+        );
+    }
+
+    #[test]
+    fn probe_tells_a_live_device_from_a_floating_muxout() {
+        let probe_with = |r0: u16| {
+            let mut transactions = mock::writes([ID_R0]);
+            transactions.push(mock::read(0, r0));
+            let mut spi = mock::Spi::new(&transactions);
+            let mut cs = mock::Pin::new(&mock::cs_frames(2));
+            let result = probe(&mut spi, &mut cs, &mut [0; 3]);
+            spi.done();
+            cs.done();
+            result
+        };
+        assert_eq!(probe_with((ID_R0 & 0xffff) as u16), Ok(()));
+        for absent in [0x0000, 0xffff, 0x2218] {
+            assert_eq!(
+                probe_with(absent),
+                Err(Error::NotPresent),
+                "{:#06x}",
+                absent
+            );
+        }
+
+        // Bring-up stops before resetting a device that is not there
+        let options = ProgramOptions {
+            probe: true,
+            ..ProgramOptions::default()
+        };
+        let trace = mock::Trace::with_readback(0xffff);
+        let (mut spi, mut delay, mut ce) = (trace.spi(), trace.delay(), trace.pin());
+        let result = bring_up(
+            &mut spi,
+            &mut HardwareCs,
+            &mut ce,
+            &mut delay,
+            &mut [0; 3],
+            &REG_MAP,
+            &options,
+            &ProgramTiming::default(),
+        );
+        assert_eq!(result, Err(Error::NotPresent));
+        assert!(!trace.events().contains(&mock::Event::Write(RESET_ON)));
+    }
+
+    #[test]
+    fn device_id_decodes_r0_readback() {
+        let family = DeviceId {
+            raw: 0x2410,
+            identity: Identity::Lmx2594Family,
+        };
+        assert_eq!(read_id(0x2410), Ok(family));
+        let other = DeviceId {
+            raw: 0x2218,
+            identity: Identity::Unknown,
+        };
+        assert_eq!(read_id(0x2218), Ok(other));
+        assert_eq!(read_id(0x0000), Err(Error::NotPresent));
+        assert_eq!(read_id(0xffff), Err(Error::NotPresent));
+    }
+
+    #[test]
+    fn bus_and_pin_failures_are_errors_not_panics() {
+        let (mut spi, mut cs, mut buf) = (mock::FailingSpi, HardwareCs, [0; 3]);
+        let mut delay = mock::Delay::new();
+        let timing = ProgramTiming::default();
+
+        REG_MAP[0].write_reg(&mut spi, &mut cs, &mut buf);
+        let mut regs = REG_MAP;
+        let failed = |addr: Field| Err(Error::WriteFailed(addr.addr));
+        let (r44, r45) = (Err(Error::WriteFailed(44)), Err(Error::WriteFailed(45)));
+        assert_eq!(mute(&mut spi, &mut cs, &mut buf, &regs), r44);
+        assert_eq!(unmute(&mut spi, &mut cs, &mut buf, &regs), r44);
+        assert_eq!(shutdown(&mut spi, &mut cs, &mut buf, &regs), r44);
+        assert_eq!(
+            enable_output(&mut spi, &mut cs, &mut buf, &mut regs, Output::A, true),
+            r44
+        );
+        let source = OutputSource::Vco;
+        assert_eq!(
+            set_output_source(&mut spi, &mut cs, &mut buf, &mut regs, Output::A, source),
+            r45
+        );
+        assert_eq!(
+            set_both_outputs(&mut spi, &mut cs, &mut buf, &mut regs, 31, source, true),
+            r44
+        );
+        let held = HeldOutputs::hold(&mut regs);
+        assert_eq!(
+            release_outputs(&mut spi, &mut cs, &mut buf, &mut regs, held),
+            r45
+        );
+        let order = MashOrder::Integer;
+        assert_eq!(
+            set_mash_order(&mut spi, &mut cs, &mut buf, &mut regs, order),
+            r44
+        );
+        assert_eq!(
+            power::set_output_drive(&mut spi, &mut cs, &mut buf, &mut regs, Output::A, 31),
+            failed(fields::OUTA_PWR)
+        );
+        assert_eq!(
+            power::set_output_boost(&mut spi, &mut cs, &mut buf, &mut regs, 1),
+            failed(fields::OUT_ISET)
+        );
+        let profile = crate::profile::Profile::FastLock;
+        assert_eq!(
+            crate::profile::set_profile(&mut spi, &mut cs, &mut buf, &mut regs, profile),
+            failed(fields::QUICK_RECAL_EN)
+        );
+        let mut req = mock::Pin::new(&[]);
+        assert_eq!(
+            crate::sysref::sysref_pulse(&mut spi, &mut cs, &mut buf, &mut regs, &mut req, 1),
+            failed(fields::SYSREF_PULSE_CNT)
+        );
+        req.done();
+        assert_eq!(
+            read_reg(&mut spi, &mut cs, &mut buf, 110),
+            Err(Error::ReadFailed(110))
+        );
+        assert_eq!(
+            readback(&mut spi, &mut cs, &mut buf, REG_MAP[0], 110),
+            Err(Error::WriteFailed(0))
+        );
+        assert_eq!(
+            reset(&mut spi, &mut cs, &mut delay, &mut buf, &timing),
+            Err(Error::WriteFailed(0))
+        );
+        let options = ProgramOptions::default();
+        assert_eq!(
+            program_all(&mut spi, &mut cs, &mut delay, &mut buf, &REG_MAP, &options, &timing),
+            Err(Error::WriteFailed(112))
+        );
+        let best_effort = ProgramOptions {
+            on_error: ErrorPolicy::BestEffort,
+            ..options
+        };
+        let result = program_all(
+            &mut spi,
+            &mut cs,
+            &mut delay,
+            &mut buf,
+            &REG_MAP,
+            &best_effort,
+            &timing,
+        );
+        assert!(matches!(result, Err(Error::WritesFailed(f)) if f.len() == 113));
+
+        let probing = ProgramOptions {
+            probe: true,
+            ..options
+        };
+        let power_on = mock::PinTransaction::set(mock::State::High);
+        let gpio_error = MockError::Io(io::ErrorKind::Other);
+        let mut ce = mock::Pin::new(&[
+            power_on.clone(),
+            power_on.clone(),
+            power_on.with_error(gpio_error),
+        ]);
+        for options in [options, probing] {
+            let result = bring_up(
+                &mut spi, &mut cs, &mut ce, &mut delay, &mut buf, &REG_MAP, &options, &timing,
+            );
+            assert_eq!(result, Err(Error::WriteFailed(0)));
+        }
+        let result = bring_up(
+            &mut spi, &mut cs, &mut ce, &mut delay, &mut buf, &REG_MAP, &options, &timing,
+        );
+        assert_eq!(result, Err(Error::PinFailed));
+        ce.done();
+    }
+
+    #[test]
+    fn program_all_waits_before_calibrating() {
+        let trace = mock::Trace::new();
+        let timing = ProgramTiming {
+            settle_ms: 1,
+            post_program_ms: 25,
+            ..ProgramTiming::default()
+        };
+        program_all(
+            &mut trace.spi(),
+            &mut HardwareCs,
+            &mut trace.delay(),
+            &mut [0; 3],
+            &REG_MAP[..2],
+            &ProgramOptions::default(),
+            &timing,
+        )
+        .unwrap();
+        use mock::Event::{Delay, Write};
+        assert_eq!(
+            trace.events(),
+            [
+                Write(REG_MAP[1]),
+                Delay(1),
+                Write(REG_MAP[0]),
+                Delay(1),
+                Delay(25),
+                Write(FCAL_EN_ON),
+                Delay(1),
+                Write(FCAL_EN_OFF),
+                Delay(1),
+            ]
+        );
+    }
+
+    /// Records the stages bring-up reports
+    #[derive(Default)]
+    struct StageLog(Vec<Stage>);
+
+    impl Logger for StageLog {
+        fn log_write(&mut self, _addr: u8, _word: u32) {}
+
+        fn log_freq(&mut self, _label: &str, _freq: Frequency) {}
+
+        fn log_error(&mut self, _error: &Error) {}
+
+        fn log_stage(&mut self, stage: Stage) {
+            self.0.push(stage);
+        }
+    }
+
+    #[test]
+    fn low_power_boot_powers_the_outputs_up_after_lock() {
+        let options = ProgramOptions {
+            boot: BootPolicy::LowPower,
+            ..ProgramOptions::default()
+        };
+        let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+        let trace = mock::Trace::with_readback(locked);
+        let (mut spi, mut delay, mut ce) = (trace.spi(), trace.delay(), trace.pin());
+        bring_up(
+            &mut spi,
+            &mut HardwareCs,
+            &mut ce,
+            &mut delay,
+            &mut [0; 3],
+            &REG_MAP,
+            &options,
+            &ProgramTiming::default(),
+        )
+        .unwrap();
+
+        let events = trace.events();
+        let last_read = events
+            .iter()
+            .rposition(|e| *e == mock::Event::Read(110))
+            .unwrap();
+        let writes = |events: &[mock::Event]| -> Vec<u32> {
+            events
+                .iter()
+                .filter_map(|e| match *e {
+                    mock::Event::Write(word) => Some(word),
+                    _ => None,
+                })
+                .collect()
+        };
+        // Until lock R44 only ever had both outputs powered down
+        let mut staged = REG_MAP;
+        fields::OUTA_PD.set(&mut staged, 1);
+        fields::OUTB_PD.set(&mut staged, 1);
+        let before: Vec<_> = writes(&events[..last_read])
+            .into_iter()
+            .filter(|w| w >> 16 == 44)
+            .collect();
+        assert_eq!(before, [staged[44]]);
+        // After it, once readback has put R0 back, and nothing else
+        let r0 = REG_MAP[0] & !FCAL_EN.mask();
+        assert_eq!(writes(&events[last_read..]), [r0, REG_MAP[44]]);
+
+        // A map with both outputs off already has nothing to restore
+        let trace = mock::Trace::with_readback(locked);
+        let (mut spi, mut delay, mut ce) = (trace.spi(), trace.delay(), trace.pin());
+        bring_up(
+            &mut spi,
+            &mut HardwareCs,
+            &mut ce,
+            &mut delay,
+            &mut [0; 3],
+            &staged,
+            &options,
+            &ProgramTiming::default(),
+        )
+        .unwrap();
+        let events = trace.events();
+        let last_read = events
+            .iter()
+            .rposition(|e| *e == mock::Event::Read(110))
+            .unwrap();
+        assert_eq!(writes(&events[last_read..]), [r0]);
+    }
+
+    #[test]
+    fn bring_up_reports_each_stage() {
+        for boot in [BootPolicy::Normal, BootPolicy::LowPower] {
+            let low_power = boot == BootPolicy::LowPower;
+            let mut staged = REG_MAP;
+            if low_power {
+                fields::OUTA_PD.set(&mut staged, 1);
+                fields::OUTB_PD.set(&mut staged, 1);
+            }
+            let mut transactions = mock::writes(
+                [RESET_ON, RESET_OFF]
+                    .iter()
+                    .chain(staged.iter().rev())
+                    .chain([FCAL_EN_ON, FCAL_EN_OFF].iter())
+                    .copied(),
+            );
+            if low_power {
+                let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+                for _ in 0..DEFAULT_LOCK_DEBOUNCE {
+                    transactions.extend(mock::readback(REG_MAP[0], 110, locked));
+                }
+                transactions.extend(mock::writes([REG_MAP[44]]));
+            }
+            let mut spi = mock::Spi::new(&transactions);
+            let mut cs_states = vec![mock::PinTransaction::set(mock::State::High)];
+            cs_states.extend(mock::cs_frames(transactions.len()));
+            let mut cs = mock::Pin::new(&cs_states);
+            let mut ce = mock::Pin::new(&[mock::PinTransaction::set(mock::State::High)]);
+            let mut log = StageLog::default();
+            let options = ProgramOptions {
+                boot,
+                ..ProgramOptions::default()
+            };
+            bring_up_logged(
+                &mut spi,
+                &mut cs,
+                &mut ce,
+                &mut mock::Delay::new(),
+                &mut [0; 3],
+                &REG_MAP,
+                &options,
+                &ProgramTiming::default(),
+                &mut log,
+            )
+            .unwrap();
+            spi.done();
+            cs.done();
+            ce.done();
+
+            let mut expected = vec![
+                Stage::PowerOn,
+                Stage::Reset,
+                Stage::Program { words: 113 },
+                Stage::Calibrate { pulses: 1 },
+            ];
+            if low_power {
+                expected.extend([Stage::LockWait, Stage::Locked(true)].iter());
+            }
+            assert_eq!(log.0, expected);
+        }
+    }
+
+    #[test]
+    fn high_z_outputs_stay_off_the_line_until_released() {
+        let readback = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+        let trace = mock::Trace::with_readback(readback);
+        let mut regs = REG_MAP;
+        let held = HeldOutputs::hold(&mut regs);
+        let options = ProgramOptions {
+            boot: BootPolicy::HighZ,
+            ..ProgramOptions::default()
+        };
+        let (mut spi, mut buf) = (trace.spi(), [0; 3]);
+        bring_up_logged(
+            &mut spi,
+            &mut HardwareCs,
+            &mut trace.pin(),
+            &mut trace.delay(),
+            &mut buf,
+            &regs,
+            &options,
+            &ProgramTiming::default(),
+            &mut StageLog::default(),
+        )
+        .unwrap();
+        let high_z = |word: u32| match word >> 16 {
+            45 => fields::OUTA_MUX.extract(word) == fields::OUT_MUX_HIGH_Z,
+            46 => fields::OUTB_MUX.extract(word) == fields::OUT_MUX_HIGH_Z,
+            _ => true,
+        };
+        let writes = |events: Vec<mock::Event>| {
+            events.into_iter().filter_map(|event| match event {
+                mock::Event::Write(word) => Some(word),
+                _ => None,
+            })
+        };
+        let programmed = trace.events().len();
+        assert!(writes(trace.events()).all(high_z));
+
+        release_outputs(&mut spi, &mut HardwareCs, &mut buf, &mut regs, held).unwrap();
+        assert_eq!(regs, REG_MAP);
+        let released: Vec<u32> = writes(trace.events().split_off(programmed)).collect();
+        assert_eq!(released, [REG_MAP[45], REG_MAP[46]]);
+        assert!(!released.iter().all(|&word| high_z(word)));
+    }
+
+    #[test]
+    fn map_id_names_one_map() {
+        assert_eq!(map_id(&REG_MAP), 0x9614);
+        assert_eq!(map_id(&default_map()), map_id(&REG_MAP));
+        let mut regs = REG_MAP;
+        regs[44] ^= 1;
+        assert_ne!(map_id(&regs), map_id(&REG_MAP));
+        regs[44] ^= 1;
+        assert_eq!(map_id(&regs), 0x9614);
+    }
+
+    #[test]
+    fn blank_or_corrupt_maps_are_never_programmed() {
+        let erased = [0xff_ffff; 113];
+        let mut misplaced = REG_MAP;
+        misplaced.swap(5, 6);
+        let mut bad_r0 = REG_MAP;
+        bad_r0[0] &= !R0_RESERVED_MASK;
+        for (regs, expected) in [
+            ([0; 113], ValidationError::Blank),
+            (erased, ValidationError::Blank),
+            (misplaced, ValidationError::Misplaced(5)),
+            (bad_r0, ValidationError::BadR0(0x00000c)),
+        ] {
+            let mut spi = mock::Spi::new(&[]);
+            let mut cs = mock::Pin::new(&[]);
+            let result = program_all(
+                &mut spi,
+                &mut cs,
+                &mut mock::Delay::new(),
+                &mut [0; 3],
+                &regs,
+                &ProgramOptions::default(),
+                &ProgramTiming::default(),
+            );
+            assert_eq!(result, Err(Error::InvalidMap(expected)));
+            spi.done();
+            cs.done();
+        }
+        assert_eq!(validate_map(&REG_MAP), Ok(()));
+        // OUT_MUTE (bit 9) is a setting, but bit 4 is reserved
+        let mut muted = REG_MAP;
+        muted[0] |= 1 << 9;
+        assert_eq!(validate_map(&muted), Ok(()));
+        muted[0] &= !(1 << 4);
+        assert_eq!(validate_map(&muted), Err(ValidationError::BadR0(muted[0])));
+        // A single erased word is out of place rather than blank
+        let mut torn = REG_MAP;
+        torn[112] = 0xff_ffff;
+        assert_eq!(validate_map(&torn), Err(ValidationError::Misplaced(112)));
+    }
+
+    #[test]
+    fn verify_reads_back_in_descending_order_after_reset() {
+        let options = ProgramOptions {
+            verify: true,
+            ..ProgramOptions::default()
+        };
+        let timing = ProgramTiming::default();
+        let readback_r0 = REG_MAP[0] & !MUXOUT_LD_SEL.mask();
+
+        // Every register reading back as written passes, with MUXout put
+        // in readback mode before the first of them
+        let mut expected = mock::writes([RESET_ON, RESET_OFF, readback_r0 & !FCAL_EN.mask()]);
+        let mut frames = expected.len();
+        for (addr, &word) in REG_MAP.iter().enumerate().rev() {
+            let word = if addr == 0 { readback_r0 } else { word };
+            expected.extend(mock::writes([word]));
+            frames += 1;
+            if (1..107).contains(&addr) {
+                expected.push(mock::read(addr as u8, word as u16));
+                frames += 1;
+            }
+        }
+        expected.extend(mock::writes([FCAL_EN_ON, FCAL_EN_OFF]));
+        frames += 2;
+        let mut spi = mock::Spi::new(&expected);
+        let mut cs = mock::Pin::new(&mock::cs_frames(frames));
+        let (mut delay, mut buf) = (mock::Delay::new(), [0; 3]);
+        reset(&mut spi, &mut cs, &mut delay, &mut buf, &timing).unwrap();
+        let result = program_all(
+            &mut spi, &mut cs, &mut delay, &mut buf, &REG_MAP, &options, &timing,
+        );
+        assert_eq!(result, Ok(()));
+        spi.done();
+        cs.done();
+
+        // A mismatch stops at the first register checked, R106
+        let trace = mock::Trace::with_readback(!(REG_MAP[106] as u16));
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        reset(&mut spi, &mut HardwareCs, &mut delay, &mut buf, &timing).unwrap();
+        let result = program_all(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut buf,
+            &REG_MAP,
+            &options,
+            &timing,
+        );
+        assert_eq!(result, Err(Error::VerifyFailed(106)));
+    }
+
+    #[test]
+    fn program_all_rejects_a_map_over_budget() {
+        let mut spi = mock::Spi::new(&[]);
+        let mut cs = mock::Pin::new(&[]);
+        // 113 settles for the map, the post-program wait and 2 settles for
+        // the calibration pulse
+        let timing = ProgramTiming {
+            budget_ms: Some(1159),
+            ..ProgramTiming::default()
+        };
+        let options = ProgramOptions::default();
+        assert_eq!(program_time_ms(REG_MAP.len(), &options, &timing), 1160);
+
+        let result = program_all(
+            &mut spi,
+            &mut cs,
+            &mut mock::Delay::new(),
+            &mut [0; 3],
+            &REG_MAP,
+            &options,
+            &timing,
+        );
+        assert_eq!(result, Err(Error::OverBudget(1160)));
+        spi.done();
+        cs.done();
+    }
+
+    #[test]
+    fn integer_mash_order_clears_the_fraction() {
+        let mut regs = REG_MAP;
+        fields::set_frac(&mut regs, 0x12345, 0x23456).unwrap();
+        let expected = [
+            REG_MAP[44] & !fields::MASH_ORDER.mask(),
+            43 << 16,
+            42 << 16,
+            39 << 16 | 1,
+            38 << 16,
+        ];
+        let mut spi = mock::Spi::new(&mock::writes(expected.iter().copied()));
+        let mut cs = mock::Pin::new(&mock::cs_frames(expected.len()));
+
+        set_mash_order(
+            &mut spi,
+            &mut cs,
+            &mut [0; 3],
+            &mut regs,
+            MashOrder::Integer,
+        )
+        .unwrap();
+        assert_eq!((fields::pll_num(&regs), fields::pll_den(&regs)), (0, 1));
+        spi.done();
+        cs.done();
+    }
+
+    /// Transactions `program_and_verify` makes after programming, with
+    /// `locked` as the lock state in every R110 reading and `n` as the N
+    /// divider
+    fn verify_reads(
+        r0: u32,
+        polls: usize,
+        locked: bool,
+        core: u16,
+        n: u32,
+    ) -> Vec<mock::SpiTransaction> {
+        let ld = if locked { 2 << 9 } else { 0 };
+        let mut reads = vec![];
+        for _ in 0..polls {
+            reads.extend(mock::readback(r0, 110, ld));
+        }
+        reads.extend(mock::readback(r0, 110, ld | core << 5));
+        reads.extend(mock::readback(r0, 111, 0x5a));
+        reads.extend(mock::readback(r0, 112, 0x12c));
+        reads.extend(mock::readback(r0, 34, (n >> 16) as u16));
+        reads.extend(mock::readback(r0, 36, n as u16));
+        reads
+    }
+
+    #[test]
+    fn fast_hop_writes_only_n_and_the_fraction() {
+        let fref = Frequency::from_mhz(100);
+        let mut regs = REG_MAP;
+        frequency::set_frequency(fref, &mut regs, Frequency::from_mhz(10_000)).unwrap();
+        let target = Frequency::from_mhz(10_050);
+        let mut expected = regs;
+        frequency::set_frequency(fref, &mut expected, target).unwrap();
+
+        let words = HOP_REGS.iter().map(|&addr| expected[addr]);
+        let transactions = mock::writes(words.chain([FCAL_EN_ON, FCAL_EN_OFF]));
+        let mut spi = mock::Spi::new(&transactions);
+        let mut cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+        let hop = fast_hop(
+            &mut spi,
+            &mut cs,
+            &mut mock::Delay::new(),
+            &mut [0; 3],
+            &mut regs,
+            fref,
+            target,
+            RetunePolicy::default(),
+            &ProgramTiming::default(),
+        )
+        .unwrap();
+        spi.done();
+        cs.done();
+        assert!(matches!(hop, Hop::Fast(plan) if plan.output == target));
+        assert_eq!(regs, expected);
+
+        // A new channel divider or VCO core needs the full sequence
+        for mhz in [5_000, 12_000] {
+            let hop = plan_fast_hop(fref, &regs, Frequency::from_mhz(mhz));
+            assert_eq!(hop, Ok(None));
+        }
+    }
+
+    /// Run `program_and_verify`, reading back `locked` as the lock state
+    /// and N with the bits in `n_error` flipped
+    fn program_and_verify_with(locked: bool, polls: usize, n_error: u32) -> VerifyReport {
+        let fref = Frequency::from_mhz(100);
+        let target = Frequency::from_mhz(10_000);
+        let mut expected = REG_MAP;
+        frequency::set_frequency(fref, &mut expected, target).unwrap();
+        let core = frequency::vco_core(target).unwrap() as u16 + 1;
+
+        let words = expected
+            .iter()
+            .rev()
+            .copied()
+            .chain([FCAL_EN_ON, FCAL_EN_OFF]);
+        let mut transactions = mock::writes(words);
+        let n = fields::pll_n(&expected) ^ n_error;
+        transactions.extend(verify_reads(expected[0], polls, locked, core, n));
+        let mut spi = mock::Spi::new(&transactions);
+        let mut cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+
+        let mut regs = REG_MAP;
+        let timing = ProgramTiming {
+            lock_timeout_ms: 3,
+            ..ProgramTiming::default()
+        };
+        let report = program_and_verify(
+            &mut spi,
+            &mut cs,
+            &mut mock::Delay::new(),
+            &mut [0; 3],
+            &mut regs,
+            fref,
+            target,
+            SolveMode::Auto,
+            &ProgramOptions::default(),
+            &timing,
+        )
+        .unwrap();
+        spi.done();
+        cs.done();
+        assert_eq!(regs, expected);
+        report
+    }
+
+    #[test]
+    fn program_and_verify_reports_lock_and_core() {
+        let report = program_and_verify_with(true, DEFAULT_LOCK_DEBOUNCE as usize, 0);
+        assert!(report.ok());
+        assert!(report.locked);
+        assert_eq!(report.vco_core, report.expected_core);
+        assert_eq!(report.capctrl, 0x5a);
+        assert_eq!(report.daciset, 0x12c);
+        assert_eq!(report.n, report.plan.n);
+    }
+
+    #[test]
+    fn program_and_verify_reports_lock_timeout() {
+        // Polled at 0 to 3 ms, then read once more for the report
+        let report = program_and_verify_with(false, 4, 0);
+        assert!(!report.ok());
+        assert!(!report.locked);
+    }
+
+    #[test]
+    fn a_single_locked_read_is_not_lock() {
+        let r0 = REG_MAP[0];
+        let ld = |locked: bool| if locked { 2 << 9 } else { 0 };
+        let wait = |reads: &[bool], timeout_ms, debounce| {
+            let mut transactions = vec![];
+            for &locked in reads {
+                transactions.extend(mock::readback(r0, 110, ld(locked)));
+            }
+            let mut spi = mock::Spi::new(&transactions);
+            let mut cs = mock::Pin::new(&mock::cs_frames(transactions.len()));
+            let result = wait_for_lock(
+                &mut spi,
+                &mut cs,
+                &mut mock::Delay::new(),
+                &mut [0; 3],
+                r0,
+                timeout_ms,
+                debounce,
+            );
+            spi.done();
+            cs.done();
+            result
+        };
+
+        // A glitch, then lock that holds for the debounce count
+        let reads = [false, true, false, true, true, true];
+        assert_eq!(wait(&reads, 5, 3), Ok(()));
+        // The glitch alone, with time running out before lock holds
+        assert_eq!(wait(&reads[..3], 2, 3), Err(Error::LockTimeout));
+        // Without debouncing the glitch is taken as lock
+        assert_eq!(wait(&reads[..2], 2, 1), Ok(()));
+    }
+
+    #[test]
+    fn program_and_verify_flags_a_corrupt_n() {
+        // Locked on the right core, but R34 read back with a bit flipped
+        let report = program_and_verify_with(true, DEFAULT_LOCK_DEBOUNCE as usize, 1 << 16);
+        assert!(!report.ok());
+        assert!(report.locked);
+        assert_eq!(report.vco_core, report.expected_core);
+        assert_eq!(report.n, report.plan.n ^ 1 << 16);
     }
 }
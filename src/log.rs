@@ -0,0 +1,200 @@
+//! Diagnostics hook for the programming path.
+//!
+//! The driver reports what it writes and what goes wrong through a
+//! `Logger`, so the events can be routed to any sink: defmt over RTT by
+//! default, a UART, or nowhere at all.
+//!
+//! Bring-up also reports each `Stage` it enters. `DefmtLogger` only logs
+//! these with the `diag` feature, which the firmware pairs with defmt
+//! timestamps to trace a first-time bring-up.
+
+use crate::frequency::Frequency;
+use crate::lmx2594::Error;
+
+/// A step of the power-up sequence, as reported to `Logger::log_stage`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Stage {
+    /// Powering on through CE, then waiting for the supplies
+    PowerOn,
+    /// Pulsing RESET
+    Reset,
+    /// Writing the register map, `words` writes over all passes
+    Program { words: u32 },
+    /// Pulsing FCAL_EN `pulses` times to calibrate the VCO
+    Calibrate { pulses: u32 },
+    /// Waiting for the PLL to lock
+    LockWait,
+    /// The lock wait ended, locked or timed out
+    Locked(bool),
+}
+
+/// Receives diagnostic events from the driver
+pub trait Logger {
+    /// A register word was written to the device
+    fn log_write(&mut self, addr: u8, word: u32);
+    /// A frequency of note was reached or computed, e.g. the output
+    fn log_freq(&mut self, label: &str, freq: Frequency);
+    /// An operation failed
+    fn log_error(&mut self, error: &Error);
+    /// Bring-up entered `stage`
+    fn log_stage(&mut self, stage: Stage);
+}
+
+/// Logs every event with defmt; this is what the driver uses unless told
+/// otherwise
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DefmtLogger;
+
+impl Logger for DefmtLogger {
+    fn log_write(&mut self, addr: u8, word: u32) {
+        defmt::trace!("Wrote R{} = {=u32:#08x}", addr, word);
+    }
+
+    fn log_freq(&mut self, label: &str, freq: Frequency) {
+        defmt::info!("{} {} Hz", label, freq.as_hz());
+    }
+
+    fn log_error(&mut self, error: &Error) {
+        defmt::warn!("{}", error);
+    }
+
+    fn log_stage(&mut self, stage: Stage) {
+        #[cfg(feature = "diag")]
+        defmt::info!("Bring-up: {}", stage);
+        #[cfg(not(feature = "diag"))]
+        let _ = stage;
+    }
+}
+
+/// Discards every event
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct NoLogger;
+
+impl Logger for NoLogger {
+    fn log_write(&mut self, _addr: u8, _word: u32) {}
+
+    fn log_freq(&mut self, _label: &str, _freq: Frequency) {}
+
+    fn log_error(&mut self, _error: &Error) {}
+
+    fn log_stage(&mut self, _stage: Stage) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields;
+    use crate::frequency::SolveMode;
+    use crate::lmx2594::{self, HardwareCs, ProgramOptions, ProgramTiming, RetunePolicy, REG_MAP};
+    use crate::mock;
+
+    /// What a `Recorder` was told, in order
+    #[derive(Clone, Debug, PartialEq)]
+    enum Logged {
+        Write(u8, u32),
+        Freq(String, Frequency),
+        Error(Error),
+        Stage(Stage),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Logged>);
+
+    impl Logger for Recorder {
+        fn log_write(&mut self, addr: u8, word: u32) {
+            self.0.push(Logged::Write(addr, word));
+        }
+
+        fn log_freq(&mut self, label: &str, freq: Frequency) {
+            self.0.push(Logged::Freq(label.into(), freq));
+        }
+
+        fn log_error(&mut self, error: &Error) {
+            self.0.push(Logged::Error(*error));
+        }
+
+        fn log_stage(&mut self, stage: Stage) {
+            self.0.push(Logged::Stage(stage));
+        }
+    }
+
+    #[test]
+    fn program_all_reports_each_write() {
+        let trace = mock::Trace::new();
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        let mut logger = Recorder::default();
+        lmx2594::program_all_logged(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut [0; 3],
+            &REG_MAP,
+            &ProgramOptions::default(),
+            &ProgramTiming::default(),
+            &mut logger,
+        )
+        .unwrap();
+
+        let mut expected = vec![Logged::Stage(Stage::Program { words: 113 })];
+        expected.extend(
+            (0..113)
+                .rev()
+                .map(|addr| Logged::Write(addr, REG_MAP[addr as usize])),
+        );
+        expected.push(Logged::Stage(Stage::Calibrate { pulses: 1 }));
+        expected.push(Logged::Write(0, lmx2594::FCAL_EN_ON));
+        expected.push(Logged::Write(0, lmx2594::FCAL_EN_OFF));
+        assert_eq!(logger.0, expected);
+
+        // A map that fails validation is reported before anything is written
+        let mut logger = Recorder::default();
+        let mut bad = REG_MAP;
+        bad[5] = 0x060000;
+        let result = lmx2594::program_all_logged(
+            &mut spi,
+            &mut HardwareCs,
+            &mut delay,
+            &mut [0; 3],
+            &bad,
+            &ProgramOptions::default(),
+            &ProgramTiming::default(),
+            &mut logger,
+        );
+        assert_eq!(logger.0, [Logged::Error(result.unwrap_err())]);
+    }
+
+    #[test]
+    fn change_frequency_reports_the_new_output() {
+        let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+        let trace = mock::Trace::with_readback(locked);
+        let (mut spi, mut delay) = (trace.spi(), trace.delay());
+        let fref = Frequency::from_mhz(100);
+        let mut regs = REG_MAP;
+        let mut logger = Recorder::default();
+        let mut retune = |regs: &mut [u32; 113], target, logger: &mut Recorder| {
+            lmx2594::change_frequency_logged(
+                &mut spi,
+                &mut HardwareCs,
+                &mut delay,
+                &mut [0; 3],
+                regs,
+                fref,
+                target,
+                SolveMode::Auto,
+                RetunePolicy::HoldOutputs,
+                &ProgramTiming::default(),
+                logger,
+            )
+        };
+
+        let target = Frequency::from_mhz(10_000);
+        let plan = retune(&mut regs, target, &mut logger).unwrap();
+        assert_eq!(plan.output, target);
+        assert_eq!(logger.0, [Logged::Freq("Retuned to".into(), target)]);
+
+        let mut logger = Recorder::default();
+        let target = Frequency::from_hz(1);
+        let error = retune(&mut regs, target, &mut logger).unwrap_err();
+        assert_eq!(logger.0, [Logged::Error(error)]);
+    }
+}
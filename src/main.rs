@@ -66,8 +66,8 @@ use panic_halt as _;
 // Pull in any important traits
 use rp_pico::hal::prelude::*;
 
-// Embed the `Hz` function/trait:
-use embedded_time::rate::*;
+// Embed the `Hz` and `microseconds` functions/traits:
+use embedded_time::{duration::Extensions as _, rate::*};
 
 // A shorter alias for the Peripheral Access Crate, which provides low-level
 // register access
@@ -83,111 +83,461 @@ use rp_pico::hal::gpio;
 // higher-level drivers.
 use rp_pico::hal;
 
-mod lmx2594;
+use lmx2594ctl::command::{self, DeviceState, Response};
+use lmx2594ctl::driver::Lmx2594Driver;
+use lmx2594ctl::frequency::{self, Frequency};
+use lmx2594ctl::idle::{AfterLock, Idle, IdleConfig};
+use lmx2594ctl::lmx2594::{self, BootPolicy, Output, ProgramOptions, ProgramTiming};
+use lmx2594ctl::recovery::{FaultTracker, Recovery};
+use lmx2594ctl::startup;
+
+// USB serial for the control interface
+use usb_device::{class_prelude::*, prelude::*};
+use usbd_serial::SerialPort;
+
+// Formatting results into the response buffer
+use core::fmt::Write as _;
+
+// Stamp each log line with the microsecond timer, to time bring-up stages.
+// It reads 0 until the timer is brought out of reset in `main`.
+#[cfg(feature = "diag")]
+defmt::timestamp!("{=u64:us}", {
+    // SAFETY: reading the raw timer registers has no side effects
+    let timer = unsafe { &*pac::TIMER::ptr() };
+    loop {
+        let hi = timer.timerawh.read().bits();
+        let lo = timer.timerawl.read().bits();
+        if timer.timerawh.read().bits() == hi {
+            break (hi as u64) << 32 | lo as u64;
+        }
+    }
+});
+
+/// Approximate frequency of the ring oscillator the RP2040 boots from,
+/// used for delays before the clocks have been configured
+const ROSC_FREQ_HZ: u32 = 6_000_000;
+
+/// Reference frequency on OSCin: the 100 MHz oscillator fitted to the
+/// LMX2594EVM (TI SNAU210, LMX2594EVM user's guide), which is also the
+/// TICS Pro default. Change it if OSCin is driven from anything else.
+const REF_FREQ: Frequency = Frequency::from_mhz(100);
 
-use lmx2594::{Lmx2594, FCAL_EN_OFF, FCAL_EN_ON, REG_MAP, RESET_OFF, RESET_ON};
+/// What the firmware does once the device is up: hold it as programmed,
+/// serve the control interface continuously, or sleep until a command
+/// comes in
+const AFTER_LOCK: AfterLock = AfterLock::WaitForCommand;
+
+/// What the main loop does between events, following `AFTER_LOCK`. Set
+/// `lock_poll_ms` to watch for loss of lock and recalibrate.
+const IDLE: IdleConfig = AFTER_LOCK.idle_config();
+
+/// Whether the recovery button (`recovery-button` feature) pulls its pin
+/// low when held; the pin is pulled up, so wire the button to ground
+#[cfg(feature = "recovery-button")]
+const RECOVERY_ACTIVE_LOW: bool = true;
+
+/// SPI clock rate for programming the LMX2594
+const SPI_HZ: u32 = 1_000_000;
+/// SPI clock rate during bring-up under `BootPolicy::LowPower`
+const LOW_POWER_SPI_HZ: u32 = 100_000;
 
 #[entry]
 fn main() -> ! {
     info!("Program start");
 
     // Grab our singleton objects
-    let mut pac = pac::Peripherals::take().unwrap();
-    let core = pac::CorePeripherals::take().unwrap();
+    let pac = startup::take(pac::Peripherals::take(), "pac::Peripherals")
+        .unwrap_or_else(|_| startup::halt());
+    let core = startup::take(pac::CorePeripherals::take(), "pac::CorePeripherals")
+        .unwrap_or_else(|_| startup::halt());
 
     // Set up the watchdog driver - needed by the clock setup code
     let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
 
-    // Configure the clocks
-    //
-    // The default is to generate a 125 MHz system clock
-    let clocks = hal::clocks::init_clocks_and_plls(
-        rp_pico::XOSC_CRYSTAL_FREQ,
-        pac.XOSC,
-        pac.CLOCKS,
-        pac.PLL_SYS,
-        pac.PLL_USB,
-        &mut pac.RESETS,
-        &mut watchdog,
-    )
-    .ok()
-    .unwrap();
+    let mut resets = pac.RESETS;
 
     // The single-cycle I/O block controls our GPIO pins
     let sio = hal::Sio::new(pac.SIO);
 
     // Set the pins up according to their function on this particular board
-    let pins = rp_pico::Pins::new(
-        pac.IO_BANK0,
-        pac.PADS_BANK0,
-        sio.gpio_bank0,
-        &mut pac.RESETS,
-    );
+    let pins = rp_pico::Pins::new(pac.IO_BANK0, pac.PADS_BANK0, sio.gpio_bank0, &mut resets);
 
-    // Set the LED to be an output
+    // Set the LED to be an output. This is done before the clocks are
+    // configured so that a clock failure can still be reported on the LED.
     let mut led_pin = pins.led.into_push_pull_output();
 
+    // Recovery button, held at power-up to boot the factory defaults. Move
+    // it by changing the pin here. Configured early so the pull-up has settled when it is read.
+    #[cfg(feature = "recovery-button")]
+    let recovery_button = pins.gpio15.into_pull_up_input();
+
+    // Until the clocks are up we are running from the ring oscillator
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, ROSC_FREQ_HZ);
+
+    // Configure the clocks
+    //
+    // The default is to generate a 125 MHz system clock. The clock
+    // peripherals are consumed by each attempt, so retries re-acquire them.
+    let mut clock_devs = Some((pac.XOSC, pac.CLOCKS, pac.PLL_SYS, pac.PLL_USB));
+    let clocks = startup::retry(
+        startup::CLOCK_INIT_ATTEMPTS,
+        |_| {
+            let (xosc, clocks, pll_sys, pll_usb) = clock_devs.take().unwrap_or_else(|| {
+                // SAFETY: the previous attempt consumed and dropped these
+                // handles, so no other owner exists.
+                let p = unsafe { pac::Peripherals::steal() };
+                (p.XOSC, p.CLOCKS, p.PLL_SYS, p.PLL_USB)
+            });
+            hal::clocks::init_clocks_and_plls(
+                rp_pico::XOSC_CRYSTAL_FREQ,
+                xosc,
+                clocks,
+                pll_sys,
+                pll_usb,
+                &mut resets,
+                &mut watchdog,
+            )
+        },
+        |attempt, _| {
+            warn!("Clock initialization attempt {} failed", attempt + 1);
+            startup::blink_sos(&mut led_pin, &mut delay);
+        },
+    );
+    let clocks = match clocks {
+        Ok(clocks) => clocks,
+        Err(_) => startup::fault("Clock initialization failed", &mut led_pin, &mut delay),
+    };
+
     // These are implicitly used by the spi driver if they are in the correct mode
     let _spi_sclk = pins.gpio2.into_mode::<gpio::FunctionSpi>();
     let _spi_mosi = pins.gpio3.into_mode::<gpio::FunctionSpi>();
     let _spi_miso = pins.gpio4.into_mode::<gpio::FunctionSpi>();
-    let mut spi_cs = pins.gpio5.into_push_pull_output();
+
+    // ~CS is toggled by hand around each 24-bit register unless the board
+    // is built for the SPI peripheral's own CSn (see `lmx2594::HardwareCs`)
+    #[cfg(not(feature = "hardware-cs"))]
+    let spi_cs = pins.gpio5.into_push_pull_output();
+    #[cfg(feature = "hardware-cs")]
+    let (_spi_csn, spi_cs) = (
+        pins.gpio5.into_mode::<gpio::FunctionSpi>(),
+        lmx2594::HardwareCs,
+    );
 
     // This pin will be used for Chip Enable on the LMX 2594
-    // (overall power-on, not SPI chip select)
-    let mut ce_pin = pins.gpio6.into_push_pull_output();
+    // (overall power-on, not SPI chip select). If the board routes uWire
+    // pin 1 to RAMPDIR instead, set `options.pin1` to match.
+    let ce_pin = pins.gpio6.into_push_pull_output();
 
     // Create an SPI driver instance for the SPI0 device
     let spi = spi::Spi::<_, _, 8>::new(pac.SPI0);
 
-    // Exchange the uninitialised SPI driver for an initialised one
-    let mut spi = spi.init(
-        &mut pac.RESETS,
+    // Set `probe: true` to check for the device before programming it;
+    // this needs MUXout wired to SPI0 RX.
+    let options = ProgramOptions::default();
+    let timing = ProgramTiming::default();
+
+    // Exchange the uninitialised SPI driver for an initialised one. Boot
+    // slowly when limiting the startup current.
+    let boot_spi_hz = match options.boot {
+        BootPolicy::Normal => SPI_HZ,
+        BootPolicy::LowPower => LOW_POWER_SPI_HZ,
+        BootPolicy::HighZ => SPI_HZ,
+    };
+    let spi = spi.init(
+        &mut resets,
         clocks.peripheral_clock.freq(),
-        1_000_000u32.Hz(),
-        &embedded_hal::spi::MODE_0,
+        boot_spi_hz.Hz(),
+        &lmx2594::SPI_MODE,
     );
 
-    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().integer());
+    let mut delay = cortex_m::delay::Delay::new(delay.free(), clocks.system_clock.freq().integer());
+    // Started before bring-up, so that `diag` timestamps count from here
+    let mut timer = hal::Timer::new(pac.TIMER, &mut resets);
 
     // Initialize the LMX2594
 
-    // Turn on the LED while we initialize
-    led_pin.set_high().unwrap();
-
-    // Ensure the ~CS pin is high before power-on
-    spi_cs.set_high().unwrap();
-    delay.delay_ms(10);
-
-    // Power on the device
-    ce_pin.set_high().unwrap();
-    delay.delay_ms(10);
+    // Turn on the LED while we initialize. All Results are Infallible for
+    // the rp2040 GPIO, so they are ignored.
+    led_pin.set_high().ok();
+
+    let mut lmx = Lmx2594Driver::new(spi, spi_cs, ce_pin);
+
+    // Shadow copy of the device's registers and settings, updated by the
+    // control interface as they change
+    let mut state = DeviceState::new(lmx2594::default_map());
+    // Boot with the last uploaded register map, if there is one, unless the
+    // recovery button asks for the factory defaults. Bring-up resets the
+    // device and programs `state.regs`, so keeping the built-in map here is
+    // the same as a `factory_reset`; the stored map is left for `load`.
+    #[cfg(feature = "recovery-button")]
+    let boot_map = startup::boot_map(&recovery_button, RECOVERY_ACTIVE_LOW);
+    #[cfg(all(feature = "map-upload", not(feature = "recovery-button")))]
+    let boot_map = startup::BootMap::Stored;
+    #[cfg(feature = "map-upload")]
+    if boot_map == startup::BootMap::Stored {
+        if let Some(regs) = lmx2594ctl::flash::load() {
+            info!("Using the register map stored in flash");
+            state.regs = regs;
+        }
+    }
 
-    let mut buf: [u8; 3] = [0; 3];
+    info!("Register map {=u16:04x}", lmx2594::map_id(&state.regs));
 
-    RESET_ON.write_reg(&mut spi, &mut spi_cs, &mut buf);
-    delay.delay_ms(10);
+    // Under `BootPolicy::HighZ` the outputs stay off the line, and the
+    // shadow map with them, until the `release` command
+    if options.boot == BootPolicy::HighZ {
+        state.held = Some(lmx2594::HeldOutputs::hold(&mut state.regs));
+    }
 
-    RESET_OFF.write_reg(&mut spi, &mut spi_cs, &mut buf);
-    delay.delay_ms(10);
+    // Retry bring-up through bus faults, reinitializing SPI first and then
+    // power cycling the device (see `recovery`). The rp2040 SPI never
+    // reports write errors itself, so here these come from `probe` and
+    // `verify` reading back garbage.
+    let mut faults = FaultTracker::default();
+    let brought_up = loop {
+        let e = match lmx.bring_up(&mut delay, &state.regs, &options, &timing) {
+            Err(e) if e.is_bus_fault() => e,
+            result => break result,
+        };
+        match faults.record_failure() {
+            Recovery::ReinitSpi => {
+                warn!("Bus fault during bring-up ({}), reinitializing SPI", e);
+                lmx = lmx.reinit_spi(|spi| {
+                    spi::Spi::new(spi.free()).init(
+                        &mut resets,
+                        clocks.peripheral_clock.freq(),
+                        boot_spi_hz.Hz(),
+                        &lmx2594::SPI_MODE,
+                    )
+                });
+            }
+            Recovery::FullReset => {
+                warn!("Bus fault during bring-up ({}), power cycling", e);
+                if let Err(e) = lmx.power_off(&mut delay, &timing) {
+                    warn!("Could not power off: {}", e);
+                }
+            }
+            Recovery::GiveUp => break Err(e),
+        }
+    };
+
+    match brought_up {
+        // Leave the LED lit if bring-up failed
+        Ok(()) => {
+            led_pin.set_low().ok();
+            // Only report a frequency the VCO can actually reach with this
+            // reference; `vco_frequency` warns otherwise
+            let in_range = frequency::vco_frequency(REF_FREQ, &state.regs).is_ok();
+            if let Some(f) = frequency::output_frequency(REF_FREQ, &state.regs).filter(|_| in_range)
+            {
+                info!("Output frequency {} Hz", f.as_hz());
+                #[cfg(feature = "morse")]
+                {
+                    delay.delay_ms(1000);
+                    lmx2594ctl::morse::blink_number(
+                        &mut led_pin,
+                        &mut delay,
+                        f.as_hz() / 1_000_000,
+                    );
+                }
+            }
+            #[cfg(feature = "diag")]
+            if let Some(pn) = lmx2594ctl::noise::estimate_phase_noise(
+                REF_FREQ,
+                &state.regs,
+                Frequency::from_khz(100),
+            ) {
+                info!("Estimated phase noise at 100 kHz: {}", pn);
+            }
+            // OUTB can differ, e.g. on the VCO while OUTA is divided
+            if let Some(f) = frequency::channel_frequency(REF_FREQ, &state.regs, Output::B) {
+                info!("OUTB frequency {} Hz", f.as_hz());
+            }
+        }
+        Err(e) => error!("LMX2594 bring-up failed: {}", e),
+    }
+    lmx.spi_mut()
+        .set_baudrate(clocks.peripheral_clock.freq(), SPI_HZ.Hz());
+
+    // Bring up the USB serial control interface once the device is programmed
+    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
+        pac.USBCTRL_REGS,
+        pac.USBCTRL_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut resets,
+    ));
+    let mut serial = SerialPort::new(&usb_bus);
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .manufacturer("rwpeterson")
+        .product("lmx2594ctl")
+        .serial_number("0001")
+        .device_class(usbd_serial::USB_CLASS_CDC)
+        .build();
+
+    #[cfg(feature = "map-upload")]
+    let mut upload = lmx2594ctl::blob::Receiver::new();
+    let mut response = Response::new();
+
+    let mut idle = Idle::new(IDLE);
+
+    // Sleeping waits for an event; with SEVONPEND, an interrupt becoming
+    // pending is one even while it is disabled in the NVIC, so USB traffic
+    // and the lock poll alarm wake the core without any handlers
+    let mut scb = core.SCB;
+    scb.set_sevonpend();
+    let mut alarm = match timer.alarm_0() {
+        Some(alarm) => alarm,
+        None => startup::fault("Timer alarm 0 already taken", &mut led_pin, &mut delay),
+    };
+    alarm.enable_interrupt(&mut timer);
 
-    for r in REG_MAP.iter().rev() {
-        r.write_reg(&mut spi, &mut spi_cs, &mut buf);
-        delay.delay_ms(10);
+    loop {
+        idle.poll_lock(
+            &mut lmx,
+            &mut delay,
+            &state,
+            &timing,
+            timer.get_counter() / 1000,
+        );
+
+        if usb_dev.poll(&mut [&mut serial]) {
+            let mut rx = [0u8; 64];
+            let count = serial.read(&mut rx).unwrap_or(0);
+            let mut input = &rx[..count];
+            while !input.is_empty() {
+                #[cfg(feature = "map-upload")]
+                if state.loading {
+                    let (byte, rest) = (input[0], &input[1..]);
+                    input = rest;
+                    if let Some(image) = upload.push(byte) {
+                        state.loading = false;
+                        response.clear();
+                        match load_map(image, &mut lmx, &mut delay, &options, &timing) {
+                            Ok(regs) => {
+                                info!("Loaded register map {=u16:04x}", lmx2594::map_id(&regs));
+                                state.regs = regs;
+                                response.write_str("loaded\r\n").ok();
+                            }
+                            Err(e) => {
+                                core::write!(response, "error: {}\r\n", e).ok();
+                            }
+                        }
+                        send(&mut usb_dev, &mut serial, response.as_bytes());
+                    }
+                    continue;
+                }
+                let used = idle.serve(input, &mut lmx, &mut state, |bytes| {
+                    send(&mut usb_dev, &mut serial, bytes)
+                });
+                input = &input[used..];
+
+                if state.retune.is_some() {
+                    response.clear();
+                    command::retune(
+                        &mut lmx,
+                        &mut delay,
+                        &mut state,
+                        REF_FREQ,
+                        &timing,
+                        &mut response,
+                    )
+                    .ok();
+                    send(&mut usb_dev, &mut serial, response.as_bytes());
+                }
+
+                if state.reset_requested {
+                    state.reset_requested = false;
+                    // Lit while reprogramming, and left lit on failure, as
+                    // during bring-up
+                    led_pin.set_high().ok();
+                    #[cfg(feature = "map-upload")]
+                    lmx2594ctl::flash::erase();
+                    // The built-in map drives the outputs as it is written
+                    state.held = None;
+                    response.clear();
+                    match lmx.factory_reset(&mut delay, &mut state.regs, &options, &timing) {
+                        Ok(()) => {
+                            led_pin.set_low().ok();
+                            response.write_str("factory defaults restored\r\n").ok();
+                        }
+                        Err(e) => {
+                            core::write!(response, "error: {}\r\n", e).ok();
+                        }
+                    }
+                    send(&mut usb_dev, &mut serial, response.as_bytes());
+                }
+            }
+        }
+
+        if idle.config().sleep {
+            alarm.clear_interrupt(&mut timer);
+            pac::NVIC::unpend(pac::Interrupt::TIMER_IRQ_0);
+            pac::NVIC::unpend(pac::Interrupt::USBCTRL_IRQ);
+            if let Some(due_ms) = idle.next_poll_ms() {
+                let wait_us = (due_ms * 1000).saturating_sub(timer.get_counter());
+                // The alarm counts at most 32 bits of us; waking early just
+                // means going back to sleep
+                let wait_us = wait_us.clamp(10, u32::MAX as u64) as u32;
+                alarm.schedule(wait_us.microseconds()).ok();
+            }
+            cortex_m::asm::wfe();
+        }
     }
-    delay.delay_ms(10);
+}
 
-    FCAL_EN_ON.write_reg(&mut spi, &mut spi_cs, &mut buf);
-    delay.delay_ms(10);
+/// Write all of `bytes` to the serial port, polling the USB device while
+/// its buffer is full
+fn send<B: UsbBus>(usb_dev: &mut UsbDevice<B>, serial: &mut SerialPort<B>, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        match serial.write(bytes) {
+            Ok(len) => bytes = &bytes[len..],
+            Err(UsbError::WouldBlock) => {
+                usb_dev.poll(&mut [serial]);
+            }
+            Err(_) => break,
+        }
+    }
+}
 
-    FCAL_EN_OFF.write_reg(&mut spi, &mut spi_cs, &mut buf);
-    delay.delay_ms(10);
+/// Check an uploaded register map image, save it to flash and program it
+#[cfg(feature = "map-upload")]
+fn load_map<S, C, P, D>(
+    image: &[u8; lmx2594ctl::blob::BLOB_LEN],
+    lmx: &mut Lmx2594Driver<S, C, P>,
+    delay: &mut D,
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+) -> Result<[u32; 113], LoadError>
+where
+    S: embedded_hal::blocking::spi::Transfer<u8> + embedded_hal::blocking::spi::Write<u8>,
+    <S as embedded_hal::blocking::spi::Transfer<u8>>::Error: core::fmt::Debug,
+    <S as embedded_hal::blocking::spi::Write<u8>>::Error: core::fmt::Debug,
+    C: lmx2594::ChipSelect,
+    P: OutputPin,
+    P::Error: core::fmt::Debug,
+    D: embedded_hal::blocking::delay::DelayMs<u32>,
+{
+    let regs = lmx2594ctl::blob::parse(image).map_err(LoadError::Image)?;
+    lmx2594ctl::flash::store(image);
+    lmx.program_all(delay, &regs, options, timing)
+        .map_err(LoadError::Program)?;
+    Ok(regs)
+}
 
-    led_pin.set_low().unwrap();
+/// Why `load_map` failed
+#[cfg(feature = "map-upload")]
+enum LoadError {
+    Image(lmx2594ctl::blob::BlobError),
+    Program(lmx2594::Error),
+}
 
-    #[allow(clippy::empty_loop)]
-    loop {
-        //FCAL_EN_ON.write_reg(&mut spi, &mut spi_cs, &mut buf);
-        //delay.delay_us(100);
+#[cfg(feature = "map-upload")]
+impl core::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LoadError::Image(e) => e.fmt(f),
+            LoadError::Program(e) => e.fmt(f),
+        }
     }
 }
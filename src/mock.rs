@@ -0,0 +1,234 @@
+//! Mock peripherals for the host-side unit tests.
+//!
+//! `embedded-hal-mock` checks every SPI write and pin change against a list
+//! of expected transactions. The helpers here build those lists for
+//! register words framed by a GPIO chip select, so a test only has to name
+//! the words it expects on the bus.
+
+use crate::fields::{FCAL_EN, MUXOUT_LD_SEL};
+use crate::lmx2594::{BusLock, WIRE_ORDER};
+use embedded_hal::{
+    blocking::{
+        delay::{DelayMs, DelayUs},
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+pub use embedded_hal_mock::delay::MockNoop as Delay;
+pub use embedded_hal_mock::pin::{Mock as Pin, State, Transaction as PinTransaction};
+pub use embedded_hal_mock::spi::{Mock as Spi, Transaction as SpiTransaction};
+use std::{cell::RefCell, rc::Rc};
+
+/// The three bytes of a 24-bit register word, in `WIRE_ORDER`
+pub fn bytes(word: u32) -> Vec<u8> {
+    WIRE_ORDER.pack(word).to_vec()
+}
+
+/// The register word sent as `bytes`, undoing `WIRE_ORDER`
+pub fn word(bytes: &[u8]) -> u32 {
+    let fold = |bytes: &[u8]| bytes.iter().fold(0, |w, &b| w << 8 | b as u32);
+    // Every order only swaps bytes or bits around, so is its own inverse
+    fold(&WIRE_ORDER.pack(fold(bytes)))
+}
+
+/// SPI transactions writing each of `words` in turn
+pub fn writes<I: IntoIterator<Item = u32>>(words: I) -> Vec<SpiTransaction> {
+    words
+        .into_iter()
+        .map(|w| SpiTransaction::write(bytes(w)))
+        .collect()
+}
+
+/// SPI transaction reading `value` back from register `addr`
+pub fn read(addr: u8, value: u16) -> SpiTransaction {
+    let [hi, lo] = value.to_be_bytes();
+    SpiTransaction::transfer(vec![0x80 | addr, 0, 0], vec![0, hi, lo])
+}
+
+/// SPI transactions for `lmx2594::readback` of register `addr` returning
+/// `value`, given `r0` with MUXout in lock detect mode: R0 switched to
+/// readback, the read, then R0 restored. Each takes one chip select frame.
+pub fn readback(r0: u32, addr: u8, value: u16) -> Vec<SpiTransaction> {
+    let r0 = r0 & !FCAL_EN.mask();
+    vec![
+        SpiTransaction::write(bytes(r0 & !MUXOUT_LD_SEL.mask())),
+        read(addr, value),
+        SpiTransaction::write(bytes(r0)),
+    ]
+}
+
+/// Chip select transactions framing `n` register accesses: low for each
+/// word, then high to latch it
+pub fn cs_frames(n: usize) -> Vec<PinTransaction> {
+    (0..n)
+        .flat_map(|_| {
+            [
+                PinTransaction::set(State::Low),
+                PinTransaction::set(State::High),
+            ]
+        })
+        .collect()
+}
+
+/// An SPI bus on which every write and transfer fails, for checking that
+/// bus errors come back as errors rather than panics
+pub struct FailingSpi;
+
+impl Write<u8> for FailingSpi {
+    type Error = ();
+
+    fn write(&mut self, _: &[u8]) -> Result<(), ()> {
+        Err(())
+    }
+}
+
+impl Transfer<u8> for FailingSpi {
+    type Error = ();
+
+    fn transfer<'w>(&mut self, _: &'w mut [u8]) -> Result<&'w [u8], ()> {
+        Err(())
+    }
+}
+
+/// A bus or pin event, in the order `Trace` saw them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    Write(u32),
+    Read(u8),
+    Delay(u32),
+    /// A wait in µs
+    DelayUs(u32),
+    /// A pin driven high (true) or low
+    Pin(bool),
+    /// A bus lock taken (true) or released
+    Lock(bool),
+}
+
+/// Records register accesses, delays and pin changes in one timeline, for
+/// checking the order they happen in across peripherals. Every register
+/// reads back the same value.
+#[derive(Clone, Default)]
+pub struct Trace {
+    events: Rc<RefCell<Vec<Event>>>,
+    readback: u16,
+    failing: &'static [u8],
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A trace whose register reads all return `value`
+    pub fn with_readback(value: u16) -> Self {
+        Trace {
+            readback: value,
+            ..Self::default()
+        }
+    }
+
+    /// A trace whose SPI writes to the registers at `addrs` fail, and are
+    /// left out of the events
+    pub fn with_failing_writes(addrs: &'static [u8]) -> Self {
+        Trace {
+            failing: addrs,
+            ..Self::default()
+        }
+    }
+
+    fn push(&self, event: Event) {
+        self.events.borrow_mut().push(event);
+    }
+
+    /// An SPI bus recording each 24-bit write
+    pub fn spi(&self) -> TraceSpi {
+        TraceSpi(self.clone())
+    }
+
+    /// A delay recording each wait
+    pub fn delay(&self) -> TraceDelay {
+        TraceDelay(self.clone())
+    }
+
+    /// A GPIO recording each level it is driven to
+    pub fn pin(&self) -> TracePin {
+        TracePin(self.clone())
+    }
+
+    /// A bus lock recording each acquire and release
+    pub fn lock(&self) -> TraceLock {
+        TraceLock(self.clone())
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.borrow().clone()
+    }
+}
+
+pub struct TraceSpi(Trace);
+
+impl Write<u8> for TraceSpi {
+    type Error = ();
+
+    fn write(&mut self, words: &[u8]) -> Result<(), ()> {
+        let word = word(words);
+        if self.0.failing.contains(&((word >> 16) as u8)) {
+            return Err(());
+        }
+        self.0.push(Event::Write(word));
+        Ok(())
+    }
+}
+
+impl Transfer<u8> for TraceSpi {
+    type Error = ();
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+        self.0.push(Event::Read(words[0] & 0x7f));
+        let [hi, lo] = self.0.readback.to_be_bytes();
+        words[1..].copy_from_slice(&[hi, lo]);
+        Ok(words)
+    }
+}
+
+pub struct TraceDelay(Trace);
+
+impl DelayMs<u32> for TraceDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.0.push(Event::Delay(ms));
+    }
+}
+
+impl DelayUs<u32> for TraceDelay {
+    fn delay_us(&mut self, us: u32) {
+        self.0.push(Event::DelayUs(us));
+    }
+}
+
+pub struct TracePin(Trace);
+
+impl OutputPin for TracePin {
+    type Error = ();
+
+    fn set_low(&mut self) -> Result<(), ()> {
+        self.0.push(Event::Pin(false));
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), ()> {
+        self.0.push(Event::Pin(true));
+        Ok(())
+    }
+}
+
+pub struct TraceLock(Trace);
+
+impl BusLock for TraceLock {
+    fn acquire(&mut self) {
+        self.0.push(Event::Lock(true));
+    }
+
+    fn release(&mut self) {
+        self.0.push(Event::Lock(false));
+    }
+}
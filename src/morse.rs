@@ -0,0 +1,122 @@
+//! Morse code on the status LED, for reading numbers off a board without a
+//! debugger attached.
+
+use crate::startup::{blink_element, DOT_MS};
+use embedded_hal::{blocking::delay::DelayMs, digital::v2::OutputPin};
+
+/// A Morse code element
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Symbol {
+    Dot,
+    Dash,
+}
+
+/// The Morse code for a decimal digit; digits above 9 are taken modulo 10
+pub fn digit(d: u8) -> [Symbol; 5] {
+    // n dots followed by dashes for 1-5, n - 5 dashes followed by dots for
+    // 6-9, and five dashes for 0
+    let d = d % 10;
+    let mut code = [Symbol::Dash; 5];
+    for (i, s) in code.iter_mut().enumerate() {
+        let dot = match d {
+            0 => false,
+            1..=5 => (i as u8) < d,
+            _ => (i as u8) >= d - 5,
+        };
+        if dot {
+            *s = Symbol::Dot;
+        }
+    }
+    code
+}
+
+/// Blink `value` in decimal, most significant digit first, followed by a
+/// word gap
+pub fn blink_number<P: OutputPin, D: DelayMs<u32>>(led: &mut P, delay: &mut D, value: u64) {
+    let mut digits = [0u8; 20];
+    let mut len = 0;
+    let mut v = value;
+    loop {
+        digits[len] = (v % 10) as u8;
+        len += 1;
+        v /= 10;
+        if v == 0 {
+            break;
+        }
+    }
+    for &d in digits[..len].iter().rev() {
+        for &s in digit(d).iter() {
+            let units = match s {
+                Symbol::Dot => 1,
+                Symbol::Dash => 3,
+            };
+            blink_element(led, delay, units);
+        }
+        // Letter gap (3 units, one already spent above)
+        delay.delay_ms(2 * DOT_MS);
+    }
+    // Word gap (7 units, three already spent above)
+    delay.delay_ms(4 * DOT_MS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Event, Trace};
+    use Symbol::{Dash as D, Dot as O};
+
+    #[test]
+    fn digits_encode_as_international_morse() {
+        let table = [
+            [D, D, D, D, D],
+            [O, D, D, D, D],
+            [O, O, D, D, D],
+            [O, O, O, D, D],
+            [O, O, O, O, D],
+            [O, O, O, O, O],
+            [D, O, O, O, O],
+            [D, D, O, O, O],
+            [D, D, D, O, O],
+            [D, D, D, D, O],
+        ];
+        for (d, code) in table.iter().enumerate() {
+            assert_eq!(digit(d as u8), *code, "digit {}", d);
+        }
+        // Out-of-range digits wrap rather than panic
+        assert_eq!(digit(13), digit(3));
+    }
+
+    #[test]
+    fn numbers_blink_digit_by_digit_with_letter_and_word_gaps() {
+        let trace = Trace::new();
+        blink_number(&mut trace.pin(), &mut trace.delay(), 19);
+        let element = |units| {
+            [
+                Event::Pin(true),
+                Event::Delay(units * DOT_MS),
+                Event::Pin(false),
+                Event::Delay(DOT_MS),
+            ]
+        };
+        let mut expected = Vec::new();
+        // 1 is .---- and 9 is ----.
+        for units in [[1, 3, 3, 3, 3], [3, 3, 3, 3, 1]] {
+            for u in units {
+                expected.extend(element(u));
+            }
+            expected.push(Event::Delay(2 * DOT_MS));
+        }
+        expected.push(Event::Delay(4 * DOT_MS));
+        assert_eq!(trace.events(), expected);
+
+        // Zero is still one digit
+        let trace = Trace::new();
+        blink_number(&mut trace.pin(), &mut trace.delay(), 0);
+        let on = trace
+            .events()
+            .iter()
+            .filter(|e| **e == Event::Pin(true))
+            .count();
+        assert_eq!(on, 5);
+    }
+}
@@ -0,0 +1,215 @@
+//! Ballpark phase noise from the datasheet's figures of merit.
+//!
+//! Inside the loop bandwidth the output noise is the PLL's: a flat floor set
+//! by the normalized figure of merit, the N divider and the PFD frequency,
+//! plus a flicker (1/f) term that falls 10 dB per decade of offset. Well
+//! outside it the output follows the free-running VCO, which falls about
+//! 20 dB per decade between roughly 100 kHz and 10 MHz. Both are scaled to
+//! the output by the channel divider.
+//!
+//! These are the textbook estimates a loop filter tool starts from, meant
+//! for choosing a PFD or comparing plans without a spectrum analyzer. They
+//! ignore the reference's own noise, the loop filter's peaking and the
+//! fractional modulator, so expect the measured curve to differ by a few
+//! dB. Everything is in tenths of a dB, as in `power`, so that no floating
+//! point is needed.
+
+use crate::fields;
+use crate::frequency::{self, Frequency};
+
+/// Normalized PLL noise floor, in tenths of a dBc/Hz
+pub const PLL_FOM_TENTHS: i32 = -2360;
+/// Normalized PLL flicker noise at a 1 GHz carrier and 10 kHz offset, in
+/// tenths of a dBc/Hz
+pub const PLL_FLICKER_TENTHS: i32 = -1290;
+/// Charge pump current the figures of merit are quoted at, in uA
+pub const FOM_CHARGE_PUMP_UA: u32 = 15_000;
+
+/// Typical open-loop VCO noise at a 1 MHz offset, in tenths of a dBc/Hz,
+/// with the frequency it was quoted at, for each of `frequency::VCO_CORES`
+pub static VCO_NOISE_1MHZ: [(Frequency, i32); 7] = [
+    (Frequency::from_mhz(8_100), -1283),
+    (Frequency::from_mhz(9_300), -1272),
+    (Frequency::from_mhz(10_400), -1266),
+    (Frequency::from_mhz(11_400), -1256),
+    (Frequency::from_mhz(12_500), -1247),
+    (Frequency::from_mhz(13_600), -1240),
+    (Frequency::from_mhz(14_700), -1233),
+];
+
+/// Estimated phase noise at OUTA at one offset, in tenths of a dBc/Hz
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct PhaseNoiseEstimate {
+    /// Offset from the carrier the estimate is for
+    pub offset: Frequency,
+    /// Flat in-band PLL noise floor
+    pub pll_floor_tenths: i32,
+    /// PLL flicker noise at `offset`
+    pub pll_flicker_tenths: i32,
+    /// In-band PLL noise at `offset`: the floor and flicker noise combined
+    pub in_band_tenths: i32,
+    /// Free-running VCO noise at `offset`, which the output follows
+    /// outside the loop bandwidth
+    pub vco_tenths: i32,
+}
+
+/// `100 * log10(num / den)`, i.e. the ratio in tenths of a dB on a power
+/// scale, to within a tenth. Both must be nonzero.
+pub fn db_tenths(num: u128, den: u128) -> i32 {
+    let diff = log2_q16(num) - log2_q16(den);
+    // 100 * log10(2) = 30.103
+    let scaled = diff * 30_103;
+    let half = 500 * 65_536;
+    ((scaled + if scaled < 0 { -half } else { half }) / (1_000 * 65_536)) as i32
+}
+
+/// log2(x) with 16 fractional bits, for a nonzero `x`
+fn log2_q16(x: u128) -> i64 {
+    let int = 127 - x.leading_zeros() as i64;
+    // x / 2^int in [1, 2), with 62 fractional bits
+    let mut y = if int > 62 {
+        x >> (int - 62)
+    } else {
+        x << (62 - int)
+    };
+    let mut frac = 0;
+    for _ in 0..16 {
+        y = (y * y) >> 62;
+        frac <<= 1;
+        if y >= 2 << 62 {
+            y >>= 1;
+            frac |= 1;
+        }
+    }
+    int << 16 | frac
+}
+
+/// `200 * log10(num / den)`, the ratio in tenths of a dB on an amplitude
+/// scale, as for dividers and frequency ratios
+fn db20_tenths(num: u128, den: u128) -> i32 {
+    db_tenths(num * num, den * den)
+}
+
+/// Add two noise powers given in tenths of a dB
+pub fn power_sum_tenths(a: i32, b: i32) -> i32 {
+    // 10 * log10(1 + 10^(-d / 10)) in tenths, for each whole dB d apart
+    const CORRECTION: [i32; 21] = [
+        30, 25, 21, 18, 15, 12, 10, 8, 6, 5, 4, 3, 3, 2, 2, 1, 1, 1, 1, 1, 0,
+    ];
+    let apart = ((a - b).abs() + 5) / 10;
+    a.max(b) + CORRECTION.get(apart as usize).copied().unwrap_or(0)
+}
+
+/// Estimate the phase noise at OUTA `offset` from the carrier, for a
+/// reference of `fref` and the dividers and charge pump gain in `regs`.
+///
+/// The figures of merit hold at `FOM_CHARGE_PUMP_UA`; at a lower charge
+/// pump current the floor is taken to rise by the ratio of the currents, a
+/// rough allowance for the charge pump's own noise. Returns `None` if the
+/// VCO is outside every core, the charge pump is off, OUTA is in high
+/// impedance or `offset` is zero.
+pub fn estimate_phase_noise(
+    fref: Frequency,
+    regs: &[u32; 113],
+    offset: Frequency,
+) -> Option<PhaseNoiseEstimate> {
+    let vco = frequency::vco_frequency_unchecked(fref, regs);
+    let core = frequency::vco_core(vco)?;
+    let output = frequency::output_frequency(fref, regs)?;
+    let pfd = frequency::pfd_frequency(fref, regs);
+    let cp_ua = frequency::charge_pump_ua(fields::CPG.get(regs));
+    if output.as_hz() == 0 || pfd.as_hz() == 0 || cp_ua == 0 || offset.as_hz() == 0 {
+        return None;
+    }
+    let (vco_hz, out_hz) = (vco.as_hz() as u128, output.as_hz() as u128);
+    // The channel divider lowers every term by 20 log10 of its ratio
+    let division = db20_tenths(vco_hz, out_hz);
+
+    // FOM + 20 log10(N * fPD) - 10 log10(fPD), with N * fPD = fVCO
+    let pll_floor_tenths = PLL_FOM_TENTHS + db20_tenths(vco_hz, 1)
+        - db_tenths(pfd.as_hz() as u128, 1)
+        + db_tenths(FOM_CHARGE_PUMP_UA as u128, cp_ua as u128).max(0)
+        - division;
+    let offset_hz = offset.as_hz() as u128;
+    let pll_flicker_tenths =
+        PLL_FLICKER_TENTHS + db20_tenths(out_hz, 1_000_000_000) - db_tenths(offset_hz, 10_000);
+
+    let (quoted, noise) = VCO_NOISE_1MHZ[core];
+    let vco_tenths = noise + db20_tenths(vco_hz, quoted.as_hz() as u128)
+        - db20_tenths(offset_hz, 1_000_000)
+        - division;
+
+    Some(PhaseNoiseEstimate {
+        offset,
+        pll_floor_tenths,
+        pll_flicker_tenths,
+        in_band_tenths: power_sum_tenths(pll_floor_tenths, pll_flicker_tenths),
+        vco_tenths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frequency::RefPath;
+    use crate::lmx2594::REG_MAP;
+
+    #[test]
+    fn decibels_to_a_tenth() {
+        assert_eq!(db_tenths(10, 1), 100);
+        assert_eq!(db_tenths(2, 1), 30);
+        assert_eq!(db_tenths(1, 2), -30);
+        assert_eq!(db_tenths(1_000_000_000, 1), 900);
+        assert_eq!(db_tenths(u64::MAX as u128, 1), 1927);
+        assert_eq!(power_sum_tenths(-1160, -1160), -1130);
+        assert_eq!(power_sum_tenths(-1160, -1190), -1142);
+        assert_eq!(power_sum_tenths(-1000, -1500), -1000);
+    }
+
+    #[test]
+    fn estimate_follows_the_figure_of_merit() {
+        // 10 GHz straight from the VCO with a 100 MHz PFD and full charge
+        // pump current: -236 + 200 - 80 = -116 dBc/Hz, and flicker noise
+        // of -129 + 20 = -109 dBc/Hz at 10 kHz, -119 dBc/Hz at 100 kHz
+        let fref = Frequency::from_mhz(100);
+        let mut regs = REG_MAP;
+        RefPath {
+            doubler: false,
+            mult: 1,
+            r_pre: 1,
+            r: 1,
+        }
+        .apply(&mut regs);
+        fields::CPG.set(&mut regs, 7);
+        frequency::set_frequency(fref, &mut regs, Frequency::from_ghz(10)).unwrap();
+        let at = |regs: &[u32; 113], khz| {
+            estimate_phase_noise(fref, regs, Frequency::from_khz(khz)).unwrap()
+        };
+
+        let near = at(&regs, 10);
+        assert_eq!(near.pll_floor_tenths, -1160);
+        assert_eq!(near.pll_flicker_tenths, -1090);
+        let far = at(&regs, 100);
+        assert_eq!(far.pll_flicker_tenths, -1190);
+        assert_eq!(far.in_band_tenths, -1142);
+        // VCO3 at 10 GHz: -126.6 dBc/Hz at 1 MHz from 10.4 GHz, 0.3 dB
+        // lower at 10 GHz, 20 dB higher at 100 kHz
+        assert_eq!(far.vco_tenths, -1069);
+
+        // Halving the output through CHDIV takes 6 dB off every term
+        frequency::set_frequency(fref, &mut regs, Frequency::from_ghz(5)).unwrap();
+        let halved = at(&regs, 100);
+        assert_eq!(halved.pll_floor_tenths, -1160 - 60);
+        assert_eq!(halved.pll_flicker_tenths, -1190 - 60);
+        assert_eq!(halved.vco_tenths, -1069 - 60);
+
+        // 6 mA instead of 15 mA raises the floor 4 dB
+        fields::CPG.set(&mut regs, 1);
+        assert_eq!(at(&regs, 100).pll_floor_tenths, -1160 - 60 + 40);
+        fields::CPG.set(&mut regs, 0);
+        assert_eq!(
+            estimate_phase_noise(fref, &regs, Frequency::from_khz(100)),
+            None
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Output power in dBm, via a per-board calibration table.
+//!
+//! OUTA_PWR and OUTB_PWR are raw 0-63 codes whose power in dBm depends on
+//! the frequency, the output match and the board. A calibration table
+//! measured on the board maps codes to dBm, and is interpolated linearly
+//! between its points. Power is given in tenths of a dBm throughout so that
+//! no floating point is needed.
+
+use crate::fields::{self, Field};
+use crate::lmx2594::{self, ChipSelect, Error, Output};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Write;
+
+/// Highest OUTx_PWR code
+pub const MAX_CODE: u8 = 63;
+
+/// One measured point of a calibration table
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct CalPoint {
+    /// OUTx_PWR code
+    pub code: u8,
+    /// Output power measured at `code`, in tenths of a dBm
+    pub dbm_tenths: i16,
+}
+
+/// A validated calibration table, see `calibrate_power`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct PowerCalibration<'a> {
+    points: &'a [CalPoint],
+}
+
+/// Check a calibration table, typically a `static` kept in flash, for use
+/// with `set_output_dbm`.
+///
+/// The table needs at least one point, codes in strictly increasing order
+/// and at most `MAX_CODE`, and power that never falls as the code rises.
+/// Anything else is rejected with `Error::InvalidCalibration`.
+pub fn calibrate_power(points: &[CalPoint]) -> Result<PowerCalibration<'_>, Error> {
+    let last = points.last().ok_or(Error::InvalidCalibration)?;
+    let ordered = points
+        .windows(2)
+        .all(|w| w[0].code < w[1].code && w[0].dbm_tenths <= w[1].dbm_tenths);
+    if !ordered || last.code > MAX_CODE {
+        defmt::warn!("Power calibration table is not in increasing order");
+        return Err(Error::InvalidCalibration);
+    }
+    Ok(PowerCalibration { points })
+}
+
+impl PowerCalibration<'_> {
+    /// The code giving `dbm_tenths`, interpolated between the table's
+    /// points and rounded to the nearest code. Requests outside of the
+    /// table are clamped to its first or last code.
+    pub fn code_for(&self, dbm_tenths: i16) -> u8 {
+        let first = self.points[0];
+        if dbm_tenths <= first.dbm_tenths {
+            return first.code;
+        }
+        for w in self.points.windows(2) {
+            let (lo, hi) = (w[0], w[1]);
+            if dbm_tenths > hi.dbm_tenths {
+                continue;
+            }
+            let span = hi.dbm_tenths as i32 - lo.dbm_tenths as i32;
+            if span == 0 {
+                return lo.code;
+            }
+            let codes = (hi.code - lo.code) as i32;
+            let offset = (dbm_tenths as i32 - lo.dbm_tenths as i32) * codes;
+            return lo.code + ((offset + span / 2) / span) as u8;
+        }
+        self.points[self.points.len() - 1].code
+    }
+}
+
+/// Set `output` to the code `cal` gives for `dbm_tenths`, recording it in
+/// the shadow map `regs` and writing the register. Returns the code used,
+/// or `Error::WriteFailed` if the SPI write failed.
+pub fn set_output_dbm<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    cal: &PowerCalibration<'_>,
+    output: Output,
+    dbm_tenths: i16,
+) -> Result<u8, Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    let code = cal.code_for(dbm_tenths);
+    write_field(spi, spi_cs, buf, regs, power_field(output), code)?;
+    Ok(code)
+}
+
+/// Highest output buffer current boost, see `set_output_boost`
+pub const MAX_BOOST: u8 = 3;
+
+/// Set the raw drive level of `output`, 0 to `MAX_CODE`, recording it in
+/// the shadow map `regs` and writing the register.
+///
+/// This is the OUTx_PWR code `set_output_dbm` picks from a calibration
+/// table. Higher codes drive the output harder and give faster edges,
+/// which helps a long trace; a level above `MAX_CODE` is rejected with
+/// `Error::InvalidDrive` and nothing is written.
+pub fn set_output_drive<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    output: Output,
+    level: u8,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    if level > MAX_CODE {
+        return Err(Error::InvalidDrive(level));
+    }
+    write_field(spi, spi_cs, buf, regs, power_field(output), level)
+}
+
+/// Set the output buffer current boost, 0 (none) to `MAX_BOOST`, recording
+/// it in the shadow map `regs` and writing the register.
+///
+/// The boost is shared by both outputs. It raises the output power at high
+/// frequencies at the cost of supply current; a boost above `MAX_BOOST` is
+/// rejected with `Error::InvalidDrive` and nothing is written.
+pub fn set_output_boost<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    boost: u8,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    if boost > MAX_BOOST {
+        return Err(Error::InvalidDrive(boost));
+    }
+    // OUT_ISET counts down from maximum boost
+    write_field(spi, spi_cs, buf, regs, fields::OUT_ISET, MAX_BOOST - boost)
+}
+
+fn power_field(output: Output) -> Field {
+    match output {
+        Output::A => fields::OUTA_PWR,
+        Output::B => fields::OUTB_PWR,
+    }
+}
+
+fn write_field<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    field: Field,
+    value: u8,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    field.set(regs, value as u32);
+    lmx2594::write_word(spi, spi_cs, buf, regs[field.addr as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::REG_MAP;
+    use crate::mock;
+
+    fn drive(output: Output, level: u8, expected: &[u32]) -> Result<(), Error> {
+        let mut spi = mock::Spi::new(&mock::writes(expected.iter().copied()));
+        let mut cs = mock::Pin::new(&mock::cs_frames(expected.len()));
+        let mut regs = REG_MAP;
+        let result = set_output_drive(&mut spi, &mut cs, &mut [0; 3], &mut regs, output, level);
+        spi.done();
+        cs.done();
+        result
+    }
+
+    #[test]
+    fn drive_level_encodes_into_each_output() {
+        let r44 = REG_MAP[44] & !fields::OUTA_PWR.mask() | 40 << 8;
+        drive(Output::A, 40, &[r44]).unwrap();
+        let r45 = REG_MAP[45] & !fields::OUTB_PWR.mask() | 40;
+        drive(Output::B, 40, &[r45]).unwrap();
+    }
+
+    #[test]
+    fn drive_level_out_of_range_writes_nothing() {
+        assert_eq!(drive(Output::A, 64, &[]), Err(Error::InvalidDrive(64)));
+        assert_eq!(drive(Output::B, 64, &[]), Err(Error::InvalidDrive(64)));
+    }
+
+    const fn point(code: u8, dbm_tenths: i16) -> CalPoint {
+        CalPoint { code, dbm_tenths }
+    }
+
+    #[test]
+    fn dbm_interpolates_between_table_points() {
+        let table = [point(0, -150), point(20, -50), point(50, 70), point(60, 70)];
+        let cal = calibrate_power(&table).unwrap();
+        let cases = [
+            (-200, 0),
+            (-150, 0),
+            (-100, 10),
+            (-50, 20),
+            (-49, 20),
+            (10, 35),
+            (70, 50),
+            (100, 60),
+        ];
+        for (dbm_tenths, code) in cases {
+            assert_eq!(cal.code_for(dbm_tenths), code, "{} dBm/10", dbm_tenths);
+        }
+
+        let r44 = REG_MAP[44] & !fields::OUTA_PWR.mask() | 35 << 8;
+        let mut spi = mock::Spi::new(&mock::writes([r44]));
+        let mut cs = mock::Pin::new(&mock::cs_frames(1));
+        let mut regs = REG_MAP;
+        let code = set_output_dbm(
+            &mut spi,
+            &mut cs,
+            &mut [0; 3],
+            &mut regs,
+            &cal,
+            Output::A,
+            10,
+        );
+        assert_eq!(code, Ok(35));
+        assert_eq!(regs[44], r44);
+        spi.done();
+        cs.done();
+    }
+
+    #[test]
+    fn calibration_tables_must_rise() {
+        let invalid: [&[CalPoint]; 4] = [
+            &[],
+            &[point(20, 0), point(10, 10)],
+            &[point(10, 10), point(20, 0)],
+            &[point(10, 0), point(MAX_CODE + 1, 10)],
+        ];
+        for table in invalid {
+            assert_eq!(calibrate_power(table), Err(Error::InvalidCalibration));
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Tuning profiles trading phase noise for lock time.
+//!
+//! Most of the LMX2594's lock time is the VCO calibration, so the profiles
+//! differ mainly in how it is run. They change these fields:
+//!
+//! ```text
+//! | Field          | Register | Performance | FastLock |
+//! |----------------|----------|-------------|----------|
+//! | CPG            | R14      | 7 (15 mA)   | 7        |
+//! | ACAL_CMP_DLY   | R4       | 10          | 5        |
+//! | QUICK_RECAL_EN | R78      | 0           | 1        |
+//! ```
+//!
+//! Both run the charge pump at full current, where its noise is lowest and
+//! the loop is widest for a given filter. `Performance` matches `REG_MAP`:
+//! every calibration searches all the cores from scratch, so the VCO always
+//! lands on its best core and capacitor code. `FastLock` starts each
+//! calibration from the previous result and halves the settling time of the
+//! amplitude calibration steps. Small retunes then lock much faster, but a
+//! retune near a core boundary can settle on a noisier core, and a marginal
+//! board may fail amplitude calibration with the shorter delay.
+
+use crate::fields::{self, Field};
+use crate::lmx2594::{self, ChipSelect, Error};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Write;
+
+/// A tuning profile, see the module documentation
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum Profile {
+    /// Lowest phase noise, as in `REG_MAP`
+    #[default]
+    Performance,
+    /// Fastest lock after a retune
+    FastLock,
+}
+
+/// Fields set by each profile, with their Performance and FastLock values,
+/// in descending register order as they are written
+const SETTINGS: [(Field, u32, u32); 3] = [
+    (fields::QUICK_RECAL_EN, 0, 1),
+    (fields::CPG, 7, 7),
+    (fields::ACAL_CMP_DLY, 10, 5),
+];
+
+/// Set `profile` in a register map. This only touches the map in RAM; see
+/// `set_profile` to change a running device.
+pub fn apply_profile(regs: &mut [u32; 113], profile: Profile) {
+    for &(field, performance, fast_lock) in &SETTINGS {
+        let value = match profile {
+            Profile::Performance => performance,
+            Profile::FastLock => fast_lock,
+        };
+        field.set(regs, value);
+    }
+}
+
+/// Set `profile` in `regs` and write the registers it changes to the
+/// device. It takes effect from the next calibration, e.g. the next retune.
+pub fn set_profile<S, C>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    profile: Profile,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+{
+    apply_profile(regs, profile);
+    for &(field, _, _) in &SETTINGS {
+        lmx2594::write_word(spi, spi_cs, buf, regs[field.addr as usize])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::REG_MAP;
+    use crate::mock;
+
+    /// Registers of `regs` that differ from `REG_MAP`, with their values
+    fn deltas(regs: &[u32; 113]) -> Vec<u32> {
+        regs.iter()
+            .zip(REG_MAP.iter())
+            .filter(|(r, base)| r != base)
+            .map(|(r, _)| *r)
+            .collect()
+    }
+
+    #[test]
+    fn performance_is_the_built_in_map() {
+        let mut regs = REG_MAP;
+        apply_profile(&mut regs, Profile::FastLock);
+        apply_profile(&mut regs, Profile::Performance);
+        assert!(deltas(&regs).is_empty());
+    }
+
+    #[test]
+    fn fast_lock_changes_calibration_and_writes_it() {
+        let r78 = REG_MAP[78] | 1 << 9;
+        let r4 = REG_MAP[4] & !0xff00 | 5 << 8;
+        let expected = [r78, REG_MAP[14], r4];
+        let mut spi = mock::Spi::new(&mock::writes(expected.iter().copied()));
+        let mut cs = mock::Pin::new(&mock::cs_frames(expected.len()));
+        let mut regs = REG_MAP;
+
+        set_profile(&mut spi, &mut cs, &mut [0; 3], &mut regs, Profile::FastLock).unwrap();
+        assert_eq!(deltas(&regs), [r4, r78]);
+        spi.done();
+        cs.done();
+    }
+}
@@ -0,0 +1,152 @@
+//! Time base for frequency ramps clocked from the RampCLK pin.
+//!
+//! With the ramp clocked externally, every rising edge on RampCLK (uWire
+//! pin 7) advances the ramp by one step, so the chirp slope is set by the
+//! RampCLK rate. The Pico generates RampCLK from a PWM slice running at 50%
+//! duty, whose rate is
+//!
+//! ```text
+//! fRAMPCLK = fSYS / ((TOP + 1) * (DIV_INT + DIV_FRAC / 16))
+//! ```
+
+use crate::frequency::Frequency;
+use crate::lmx2594::{Error, Pin1Function};
+use core::fmt::Debug;
+use embedded_hal::digital::v2::OutputPin;
+
+/// PWM settings generating RampCLK
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct RampClock {
+    /// Integer part of the PWM clock divider, 1-255
+    pub div_int: u8,
+    /// Fractional part of the PWM clock divider in 16ths, 0-15
+    pub div_frac: u8,
+    /// PWM counter wrap value, at least 1 so the output can toggle
+    pub top: u16,
+}
+
+impl RampClock {
+    /// Check the settings, rejecting a divider or wrap value the PWM slice
+    /// cannot use with `Error::InvalidRampClock`
+    pub fn new(div_int: u8, div_frac: u8, top: u16) -> Result<Self, Error> {
+        if div_int == 0 || div_frac > 15 || top == 0 {
+            defmt::warn!(
+                "Invalid RampCLK divider {}+{}/16, TOP {}",
+                div_int,
+                div_frac,
+                top
+            );
+            return Err(Error::InvalidRampClock);
+        }
+        Ok(RampClock {
+            div_int,
+            div_frac,
+            top,
+        })
+    }
+
+    /// System clock cycles per RampCLK period, in 16ths of a cycle
+    fn period_sixteenths(&self) -> u64 {
+        (self.top as u64 + 1) * (self.div_int as u64 * 16 + self.div_frac as u64)
+    }
+
+    /// RampCLK rate, i.e. ramp steps per second, from a system clock of
+    /// `sys_clk`, rounded down to the nearest Hz
+    pub fn step_rate(&self, sys_clk: Frequency) -> Frequency {
+        Frequency::from_hz(sys_clk.as_hz() * 16 / self.period_sixteenths())
+    }
+
+    /// Duration of one ramp step in ns from a system clock of `sys_clk`,
+    /// rounded to the nearest ns
+    pub fn step_time_ns(&self, sys_clk: Frequency) -> u64 {
+        let den = sys_clk.as_hz().max(1) * 16;
+        (self.period_sixteenths() * 1_000_000_000 + den / 2) / den
+    }
+}
+
+/// Level to drive on the RAMPDIR input
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum RampDir {
+    /// RAMPDIR high
+    Up,
+    /// RAMPDIR low
+    Down,
+}
+
+/// Drive the RAMPDIR input on uWire pin 1, given what `pin1` says the pin
+/// is wired to.
+///
+/// If pin 1 is the chip enable, nothing is driven and
+/// `Error::Pin1IsChipEnable` is returned, since pulling it low for `Down`
+/// would power the device off.
+pub fn set_ramp_dir<P>(pin: &mut P, pin1: Pin1Function, dir: RampDir) -> Result<(), Error>
+where
+    P: OutputPin,
+    P::Error: Debug,
+{
+    if pin1 != Pin1Function::RampDir {
+        return Err(Error::Pin1IsChipEnable);
+    }
+    match dir {
+        RampDir::Up => pin.set_high(),
+        RampDir::Down => pin.set_low(),
+    }
+    .map_err(|_| Error::PinFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, PinTransaction, State};
+
+    #[test]
+    fn ramp_dir_drives_pin_1_when_wired_to_rampdir() {
+        let mut pin = mock::Pin::new(&[
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+        ]);
+        set_ramp_dir(&mut pin, Pin1Function::RampDir, RampDir::Up).unwrap();
+        set_ramp_dir(&mut pin, Pin1Function::RampDir, RampDir::Down).unwrap();
+        pin.done();
+    }
+
+    #[test]
+    fn ramp_dir_leaves_chip_enable_alone() {
+        let mut pin = mock::Pin::new(&[]);
+        for dir in [RampDir::Up, RampDir::Down] {
+            assert_eq!(
+                set_ramp_dir(&mut pin, Pin1Function::ChipEnable, dir),
+                Err(Error::Pin1IsChipEnable)
+            );
+        }
+        pin.done();
+    }
+
+    #[test]
+    fn divider_and_wrap_set_the_step_rate() {
+        let sys_clk = Frequency::from_mhz(125);
+        let clock = RampClock::new(1, 0, 124).unwrap();
+        assert_eq!(clock.step_rate(sys_clk), Frequency::from_mhz(1));
+        assert_eq!(clock.step_time_ns(sys_clk), 1_000);
+
+        // A fractional divider of 2.5
+        let clock = RampClock::new(2, 8, 99).unwrap();
+        assert_eq!(clock.step_rate(sys_clk), Frequency::from_khz(500));
+        assert_eq!(clock.step_time_ns(sys_clk), 2_000);
+
+        // The slowest clock: 7.45 Hz rounds down, 134.18496 ms to the ns
+        let clock = RampClock::new(255, 15, u16::MAX).unwrap();
+        assert_eq!(clock.step_rate(sys_clk), Frequency::from_hz(7));
+        assert_eq!(clock.step_time_ns(sys_clk), 134_184_960);
+    }
+
+    #[test]
+    fn ramp_clock_rejects_settings_the_pwm_cannot_use() {
+        for (div_int, div_frac, top) in [(0, 0, 1), (1, 16, 1), (1, 0, 0)] {
+            assert_eq!(
+                RampClock::new(div_int, div_frac, top),
+                Err(Error::InvalidRampClock)
+            );
+        }
+    }
+}
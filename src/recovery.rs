@@ -0,0 +1,108 @@
+//! Escalating recovery from bus faults.
+//!
+//! A failed write or readback is usually a transient glitch on the uWire
+//! lines rather than a dead device. The firmware first retries after
+//! reinitializing the SPI peripheral, and if the fault keeps coming back,
+//! after a full reset: powering the device off through CE and bringing it
+//! up again. `FaultTracker` decides which step comes next; it holds no
+//! peripherals, so the ladder can be checked on its own.
+
+/// Default number of SPI reinitializations before a full reset
+pub const DEFAULT_SPI_REINITS: u32 = 2;
+/// Default number of full resets before giving up
+pub const DEFAULT_FULL_RESETS: u32 = 1;
+
+/// What to do before retrying a failed operation
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Recovery {
+    /// Reset and reconfigure the SPI peripheral, see
+    /// `Lmx2594Driver::reinit_spi`
+    ReinitSpi,
+    /// Power the device off and bring it up again, see
+    /// `Lmx2594Driver::power_off`
+    FullReset,
+    /// Recovery has not helped; stop retrying and report the fault
+    GiveUp,
+}
+
+/// Counts consecutive bus faults and escalates the recovery for each
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct FaultTracker {
+    spi_reinits: u32,
+    full_resets: u32,
+    failures: u32,
+}
+
+impl FaultTracker {
+    /// Allow `spi_reinits` SPI reinitializations in a row, then
+    /// `full_resets` full resets, before giving up
+    pub const fn new(spi_reinits: u32, full_resets: u32) -> Self {
+        FaultTracker {
+            spi_reinits,
+            full_resets,
+            failures: 0,
+        }
+    }
+
+    /// Record a failed operation, returning what to do before retrying it
+    pub fn record_failure(&mut self) -> Recovery {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures <= self.spi_reinits {
+            Recovery::ReinitSpi
+        } else if self.failures <= self.spi_reinits + self.full_resets {
+            Recovery::FullReset
+        } else {
+            Recovery::GiveUp
+        }
+    }
+
+    /// Record a successful operation, which starts the ladder over
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Failures since the last success
+    pub fn consecutive_failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+impl Default for FaultTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPI_REINITS, DEFAULT_FULL_RESETS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::Error;
+
+    #[test]
+    fn escalates_from_spi_reinit_to_full_reset_to_giving_up() {
+        let mut faults = FaultTracker::new(2, 1);
+        let ladder = [
+            Recovery::ReinitSpi,
+            Recovery::ReinitSpi,
+            Recovery::FullReset,
+            Recovery::GiveUp,
+            Recovery::GiveUp,
+        ];
+        for expected in ladder {
+            assert_eq!(faults.record_failure(), expected);
+        }
+        assert_eq!(faults.consecutive_failures(), 5);
+
+        faults.record_success();
+        assert_eq!(faults.consecutive_failures(), 0);
+        assert_eq!(faults.record_failure(), Recovery::ReinitSpi);
+    }
+
+    #[test]
+    fn only_bus_faults_are_retried() {
+        assert!(Error::WriteFailed(7).is_bus_fault());
+        assert!(Error::NotPresent.is_bus_fault());
+        assert!(!Error::LockTimeout.is_bus_fault());
+        assert!(!Error::OutOfRange.is_bus_fault());
+    }
+}
@@ -0,0 +1,181 @@
+//! Inferring an unknown reference frequency from where the PLL locks.
+//!
+//! Programming the VCO for exactly `VCO_MIN` on the assumption of a guessed
+//! reference puts the real VCO at `VCO_MIN * actual / guess`. A guess at or
+//! below the actual reference leaves the VCO inside its range, where it
+//! locks; a guess above it pushes the VCO below the range, where it does
+//! not. Lock is therefore a yes/no answer to "is the reference at least
+//! this fast?", and bisecting on it measures the reference.
+//!
+//! The answer is only as sharp as the bottom of the VCO range on the part
+//! at hand. Most silicon calibrates a little below the guaranteed 7.5 GHz,
+//! which makes the estimate read high by the same fraction, so treat it as
+//! a bring-up aid for unknown boards rather than a frequency counter.
+
+use crate::frequency::{Frequency, SolveMode, VCO_MIN};
+use crate::lmx2594::{self, ChipSelect, Error, ProgramTiming, RetunePolicy};
+use core::fmt::Debug;
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    spi::{Transfer, Write},
+};
+
+/// Most lock checks `bisect_reference` makes, however fine the resolution
+pub const MAX_STEPS: u32 = 32;
+
+/// Find the fastest reference between `lo` and `hi` for which `locks`
+/// reports lock, to within `resolution` or `MAX_STEPS` lock checks,
+/// whichever comes first.
+///
+/// `locks` must hold at `lo`, which is checked first; if it does not, the
+/// search gives up with `Error::LockTimeout`. A reference that still locks
+/// at `hi` is returned as `hi`.
+pub fn bisect_reference<F>(
+    lo: Frequency,
+    hi: Frequency,
+    resolution: Frequency,
+    mut locks: F,
+) -> Result<Frequency, Error>
+where
+    F: FnMut(Frequency) -> Result<bool, Error>,
+{
+    if !locks(lo)? {
+        defmt::warn!("No lock even with a {} reference", lo);
+        return Err(Error::LockTimeout);
+    }
+    let (mut lo, mut hi) = (lo.as_hz(), hi.as_hz());
+    if locks(Frequency::from_hz(hi))? {
+        return Ok(Frequency::from_hz(hi));
+    }
+    // Invariant: lo locks and hi does not
+    let step = resolution.as_hz().max(1);
+    for _ in 2..MAX_STEPS {
+        if hi - lo <= step {
+            break;
+        }
+        let mid = lo + (hi - lo) / 2;
+        if locks(Frequency::from_hz(mid))? {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(Frequency::from_hz(lo))
+}
+
+/// Whether the PLL locks with `regs` retuned to `VCO_MIN` on the
+/// assumption of a reference of `guess`. `regs` is left untouched; the
+/// device is left with the trial settings.
+pub fn locks_at<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+    guess: Frequency,
+    timing: &ProgramTiming,
+) -> Result<bool, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let mut trial = *regs;
+    match lmx2594::change_frequency(
+        spi,
+        spi_cs,
+        delay,
+        buf,
+        &mut trial,
+        guess,
+        VCO_MIN,
+        SolveMode::Fractional,
+        RetunePolicy::Live,
+        timing,
+    ) {
+        Ok(_) => Ok(true),
+        Err(Error::LockTimeout) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Measure the reference of a running device, somewhere between `lo` and
+/// `hi`, by `bisect_reference` over `locks_at`.
+///
+/// `regs` must hold the reference path the device is running with and is
+/// left untouched; the device is left at the last trial, so program it
+/// again with the inferred reference afterwards. Each guess must give a
+/// usable fractional-mode PFD through that path, or the search stops with
+/// the solver's error.
+#[allow(clippy::too_many_arguments)]
+pub fn detect_reference<S, C, D>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &[u32; 113],
+    lo: Frequency,
+    hi: Frequency,
+    resolution: Frequency,
+    timing: &ProgramTiming,
+) -> Result<Frequency, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+{
+    let fref = bisect_reference(lo, hi, resolution, |guess| {
+        locks_at(spi, spi_cs, delay, buf, regs, guess, timing)
+    })?;
+    defmt::info!("Reference looks like {}", fref);
+    Ok(fref)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bisection_closes_in_on_the_fastest_locking_guess() {
+        // A model of the PLL: it locks for any guess up to the reference
+        let actual = Frequency::from_hz(61_440_000);
+        let mut checks = 0;
+        let locks = |guess: Frequency| {
+            checks += 1;
+            Ok(guess <= actual)
+        };
+        let lo = Frequency::from_mhz(10);
+        let hi = Frequency::from_mhz(200);
+        let found = bisect_reference(lo, hi, Frequency::from_khz(1), locks).unwrap();
+        assert!(found <= actual && actual.as_hz() - found.as_hz() <= 1_000);
+        assert!(checks <= MAX_STEPS);
+
+        // Bounded even when the resolution cannot be reached
+        let mut checks = 0;
+        let locks = |guess: Frequency| {
+            checks += 1;
+            Ok(guess <= actual)
+        };
+        let far = Frequency::from_ghz(1_000_000);
+        let found = bisect_reference(lo, far, Frequency::from_hz(1), locks).unwrap();
+        assert_eq!(checks, MAX_STEPS);
+        assert!(found <= actual);
+
+        let never = |_| Ok(false);
+        assert_eq!(
+            bisect_reference(lo, hi, actual, never),
+            Err(Error::LockTimeout)
+        );
+        let always = |_| Ok(true);
+        assert_eq!(bisect_reference(lo, hi, actual, always), Ok(hi));
+        let failing = |_| Err(Error::ReadFailed(110));
+        assert_eq!(
+            bisect_reference(lo, hi, actual, failing),
+            Err(Error::ReadFailed(110))
+        );
+    }
+}
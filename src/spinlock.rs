@@ -0,0 +1,36 @@
+//! Sharing the SPI bus between the two RP2040 cores through the SIO
+//! hardware spinlocks, for `lmx2594::LockedCs`.
+
+use crate::lmx2594::BusLock;
+use rp_pico::hal::sio::Spinlock;
+
+/// A `BusLock` on one of the SIO hardware spinlocks, which both cores see.
+///
+/// Every user of the shared bus must wrap its chip select with the same
+/// spinlock number, and nothing else may claim that spinlock. It is not
+/// re-entrant and does not mask interrupts, so the bus must not also be
+/// used from an interrupt handler on either core.
+pub struct SpinlockBus<S: Spinlock>(Option<S>);
+
+impl<S: Spinlock> SpinlockBus<S> {
+    pub fn new() -> Self {
+        SpinlockBus(None)
+    }
+}
+
+impl<S: Spinlock> Default for SpinlockBus<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Spinlock> BusLock for SpinlockBus<S> {
+    fn acquire(&mut self) {
+        self.0 = Some(S::claim());
+    }
+
+    fn release(&mut self) {
+        // Dropping the claim unlocks the spinlock
+        self.0 = None;
+    }
+}
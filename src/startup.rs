@@ -0,0 +1,218 @@
+//! Helpers for bringing up the Pico itself before the LMX2594 is touched.
+//!
+//! Failures here happen before there is anything useful to talk to, so the
+//! only diagnostic channel we can rely on is the on-board LED.
+
+use embedded_hal::{
+    blocking::delay::DelayMs,
+    digital::v2::{InputPin, OutputPin},
+};
+
+/// Number of times clock initialization is attempted before giving up
+pub const CLOCK_INIT_ATTEMPTS: u32 = 3;
+
+/// Length of a Morse "dit" on the status LED, in milliseconds. Everything
+/// else blinked on it is a multiple of this, here and in `morse`.
+pub const DOT_MS: u32 = 150;
+
+/// A peripheral singleton had already been taken
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct AlreadyTaken(pub &'static str);
+
+/// Check the result of a singleton `take()`, such as
+/// `pac::Peripherals::take()`, logging which `name`d singleton was missing.
+///
+/// `take()` only fails if it has been called before, which in practice
+/// means `main` was re-entered without a full reset; a bare `unwrap()`
+/// would leave nothing to go on.
+pub fn take<T>(taken: Option<T>, name: &'static str) -> Result<T, AlreadyTaken> {
+    taken.ok_or_else(|| {
+        defmt::error!(
+            "{} already taken; was main re-entered without a reset?",
+            name
+        );
+        AlreadyTaken(name)
+    })
+}
+
+/// Where the register map programmed at boot comes from
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum BootMap {
+    /// The map stored in flash, if there is one
+    Stored,
+    /// The built-in map, ignoring anything stored, as after `factory_reset`
+    FactoryDefaults,
+}
+
+/// Decide the boot map from a recovery `button`, which is held when it
+/// reads low if `active_low`, or high otherwise.
+///
+/// Holding the button at power-up is the way out of a stored map that
+/// leaves the board unusable. A pin that cannot be read counts as released,
+/// so a flaky button never discards a good map.
+pub fn boot_map<P: InputPin>(button: &P, active_low: bool) -> BootMap {
+    let held = if active_low {
+        button.is_low()
+    } else {
+        button.is_high()
+    };
+    match held {
+        Ok(true) => {
+            defmt::warn!("Recovery button held, booting factory defaults");
+            BootMap::FactoryDefaults
+        }
+        Ok(false) => BootMap::Stored,
+        Err(_) => {
+            defmt::warn!("Could not read the recovery button");
+            BootMap::Stored
+        }
+    }
+}
+
+/// Stop here for good, leaving the log intact for a debug probe
+pub fn halt() -> ! {
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Report a fault the firmware cannot continue from: log `what` and blink
+/// SOS on the LED for good
+pub fn fault<P: OutputPin, D: DelayMs<u32>>(what: &str, led: &mut P, delay: &mut D) -> ! {
+    defmt::error!("{}, giving up", what);
+    loop {
+        blink_sos(led, delay);
+    }
+}
+
+/// Run `op` up to `attempts` times, returning the first success.
+///
+/// `on_fail` is called with the (zero-based) attempt number and the error
+/// after every failed attempt, including the last one. If every attempt
+/// fails, the error from the last attempt is returned; with `attempts` of
+/// zero `op` is never run and the error is `None`.
+pub fn retry<T, E>(
+    attempts: u32,
+    mut op: impl FnMut(u32) -> Result<T, E>,
+    mut on_fail: impl FnMut(u32, &E),
+) -> Result<T, Option<E>> {
+    let mut last = None;
+    for attempt in 0..attempts {
+        match op(attempt) {
+            Ok(t) => return Ok(t),
+            Err(e) => {
+                on_fail(attempt, &e);
+                last = Some(e);
+            }
+        }
+    }
+    Err(last)
+}
+
+/// Blink a single SOS (`... --- ...`) on the LED.
+///
+/// All Results are Infallible for the rp2040 GPIO, so they are ignored.
+pub fn blink_sos<P: OutputPin, D: DelayMs<u32>>(led: &mut P, delay: &mut D) {
+    for &units in [1, 1, 1, 3, 3, 3, 1, 1, 1].iter() {
+        blink_element(led, delay, units);
+    }
+    // Word gap (7 units, one already spent above)
+    delay.delay_ms(6 * DOT_MS);
+}
+
+/// Light the LED for one Morse element of `units` dits, then leave it off
+/// for the one-dit gap that follows every element
+pub fn blink_element<P: OutputPin, D: DelayMs<u32>>(led: &mut P, delay: &mut D, units: u32) {
+    led.set_high().ok();
+    delay.delay_ms(units * DOT_MS);
+    led.set_low().ok();
+    delay.delay_ms(DOT_MS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Event, Pin, PinTransaction, State, Trace};
+
+    #[test]
+    fn take_names_the_missing_singleton() {
+        assert_eq!(take(Some(7), "pac::Peripherals"), Ok(7));
+        assert_eq!(
+            take(None::<u32>, "pac::Peripherals"),
+            Err(AlreadyTaken("pac::Peripherals"))
+        );
+    }
+
+    #[test]
+    fn held_button_boots_factory_defaults() {
+        let cases = [
+            (true, State::Low, BootMap::FactoryDefaults),
+            (true, State::High, BootMap::Stored),
+            (false, State::High, BootMap::FactoryDefaults),
+            (false, State::Low, BootMap::Stored),
+        ];
+        for (active_low, level, expected) in cases {
+            let mut button = Pin::new(&[PinTransaction::get(level)]);
+            assert_eq!(boot_map(&button, active_low), expected);
+            button.done();
+        }
+    }
+
+    #[test]
+    fn retry_stops_at_the_first_success() {
+        let mut failed = Vec::new();
+        let result = retry(
+            5,
+            |attempt| {
+                if attempt < 2 {
+                    Err(attempt)
+                } else {
+                    Ok(attempt)
+                }
+            },
+            |attempt, e| failed.push((attempt, *e)),
+        );
+        assert_eq!(result, Ok(2));
+        assert_eq!(failed, [(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn retry_reports_every_failure_and_returns_the_last() {
+        let mut runs = 0;
+        let mut failed = Vec::new();
+        let result: Result<(), _> = retry(
+            3,
+            |attempt| {
+                runs += 1;
+                Err(attempt * 10)
+            },
+            |attempt, e| failed.push((attempt, *e)),
+        );
+        assert_eq!(result, Err(Some(20)));
+        assert_eq!(runs, 3);
+        assert_eq!(failed, [(0, 0), (1, 10), (2, 20)]);
+    }
+
+    #[test]
+    fn retry_with_no_attempts_never_runs() {
+        let result: Result<(), Option<()>> = retry(0, |_| panic!("ran"), |_, _| panic!("failed"));
+        assert_eq!(result, Err(None));
+    }
+
+    #[test]
+    fn sos_blinks_three_dots_three_dashes_three_dots() {
+        let trace = Trace::new();
+        blink_sos(&mut trace.pin(), &mut trace.delay());
+        let mut expected = Vec::new();
+        for units in [1, 1, 1, 3, 3, 3, 1, 1, 1] {
+            expected.extend([
+                Event::Pin(true),
+                Event::Delay(units * DOT_MS),
+                Event::Pin(false),
+                Event::Delay(DOT_MS),
+            ]);
+        }
+        expected.push(Event::Delay(6 * DOT_MS));
+        assert_eq!(trace.events(), expected);
+    }
+}
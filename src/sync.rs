@@ -0,0 +1,235 @@
+//! Phase realignment through the SYNC pin.
+//!
+//! With VCO_PHASE_SYNC set in R0, a rising edge on SYNC (uWire pin 10)
+//! resets the dividers so that the output phase is deterministic relative
+//! to the reference. In fractional mode a retune loses that alignment, so
+//! phase coherent setups pulse SYNC again once the PLL has relocked.
+//! VCO_PHASE_SYNC is left to the register map.
+//!
+//! Several synthesizers share a phase reference the same way: with their
+//! SYNC pins tied to one GPIO, `program_coherent` programs each in turn and
+//! then realigns them all with a single pulse.
+
+use crate::frequency::{Frequency, FrequencyPlan, SolveMode};
+use crate::lmx2594::{self, ChipSelect, Error, ProgramOptions, ProgramTiming, RetunePolicy};
+use core::fmt::Debug;
+use embedded_hal::{
+    blocking::{
+        delay::DelayMs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+
+/// Raise and lower SYNC once
+pub fn sync_pulse<P>(sync: &mut P) -> Result<(), Error>
+where
+    P: OutputPin,
+    P::Error: Debug,
+{
+    sync.set_high().map_err(|_| Error::PinFailed)?;
+    sync.set_low().map_err(|_| Error::PinFailed)
+}
+
+/// `lmx2594::change_frequency`, then a `sync_pulse` on `sync` once the PLL
+/// has locked. With `sync` as `None`, for boards without SYNC wired, this
+/// is just `change_frequency`. Under `RetunePolicy::Deferred` nothing has
+/// locked yet, so SYNC is left alone; pulse it after `commit_calibration`.
+#[allow(clippy::too_many_arguments)]
+pub fn change_frequency_synced<S, C, D, P>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    fref: Frequency,
+    target: Frequency,
+    mode: SolveMode,
+    policy: RetunePolicy,
+    timing: &ProgramTiming,
+    sync: Option<&mut P>,
+) -> Result<FrequencyPlan, Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+    P: OutputPin,
+    P::Error: Debug,
+{
+    let plan = lmx2594::change_frequency(
+        spi, spi_cs, delay, buf, regs, fref, target, mode, policy, timing,
+    )?;
+    if let Some(sync) = sync.filter(|_| policy != RetunePolicy::Deferred) {
+        sync_pulse(sync)?;
+    }
+    Ok(plan)
+}
+
+/// One synthesizer in a phase-coherent group, see `program_coherent`
+pub struct Device<'a, C> {
+    /// Its own CSB on the shared bus
+    pub spi_cs: C,
+    /// The register map to program it with
+    pub regs: &'a [u32; 113],
+}
+
+/// Program every device in `devices`, wait for each to lock, then align
+/// their outputs with one `sync_pulse` on `sync`.
+///
+/// The LMX2594 has no serial data output, so uWire cannot be daisy-chained
+/// through the devices; they share SCK and SDI instead, with a CSB each.
+/// Their SYNC pins are tied together and driven from `sync`, so that the
+/// one rising edge reaches all of them at once, and each map needs
+/// VCO_PHASE_SYNC set. Lock is read back over MUXout from one device at a
+/// time, so with the MUXout pins joined onto MISO, each needs a buffer
+/// enabled by its own CSB. All devices must be fed the same reference.
+///
+/// Nothing is pulsed unless every device programmed and locked; the first
+/// error stops the sequence, leaving the later devices as they were.
+pub fn program_coherent<S, C, D, P>(
+    spi: &mut S,
+    delay: &mut D,
+    buf: &mut [u8; 3],
+    devices: &mut [Device<C>],
+    options: &ProgramOptions,
+    timing: &ProgramTiming,
+    sync: &mut P,
+) -> Result<(), Error>
+where
+    S: Transfer<u8> + Write<u8>,
+    <S as Transfer<u8>>::Error: Debug,
+    <S as Write<u8>>::Error: Debug,
+    C: ChipSelect,
+    D: DelayMs<u32>,
+    P: OutputPin,
+    P::Error: Debug,
+{
+    for device in devices.iter_mut() {
+        lmx2594::program_all(
+            spi,
+            &mut device.spi_cs,
+            delay,
+            buf,
+            device.regs,
+            options,
+            timing,
+        )?;
+    }
+    for device in devices.iter_mut() {
+        lmx2594::wait_for_lock(
+            spi,
+            &mut device.spi_cs,
+            delay,
+            buf,
+            device.regs[0],
+            timing.lock_timeout_ms,
+            timing.lock_debounce,
+        )?;
+    }
+    sync_pulse(sync)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{self, FCAL_EN};
+    use crate::lmx2594::{HardwareCs, REG_MAP};
+    use crate::mock::{self, Event};
+
+    const FREF: Frequency = Frequency::from_mhz(100);
+    const TARGET: Frequency = Frequency::from_mhz(10_050);
+
+    fn trace_retune(sync: bool) -> Vec<Event> {
+        let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+        let trace = mock::Trace::with_readback(locked);
+        let mut pin = trace.pin();
+        let mut regs = REG_MAP;
+        change_frequency_synced(
+            &mut trace.spi(),
+            &mut HardwareCs,
+            &mut trace.delay(),
+            &mut [0; 3],
+            &mut regs,
+            FREF,
+            TARGET,
+            SolveMode::Auto,
+            RetunePolicy::default(),
+            &ProgramTiming::default(),
+            if sync { Some(&mut pin) } else { None },
+        )
+        .unwrap();
+        trace.events()
+    }
+
+    #[test]
+    fn sync_follows_the_retune_and_lock() {
+        let events = trace_retune(true);
+        let (retune, pulse) = events.split_at(events.len() - 2);
+        assert_eq!(pulse, [Event::Pin(true), Event::Pin(false)]);
+        let mut expected = REG_MAP;
+        crate::frequency::set_frequency(FREF, &mut expected, TARGET).unwrap();
+        for addr in [34, 36, 42, 43] {
+            assert!(retune.contains(&Event::Write(expected[addr])));
+        }
+        assert!(retune.contains(&Event::Write(REG_MAP[0] & !FCAL_EN.mask())));
+        assert!(retune.contains(&Event::Read(110)));
+
+        // Without SYNC wired, the same retune and nothing else
+        assert_eq!(trace_retune(false), retune);
+    }
+
+    #[test]
+    fn one_shared_sync_follows_every_device() {
+        let locked = (fields::RB_LD_VTUNE_LOCKED as u16) << 9;
+        let trace = mock::Trace::with_readback(locked);
+        let mut second = REG_MAP;
+        crate::frequency::set_frequency(FREF, &mut second, TARGET).unwrap();
+        let mut devices = [
+            Device {
+                spi_cs: HardwareCs,
+                regs: &REG_MAP,
+            },
+            Device {
+                spi_cs: HardwareCs,
+                regs: &second,
+            },
+        ];
+        let program = |trace: &mock::Trace, devices: &mut [Device<HardwareCs>]| {
+            program_coherent(
+                &mut trace.spi(),
+                &mut trace.delay(),
+                &mut [0; 3],
+                devices,
+                &ProgramOptions::default(),
+                &ProgramTiming::default(),
+                &mut trace.pin(),
+            )
+        };
+        program(&trace, &mut devices).unwrap();
+
+        // Both maps go out in full, one after the other, then both are
+        // checked for lock, and only then is SYNC pulsed, once
+        let events = trace.events();
+        let (rest, pulse) = events.split_at(events.len() - 2);
+        assert_eq!(pulse, [Event::Pin(true), Event::Pin(false)]);
+        assert!(!rest.iter().any(|e| matches!(e, Event::Pin(_))));
+        let position = |word: u32| rest.iter().position(|e| *e == Event::Write(word));
+        assert!(position(REG_MAP[36]) < position(second[36]));
+        // R0 is written with the map and again to calibrate, per device,
+        // all before the first lock check
+        let first_read = rest.iter().position(|e| *e == Event::Read(110)).unwrap();
+        let (programming, _) = rest.split_at(first_read);
+        let calibrations = programming
+            .iter()
+            .filter(|e| **e == Event::Write(REG_MAP[0]));
+        assert_eq!(calibrations.count(), 4);
+        assert_eq!(rest.iter().filter(|e| **e == Event::Read(110)).count(), 6);
+
+        // A device that never locks gets no pulse
+        let trace = mock::Trace::new();
+        assert_eq!(program(&trace, &mut devices), Err(Error::LockTimeout));
+        assert!(!trace.events().iter().any(|e| matches!(e, Event::Pin(_))));
+    }
+}
@@ -0,0 +1,102 @@
+//! SYSREF bursts for JESD204B deterministic latency.
+//!
+//! In pulsed master mode the LMX2594 generates SYSREF itself, but only
+//! sends a burst of `SYSREF_PULSE_CNT` pulses each time the SysRefReq pin
+//! (uWire pin 9) rises, rather than running continuously. SYSREF comes out
+//! of OUTB, so OUTB_MUX must select it (`fields::OUT_MUX_SYSREF`), and the
+//! SYSREF divider in R71-R72 sets the pulse rate; both are left to the
+//! register map.
+
+use crate::fields;
+use crate::lmx2594::{self, ChipSelect, Error};
+use core::fmt::Debug;
+use embedded_hal::{blocking::spi::Write, digital::v2::OutputPin};
+
+/// Most pulses in one burst
+pub const MAX_PULSES: u8 = 15;
+
+/// Put the shadow map `regs` in pulsed master mode with `count` pulses per
+/// burst, 1 to `MAX_PULSES`, without writing anything to the device.
+pub fn configure_pulser(regs: &mut [u32; 113], count: u8) -> Result<(), Error> {
+    if count == 0 || count > MAX_PULSES {
+        return Err(Error::InvalidPulseCount(count));
+    }
+    fields::SYSREF_PULSE_CNT.set(regs, count as u32);
+    fields::SYSREF_REPEAT.set(regs, 0);
+    fields::SYSREF_EN.set(regs, 1);
+    fields::SYSREF_PULSE.set(regs, 1);
+    Ok(())
+}
+
+/// Send one burst of `count` SYSREF pulses: configure the pulser as
+/// `configure_pulser` does, write R74 and R71, then raise and lower
+/// SysRefReq on `req` to trigger it.
+///
+/// A count out of range is rejected with `Error::InvalidPulseCount`
+/// before anything is written or driven.
+pub fn sysref_pulse<S, C, P>(
+    spi: &mut S,
+    spi_cs: &mut C,
+    buf: &mut [u8; 3],
+    regs: &mut [u32; 113],
+    req: &mut P,
+    count: u8,
+) -> Result<(), Error>
+where
+    S: Write<u8>,
+    S::Error: Debug,
+    C: ChipSelect,
+    P: OutputPin,
+    P::Error: Debug,
+{
+    configure_pulser(regs, count)?;
+    for field in [fields::SYSREF_PULSE_CNT, fields::SYSREF_EN] {
+        lmx2594::write_word(spi, spi_cs, buf, regs[field.addr as usize])?;
+    }
+    req.set_high().map_err(|_| Error::PinFailed)?;
+    req.set_low().map_err(|_| Error::PinFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::REG_MAP;
+    use crate::mock::{self, PinTransaction, State};
+
+    #[test]
+    fn burst_writes_count_and_mode_then_strobes_request() {
+        let r74 = 0x4a_3000;
+        let r71 = REG_MAP[71] | 0b11 << 3;
+        let mut spi = mock::Spi::new(&mock::writes([r74, r71]));
+        let mut cs = mock::Pin::new(&mock::cs_frames(2));
+        let mut req = mock::Pin::new(&[
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+        ]);
+
+        let mut regs = REG_MAP;
+        sysref_pulse(&mut spi, &mut cs, &mut [0; 3], &mut regs, &mut req, 3).unwrap();
+        assert_eq!((regs[71], regs[74]), (r71, r74));
+
+        spi.done();
+        cs.done();
+        req.done();
+    }
+
+    #[test]
+    fn burst_length_out_of_range_does_nothing() {
+        let mut spi = mock::Spi::new(&[]);
+        let mut cs = mock::Pin::new(&[]);
+        let mut req = mock::Pin::new(&[]);
+        let mut regs = REG_MAP;
+        for count in [0, MAX_PULSES + 1] {
+            let result = sysref_pulse(&mut spi, &mut cs, &mut [0; 3], &mut regs, &mut req, count);
+            assert_eq!(result, Err(Error::InvalidPulseCount(count)));
+        }
+        assert_eq!(regs, REG_MAP);
+
+        spi.done();
+        cs.done();
+        req.done();
+    }
+}
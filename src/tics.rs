@@ -0,0 +1,317 @@
+//! Register maps from TICS Pro hex exports, parsed at compile time.
+//!
+//! TICS Pro exports a register map as one `R<addr>\t0x<word>` line per
+//! register, R112 first, the same format `lmx2594::write_hex_dump` writes.
+//! Dropping the export into the project and parsing it in a `static` saves
+//! transcribing it into `REG_MAP` by hand, and a bad export fails the build:
+//!
+//! ```ignore
+//! static MAP: [u32; 113] = match tics::parse_hex_export(include_str!("../maps/board.txt")) {
+//!     Ok(map) => map,
+//!     Err(_) => panic!("bad TICS Pro export"),
+//! };
+//! ```
+//!
+//! Blank lines and surrounding whitespace are ignored, and the `0x` prefix
+//! is optional. Every register R0-R112 must appear exactly once, and the
+//! address byte of each word must match its `R<addr>` label.
+//!
+//! A `//` starts a comment running to the end of the line, as in `REG_MAP`.
+//! The firmware parser skips comments; on host builds
+//! `parse_annotated_export` keeps the one after each register and
+//! `write_annotated_export` puts it back, so an annotated map survives a
+//! round trip through the tools. Comments on lines of their own are not
+//! kept.
+
+use core::fmt;
+#[cfg(feature = "host")]
+use std::{string::String, vec::Vec};
+
+/// Why an export was rejected
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum TicsError {
+    /// The line, counting from 1, is not an `R<addr> 0x<word>` pair, or its
+    /// word is not addressed to `R<addr>`
+    Malformed(u32),
+    /// A line addresses a register beyond R112
+    InvalidAddress(u8),
+    /// Two lines address the same register
+    DuplicateAddress(u8),
+    /// The register is not in the export
+    MissingRegister(u8),
+}
+
+impl fmt::Display for TicsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TicsError::Malformed(line) => write!(f, "line {} is not a register", line),
+            TicsError::InvalidAddress(addr) => write!(f, "no register at address {}", addr),
+            TicsError::DuplicateAddress(addr) => write!(f, "R{} appears twice", addr),
+            TicsError::MissingRegister(addr) => write!(f, "R{} is missing", addr),
+        }
+    }
+}
+
+const fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r')
+}
+
+const fn hex_digit(byte: u8) -> Option<u32> {
+    match byte {
+        b'0'..=b'9' => Some((byte - b'0') as u32),
+        b'a'..=b'f' => Some((byte - b'a' + 10) as u32),
+        b'A'..=b'F' => Some((byte - b'A' + 10) as u32),
+        _ => None,
+    }
+}
+
+const fn is_comment(bytes: &[u8], i: usize, end: usize) -> bool {
+    i + 1 < end && bytes[i] == b'/' && bytes[i + 1] == b'/'
+}
+
+/// The `R<addr>` label and word on `bytes[start..end]`, with the index of
+/// its comment text (`end` if there is none), `Ok(None)` for a blank or
+/// comment line, or `Err(())` if it is anything else
+const fn parse_line(
+    bytes: &[u8],
+    start: usize,
+    end: usize,
+) -> Result<Option<(u32, u32, usize)>, ()> {
+    let mut i = start;
+    while i < end && is_space(bytes[i]) {
+        i += 1;
+    }
+    if i == end || is_comment(bytes, i, end) {
+        return Ok(None);
+    }
+    if bytes[i] != b'R' {
+        return Err(());
+    }
+    i += 1;
+    let mut label = 0;
+    let digits = i;
+    while i < end && bytes[i].is_ascii_digit() && i - digits < 3 {
+        label = label * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    if i == digits || i == end || !is_space(bytes[i]) {
+        return Err(());
+    }
+    while i < end && is_space(bytes[i]) {
+        i += 1;
+    }
+    if i + 1 < end && bytes[i] == b'0' && (bytes[i + 1] == b'x' || bytes[i + 1] == b'X') {
+        i += 2;
+    }
+    let mut word = 0;
+    let digits = i;
+    while i < end && i - digits < 6 {
+        match hex_digit(bytes[i]) {
+            Some(digit) => word = word << 4 | digit,
+            None => break,
+        }
+        i += 1;
+    }
+    if i == digits {
+        return Err(());
+    }
+    while i < end && is_space(bytes[i]) {
+        i += 1;
+    }
+    if is_comment(bytes, i, end) {
+        i += 2;
+    } else if i != end {
+        return Err(());
+    }
+    Ok(Some((label, word, i)))
+}
+
+/// Parse a TICS Pro hex export into a register map; usable in a `static`.
+pub const fn parse_hex_export(text: &str) -> Result<[u32; 113], TicsError> {
+    let bytes = text.as_bytes();
+    let mut regs = [0; 113];
+    let mut seen = 0u128;
+    let mut line = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        line += 1;
+        let start = i;
+        while i < bytes.len() && bytes[i] != b'\n' {
+            i += 1;
+        }
+        let (label, word) = match parse_line(bytes, start, i) {
+            Ok(Some((label, word, _))) => (label, word),
+            Ok(None) => {
+                i += 1;
+                continue;
+            }
+            Err(()) => return Err(TicsError::Malformed(line)),
+        };
+        i += 1;
+        if label >= 113 {
+            let addr = if label > u8::MAX as u32 {
+                u8::MAX
+            } else {
+                label as u8
+            };
+            return Err(TicsError::InvalidAddress(addr));
+        }
+        if word >> 16 != label {
+            return Err(TicsError::Malformed(line));
+        }
+        if seen & 1 << label != 0 {
+            return Err(TicsError::DuplicateAddress(label as u8));
+        }
+        seen |= 1 << label;
+        regs[label as usize] = word;
+    }
+    let mut addr = 0;
+    while addr < 113 {
+        if seen & 1 << addr == 0 {
+            return Err(TicsError::MissingRegister(addr as u8));
+        }
+        addr += 1;
+    }
+    Ok(regs)
+}
+
+/// Comments kept from an export, one slot per register address
+#[cfg(feature = "host")]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Annotations(Vec<Option<String>>);
+
+#[cfg(feature = "host")]
+impl Annotations {
+    /// The comment on register `addr`, if it has one
+    pub fn get(&self, addr: u8) -> Option<&str> {
+        self.0.get(addr as usize)?.as_deref()
+    }
+
+    /// Set or clear the comment on register `addr`, which must be in the map
+    pub fn set(&mut self, addr: u8, note: Option<&str>) {
+        if self.0.len() < 113 {
+            self.0.resize(113, None);
+        }
+        self.0[addr as usize] = note.map(String::from);
+    }
+}
+
+/// `parse_hex_export`, also keeping the comment after each register
+#[cfg(feature = "host")]
+pub fn parse_annotated_export(text: &str) -> Result<([u32; 113], Annotations), TicsError> {
+    let regs = parse_hex_export(text)?;
+    let mut notes = Annotations::default();
+    for line in text.split('\n') {
+        let bytes = line.as_bytes();
+        // Already checked by parse_hex_export
+        if let Ok(Some((label, _, comment))) = parse_line(bytes, 0, bytes.len()) {
+            let note = line[comment..].trim();
+            if !note.is_empty() {
+                notes.set(label as u8, Some(note));
+            }
+        }
+    }
+    Ok((regs, notes))
+}
+
+/// `lmx2594::write_hex_dump`, with each register's comment in `notes`
+/// after its word
+#[cfg(feature = "host")]
+pub fn write_annotated_export<W: fmt::Write>(
+    regs: &[u32; 113],
+    notes: &Annotations,
+    out: &mut W,
+) -> fmt::Result {
+    for (addr, word) in regs.iter().enumerate().rev() {
+        write!(out, "R{}\t{:#08X}", addr, word)?;
+        if let Some(note) = notes.get(addr as u8) {
+            write!(out, "\t// {}", note)?;
+        }
+        out.write_str("\r\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmx2594::{self, REG_MAP};
+
+    // Parsed at compile time, as a firmware `static` would be
+    const EMPTY: Result<[u32; 113], TicsError> = parse_hex_export("");
+
+    #[test]
+    fn parses_a_full_export() {
+        let mut export = String::new();
+        lmx2594::write_hex_dump(&REG_MAP, &mut export).unwrap();
+        assert!(export.starts_with("R112\t0x700000\r\nR111\t0x6F0000\r\n"));
+        assert_eq!(parse_hex_export(&export), Ok(REG_MAP));
+
+        // Unix line endings, lower case, no prefix and a trailing blank line
+        let relaxed = export
+            .replace("\r\n", "\n")
+            .replace("0x", "")
+            .to_lowercase();
+        let relaxed = relaxed.replace('r', "R") + "\n";
+        assert_eq!(parse_hex_export(&relaxed), Ok(REG_MAP));
+    }
+
+    #[test]
+    fn rejects_incomplete_or_garbled_exports() {
+        assert_eq!(EMPTY, Err(TicsError::MissingRegister(0)));
+        let mut export = String::new();
+        lmx2594::write_hex_dump(&REG_MAP, &mut export).unwrap();
+        let lines: Vec<&str> = export.lines().collect();
+
+        let missing = lines[..112].join("\n");
+        assert_eq!(
+            parse_hex_export(&missing),
+            Err(TicsError::MissingRegister(0))
+        );
+        let twice = format!("{}\n{}", export, lines[3]);
+        assert_eq!(
+            parse_hex_export(&twice),
+            Err(TicsError::DuplicateAddress(109))
+        );
+        let beyond = format!("R113\t0x710000\n{}", export);
+        assert_eq!(
+            parse_hex_export(&beyond),
+            Err(TicsError::InvalidAddress(113))
+        );
+        let mislabeled = export.replace("R5\t", "R6\t");
+        assert_eq!(
+            parse_hex_export(&mislabeled),
+            Err(TicsError::Malformed(108))
+        );
+        let garbled = export.replace("R7\t0x", "R7\t0y");
+        assert_eq!(parse_hex_export(&garbled), Err(TicsError::Malformed(106)));
+    }
+
+    #[test]
+    fn comments_are_skipped_by_the_firmware_parser() {
+        let mut export = String::new();
+        lmx2594::write_hex_dump(&REG_MAP, &mut export).unwrap();
+        let commented = export.replace("R0\t0x00241C", "// calibrated\nR0\t0x00241C // 0");
+        assert_eq!(parse_hex_export(&commented), Ok(REG_MAP));
+        let stray = export.replace("R0\t0x00241C", "R0\t0x00241C / 0");
+        assert_eq!(parse_hex_export(&stray), Err(TicsError::Malformed(113)));
+    }
+
+    #[cfg(feature = "host")]
+    #[test]
+    fn annotations_survive_import_and_export() {
+        let mut export = String::new();
+        lmx2594::write_hex_dump(&REG_MAP, &mut export).unwrap();
+        let annotated = export.replace("R44\t0x2C1FA3\r\n", "R44\t0x2C1FA3\t// OUTA power\r\n");
+        assert_ne!(annotated, export);
+
+        let (regs, notes) = parse_annotated_export(&annotated).unwrap();
+        assert_eq!(regs, REG_MAP);
+        assert_eq!(notes.get(44), Some("OUTA power"));
+        assert_eq!(notes.get(45), None);
+
+        let mut round_trip = String::new();
+        write_annotated_export(&regs, &notes, &mut round_trip).unwrap();
+        assert_eq!(round_trip, annotated);
+    }
+}